@@ -0,0 +1,186 @@
+// Camera that follows a character, first person at the eyes or third person on a spring arm
+// It only ever reads the character's Transform, so predicted, interpolated or host owned all work
+// Aiming goes back to the character through CharacterAction::Face, which is what gets networked
+use crate::{CameraAction, CharacterAction, LocalGravity, facing, up_from_gravity};
+use avian3d::prelude::*;
+use bevy::app::RunFixedMainLoopSystems;
+use bevy::prelude::*;
+use bevy::transform::TransformSystems;
+use leafwing_input_manager::prelude::*;
+
+/// Closest the camera gets to whatever the arm hits
+const ARM_MARGIN: f32 = 0.2;
+/// Radius of the sphere swept along the arm, keeps the near plane out of walls
+const ARM_RADIUS: f32 = 0.2;
+const MAX_PITCH: f32 = 1.55;
+
+pub struct CameraRigPlugin;
+
+impl Plugin for CameraRigPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(InputManagerPlugin::<CameraAction>::default());
+        app.register_type::<CameraRig>();
+        app.add_observer(add_camera_input);
+        // Aim before the fixed loop so the character's movement this frame already uses it
+        app.add_systems(
+            RunFixedMainLoop,
+            aim.in_set(RunFixedMainLoopSystems::BeforeFixedMainLoop),
+        );
+        app.add_systems(
+            PostUpdate,
+            follow_target.before(TransformSystems::Propagate),
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum CameraMode {
+    FirstPerson,
+    #[default]
+    ThirdPerson,
+}
+
+/// Put on a camera to have it follow `target`, which should have a [`CharacterController`](crate::CharacterController)
+#[derive(Component, Clone, Debug, Reflect)]
+#[reflect(Component)]
+pub struct CameraRig {
+    pub target: Entity,
+    pub mode: CameraMode,
+    /// Radians around the target's up
+    pub yaw: f32,
+    pub pitch: f32,
+    pub eye_height: f32,
+    /// Arm length in third person, before anything shortens it
+    pub distance: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    pub sensitivity: f32,
+    /// How fast first and third person blend into each other, in blends per second
+    pub switch_speed: f32,
+    /// 0 is first person, 1 is third, eases toward whichever `mode` says
+    blend: f32,
+    /// Arm length last frame, it pulls in instantly and eases back out
+    arm: f32,
+}
+
+impl CameraRig {
+    pub fn new(target: Entity) -> Self {
+        Self {
+            target,
+            mode: CameraMode::default(),
+            yaw: 0.0,
+            pitch: -0.2,
+            eye_height: 0.7,
+            distance: 4.0,
+            min_distance: 1.5,
+            max_distance: 12.0,
+            sensitivity: 0.003,
+            switch_speed: 4.0,
+            blend: 1.0,
+            arm: 4.0,
+        }
+    }
+}
+
+fn add_camera_input(
+    trigger: On<Add, CameraRig>,
+    mut commands: Commands,
+    maps: Query<(), With<InputMap<CameraAction>>>,
+) {
+    if !maps.contains(trigger.entity) {
+        commands
+            .entity(trigger.entity)
+            .insert(CameraAction::default_input_map());
+    }
+}
+
+fn aim(
+    mut rigs: Query<(&mut CameraRig, &ActionState<CameraAction>)>,
+    mut targets: Query<&mut ActionState<CharacterAction>>,
+) {
+    for (mut rig, camera_actions) in &mut rigs {
+        let look = camera_actions.axis_pair(&CameraAction::Look);
+        let sensitivity = rig.sensitivity;
+        rig.yaw = (rig.yaw - look.x * sensitivity).rem_euclid(std::f32::consts::TAU);
+        rig.pitch = (rig.pitch - look.y * sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+
+        let zoom = camera_actions.value(&CameraAction::Zoom);
+        if zoom != 0.0 {
+            rig.distance = (rig.distance - zoom).clamp(rig.min_distance, rig.max_distance);
+        }
+        if camera_actions.just_pressed(&CameraAction::ToggleView) {
+            rig.mode = match rig.mode {
+                CameraMode::FirstPerson => CameraMode::ThirdPerson,
+                CameraMode::ThirdPerson => CameraMode::FirstPerson,
+            };
+        }
+
+        if let Ok(mut actions) = targets.get_mut(rig.target) {
+            actions.set_value(&CharacterAction::Face, rig.yaw);
+        }
+    }
+}
+
+/// After physics so it sees where the character ended up this frame
+fn follow_target(
+    time: Res<Time>,
+    gravity: Res<Gravity>,
+    spatial: SpatialQuery,
+    mut rigs: Query<(&mut CameraRig, &mut Transform)>,
+    mut targets: Query<
+        (&Transform, Option<&LocalGravity>, Option<&mut Visibility>),
+        Without<CameraRig>,
+    >,
+) {
+    let dt = time.delta_secs();
+    for (mut rig, mut camera) in &mut rigs {
+        let Ok((target, local_gravity, visibility)) = targets.get_mut(rig.target) else {
+            continue;
+        };
+        let up = up_from_gravity(local_gravity.map_or(gravity.0, |gravity| gravity.0));
+        let look = facing(up, rig.yaw) * Quat::from_rotation_x(rig.pitch);
+        let eye = target.translation + up * rig.eye_height;
+
+        let goal = match rig.mode {
+            CameraMode::FirstPerson => 0.0,
+            CameraMode::ThirdPerson => 1.0,
+        };
+        let step = rig.switch_speed * dt;
+        rig.blend += (goal - rig.blend).clamp(-step, step);
+
+        // Spring arm, sweep back from the eyes and stop short of whatever is behind us
+        let wanted = rig.distance * rig.blend;
+        let back = look * Vec3::Z;
+        let reach = Dir3::new(back)
+            .ok()
+            .and_then(|direction| {
+                spatial.cast_shape(
+                    &Collider::sphere(ARM_RADIUS),
+                    eye,
+                    Quat::IDENTITY,
+                    direction,
+                    &ShapeCastConfig::from_max_distance(wanted),
+                    &SpatialQueryFilter::from_excluded_entities([rig.target]),
+                )
+            })
+            .map_or(wanted, |hit| (hit.distance - ARM_MARGIN).max(0.0));
+        rig.arm = if reach < rig.arm {
+            reach
+        } else {
+            rig.arm + (reach - rig.arm) * (1.0 - (-10.0 * dt).exp())
+        };
+
+        camera.translation = eye + back * rig.arm;
+        camera.rotation = look;
+
+        // Don't look at the inside of our own head
+        if let Some(mut visibility) = visibility {
+            let hidden = rig.arm < 0.5;
+            visibility.set_if_neq(if hidden {
+                Visibility::Hidden
+            } else {
+                Visibility::Inherited
+            });
+        }
+    }
+}
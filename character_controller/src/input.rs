@@ -0,0 +1,50 @@
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Everything a character can be told to do, these are what get sent over the network
+#[derive(Actionlike, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+pub enum CharacterAction {
+    /// x is right, y is forward
+    #[actionlike(DualAxis)]
+    Move,
+    Jump,
+    Sprint,
+    /// Yaw in radians around up, set by whatever is aiming like the [`CameraRig`](crate::camera::CameraRig)
+    /// rather than bound to an input, so the host knows which way the character faces
+    #[actionlike(Axis)]
+    Face,
+}
+
+impl CharacterAction {
+    pub fn default_input_map() -> InputMap<Self> {
+        InputMap::default()
+            .with_dual_axis(CharacterAction::Move, VirtualDPad::wasd())
+            .with_dual_axis(CharacterAction::Move, GamepadStick::LEFT)
+            .with(CharacterAction::Jump, KeyCode::Space)
+            .with(CharacterAction::Jump, GamepadButton::South)
+            .with(CharacterAction::Sprint, KeyCode::ShiftLeft)
+            .with(CharacterAction::Sprint, GamepadButton::LeftThumb)
+    }
+}
+
+/// Local only, never sent anywhere
+#[derive(Actionlike, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+pub enum CameraAction {
+    #[actionlike(DualAxis)]
+    Look,
+    #[actionlike(Axis)]
+    Zoom,
+    ToggleView,
+}
+
+impl CameraAction {
+    pub fn default_input_map() -> InputMap<Self> {
+        InputMap::default()
+            .with_dual_axis(CameraAction::Look, MouseMove::default())
+            .with_dual_axis(CameraAction::Look, GamepadStick::RIGHT)
+            .with_axis(CameraAction::Zoom, MouseScrollAxis::Y)
+            .with(CameraAction::ToggleView, KeyCode::KeyV)
+            .with(CameraAction::ToggleView, GamepadButton::RightThumb)
+    }
+}
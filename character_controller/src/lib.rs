@@ -2,20 +2,35 @@ use avian3d::prelude::*;
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
-// This should all be implemented with the idea of a networking!!!
+pub mod camera;
+pub mod input;
+mod movement;
+
+pub use camera::{CameraMode, CameraRig, CameraRigPlugin};
+pub use input::{CameraAction, CharacterAction};
+
+// Everything here only reads ActionState and writes physics in FixedUpdate, so lightyear can
+// rerun it during rollback. Leafwing's InputManagerPlugin isn't added here, lightyear's input
+// plugin brings it when networked, add `InputManagerPlugin::<CharacterAction>` yourself otherwise
 
 pub struct CharacterControllerPlugin;
 
 impl Plugin for CharacterControllerPlugin {
-    fn build(&self, _app: &mut App) {}
+    fn build(&self, app: &mut App) {
+        app.register_type::<CharacterController>();
+        app.register_type::<CharacterMotion>();
+        app.register_type::<LocalGravity>();
+        app.add_systems(FixedUpdate, movement::move_characters);
+    }
 }
 
 #[derive(Bundle, Serialize, Deserialize)]
 pub struct CharacterControllerBundle {
-    character_controller: CharacterController,
-    collider: Collider,
-    rigidbody: RigidBody,
-    position: Transform,
+    pub character_controller: CharacterController,
+    pub collider: Collider,
+    /// Kinematic, see [`CharacterController`]
+    pub rigidbody: RigidBody,
+    pub position: Transform,
 }
 
 impl Default for CharacterControllerBundle {
@@ -23,23 +38,89 @@ impl Default for CharacterControllerBundle {
         Self {
             character_controller: CharacterController::default(),
             collider: Collider::capsule(0.5, 1.0),
-            rigidbody: RigidBody::Dynamic,
+            rigidbody: RigidBody::Kinematic,
             position: Transform::default(),
         }
     }
 }
 
-#[derive(Component, Debug, Reflect, Clone, Copy, Serialize, Deserialize)]
+/// Tuning for a kinematic character, it moves with shape casts in FixedUpdate rather than
+/// being pushed by the solver. It faces [`CharacterAction::Face`], up comes from gravity
+#[derive(Component, Debug, Reflect, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[require(CharacterMotion)]
 pub struct CharacterController {
     pub speed: f32,
+    pub sprint_multiplier: f32,
+    /// Upward speed a jump starts with
     pub jump_force: f32,
+    /// How fast it gets up to speed or stops, in m/s²
+    pub acceleration: f32,
+    /// Fraction of `acceleration` left in the air
+    pub air_control: f32,
+    /// Steepest ground it can stand on, in radians
+    pub max_slope: f32,
+    /// Tallest ledge it walks up without jumping
+    pub step_height: f32,
+    /// How far below it still counts as ground, and how far it gets pulled down to stay on it
+    pub snap_distance: f32,
+    /// Seconds after walking off an edge that a jump still works
+    pub coyote_time: f32,
+    /// Seconds a jump pressed before landing is remembered
+    pub jump_buffer: f32,
 }
 
 impl Default for CharacterController {
     fn default() -> Self {
         Self {
             speed: 5.0,
-            jump_force: 10.0,
+            sprint_multiplier: 1.6,
+            jump_force: 6.0,
+            acceleration: 60.0,
+            air_control: 0.3,
+            max_slope: 50f32.to_radians(),
+            step_height: 0.45,
+            snap_distance: 0.3,
+            coyote_time: 0.12,
+            jump_buffer: 0.15,
+        }
+    }
+}
+
+/// What the controller carries between ticks, predicted along with the position when networked
+#[derive(Component, Debug, Reflect, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CharacterMotion {
+    pub velocity: Vec3,
+    pub grounded: bool,
+    pub ground_normal: Vec3,
+    pub since_grounded: f32,
+    pub since_jump_pressed: f32,
+}
+
+impl Default for CharacterMotion {
+    fn default() -> Self {
+        Self {
+            velocity: Vec3::ZERO,
+            grounded: false,
+            ground_normal: Vec3::Y,
+            since_grounded: f32::INFINITY,
+            since_jump_pressed: f32::INFINITY,
         }
     }
 }
+
+/// Gravity pulling on this body when it isn't just avian's global [`Gravity`], like on a planet
+/// Whatever owns the gravity keeps this up to date, controllers treat its opposite as up
+#[derive(
+    Component, Debug, Reflect, Clone, Copy, Default, Deref, DerefMut, Serialize, Deserialize,
+)]
+pub struct LocalGravity(pub Vec3);
+
+/// Opposite of gravity, plain Y when there isn't any
+pub fn up_from_gravity(gravity: Vec3) -> Vec3 {
+    gravity.try_normalize().map_or(Vec3::Y, |down| -down)
+}
+
+/// Upright on `up` turned `yaw` radians around it, yaw 0 faces -Z when up is Y
+pub fn facing(up: Vec3, yaw: f32) -> Quat {
+    Quat::from_rotation_arc(Vec3::Y, up) * Quat::from_rotation_y(yaw)
+}
@@ -0,0 +1,363 @@
+// Kinematic movement, the body never gets pushed around by the solver, everything it touches is
+// found with shape casts. The result goes out as LinearVelocity so avian still moves it and
+// anything dynamic it walks into gets shoved
+use crate::{
+    CharacterAction, CharacterController, CharacterMotion, LocalGravity, facing, up_from_gravity,
+};
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+
+/// Gap kept between the collider and whatever it's touching so casts don't start inside things
+const SKIN: f32 = 0.02;
+/// Collide and slide gives up after this many surfaces in one tick
+const MAX_SLIDES: usize = 4;
+
+/// Everything a cast needs that stays the same for the whole tick
+struct Caster<'a> {
+    spatial: &'a SpatialQueryPipeline,
+    shape: &'a Collider,
+    rotation: Quat,
+    filter: SpatialQueryFilter,
+}
+
+impl Caster<'_> {
+    fn cast(&self, origin: Vec3, motion: Vec3) -> Option<ShapeHitData> {
+        let direction = Dir3::new(motion).ok()?;
+        self.spatial.cast_shape(
+            self.shape,
+            origin,
+            self.rotation,
+            direction,
+            &ShapeCastConfig {
+                max_distance: motion.length() + SKIN,
+                ignore_origin_penetration: true,
+                ..default()
+            },
+            &self.filter,
+        )
+    }
+}
+
+pub(crate) fn move_characters(
+    time: Res<Time>,
+    gravity: Res<Gravity>,
+    // The pipeline rather than SpatialQuery, its collider query would clash with our Rotation
+    spatial: Res<SpatialQueryPipeline>,
+    mut characters: Query<(
+        Entity,
+        &CharacterController,
+        &mut CharacterMotion,
+        &ActionState<CharacterAction>,
+        &Collider,
+        &Position,
+        &mut Rotation,
+        &mut LinearVelocity,
+        Option<&LocalGravity>,
+    )>,
+) {
+    let dt = time.delta_secs();
+    if dt <= 0.0 {
+        return;
+    }
+    for (
+        entity,
+        controller,
+        mut motion,
+        actions,
+        collider,
+        position,
+        mut rotation,
+        mut linear_velocity,
+        local_gravity,
+    ) in &mut characters
+    {
+        let gravity = local_gravity.map_or(gravity.0, |gravity| gravity.0);
+        let up = up_from_gravity(gravity);
+        // Stand upright and face wherever we're aiming
+        rotation.0 = facing(up, actions.value(&CharacterAction::Face));
+        let caster = Caster {
+            spatial: &spatial,
+            shape: collider,
+            rotation: rotation.0,
+            filter: SpatialQueryFilter::from_excluded_entities([entity]),
+        };
+        let min_ground_dot = controller.max_slope.cos();
+        let walkable = |normal: Vec3| normal.dot(up) >= min_ground_dot;
+
+        // Ground check, rising fast means we just left it
+        let ground = caster
+            .cast(position.0, -up * controller.snap_distance)
+            .filter(|hit| walkable(hit.normal1) && motion.velocity.dot(up) <= 0.1);
+        motion.grounded = ground.is_some();
+        motion.ground_normal = ground.map_or(up, |hit| hit.normal1);
+
+        // Coyote time and jump buffering
+        if motion.grounded {
+            motion.since_grounded = 0.0;
+        } else {
+            motion.since_grounded += dt;
+        }
+        if actions.just_pressed(&CharacterAction::Jump) {
+            motion.since_jump_pressed = 0.0;
+        } else {
+            motion.since_jump_pressed += dt;
+        }
+
+        // Walking, relative to where the body faces
+        let forward = rotation.0 * Vec3::NEG_Z;
+        let right = forward.cross(up);
+        let axis = actions.clamped_axis_pair(&CharacterAction::Move);
+        let speed = if actions.pressed(&CharacterAction::Sprint) {
+            controller.speed * controller.sprint_multiplier
+        } else {
+            controller.speed
+        };
+        let target = (right * axis.x + forward * axis.y) * speed;
+        let acceleration = if motion.grounded {
+            controller.acceleration
+        } else {
+            controller.acceleration * controller.air_control
+        };
+        let mut vertical = motion.velocity.dot(up);
+        let horizontal = motion.velocity - up * vertical;
+        let horizontal = horizontal + (target - horizontal).clamp_length_max(acceleration * dt);
+
+        let jumping = motion.since_jump_pressed <= controller.jump_buffer
+            && motion.since_grounded <= controller.coyote_time;
+        if jumping {
+            vertical = controller.jump_force;
+            // Used up, a held button or the rest of the coyote window can't jump again
+            motion.since_jump_pressed = f32::INFINITY;
+            motion.since_grounded = f32::INFINITY;
+            motion.grounded = false;
+        } else if motion.grounded {
+            vertical = 0.0;
+        } else {
+            vertical -= gravity.length() * dt;
+        }
+
+        let mut velocity = if motion.grounded {
+            // Follow the slope instead of walking into it or off it
+            let along = horizontal.reject_from_normalized(motion.ground_normal);
+            along.normalize_or_zero() * horizontal.length()
+        } else {
+            horizontal + up * vertical
+        };
+
+        let start = position.0;
+        let mut end = collide_and_slide(
+            &caster,
+            start,
+            &mut velocity,
+            dt,
+            up,
+            motion.grounded,
+            &walkable,
+        );
+
+        // Stairs and voxel ledges, only when something stopped us short on the ground
+        if motion.grounded {
+            let wanted = start + horizontal * dt;
+            let short = (wanted - end).reject_from_normalized(up).length();
+            // Walking into the ledge ate the speed last tick, so this can be well under SKIN
+            if short > 1e-4
+                && let Some(stepped) =
+                    step_up(&caster, start, horizontal * dt, up, controller, &walkable)
+            {
+                end = stepped;
+                velocity = horizontal;
+            }
+        }
+
+        // Keep hugging the ground going downhill instead of launching off every crest
+        if motion.grounded
+            && !jumping
+            && let Some(hit) = caster
+                .cast(end, -up * controller.snap_distance)
+                .filter(|hit| walkable(hit.normal1))
+        {
+            end -= up * (hit.distance - SKIN).max(0.0);
+        }
+
+        // Flattened on the ground so walking uphill doesn't read as rising off it next tick
+        motion.velocity = if motion.grounded {
+            velocity.reject_from_normalized(up)
+        } else {
+            velocity
+        };
+        linear_velocity.0 = (end - start) / dt;
+    }
+}
+
+/// Moves as far as it can, sliding along whatever it hits. Steep walls can't be climbed by sliding
+/// up them while grounded. `velocity` loses whatever went into the surfaces
+fn collide_and_slide(
+    caster: &Caster,
+    start: Vec3,
+    velocity: &mut Vec3,
+    dt: f32,
+    up: Vec3,
+    grounded: bool,
+    walkable: &impl Fn(Vec3) -> bool,
+) -> Vec3 {
+    let mut position = start;
+    let mut remaining = *velocity * dt;
+    for _ in 0..MAX_SLIDES {
+        if remaining.length_squared() < 1e-8 {
+            break;
+        }
+        let Some(hit) = caster.cast(position, remaining) else {
+            position += remaining;
+            break;
+        };
+        let direction = remaining.normalize();
+        let travel = (hit.distance - SKIN).max(0.0);
+        position += direction * travel;
+        remaining = direction * (remaining.length() - travel);
+
+        let mut normal = hit.normal1;
+        if grounded && !walkable(normal) {
+            // Treat it as a vertical wall so it can't be walked up
+            normal = normal.reject_from_normalized(up).normalize_or(normal);
+        }
+        remaining = remaining.reject_from_normalized(normal);
+        if velocity.dot(normal) < 0.0 {
+            *velocity = velocity.reject_from_normalized(normal);
+        }
+    }
+    position
+}
+
+/// Up by the step height, across, then back down onto something walkable
+fn step_up(
+    caster: &Caster,
+    start: Vec3,
+    across: Vec3,
+    up: Vec3,
+    controller: &CharacterController,
+    walkable: &impl Fn(Vec3) -> bool,
+) -> Option<Vec3> {
+    let across = across.reject_from_normalized(up);
+    if across.length_squared() < 1e-8 {
+        return None;
+    }
+    // A rounded bottom has to get far enough past the edge to stand on top instead of its corner,
+    // counting the SKIN it stopped short of the ledge by
+    let reach = caster
+        .shape
+        .shape_scaled()
+        .as_capsule()
+        .map_or(0.0, |capsule| {
+            capsule.radius * (1.0 - controller.max_slope.sin()) + 2.0 * SKIN
+        });
+    let across = across.normalize() * across.length().max(reach);
+    let rise = caster
+        .cast(start, up * controller.step_height)
+        .map_or(controller.step_height, |hit| (hit.distance - SKIN).max(0.0));
+    if rise <= SKIN {
+        return None;
+    }
+    let raised = start + up * rise;
+    if caster.cast(raised, across).is_some() {
+        return None;
+    }
+    let over = raised + across;
+    let landing = caster.cast(over, -up * (rise + controller.snap_distance))?;
+    if !walkable(landing.normal1) {
+        return None;
+    }
+    Some(over - up * (landing.distance - SKIN).max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CharacterControllerBundle, CharacterControllerPlugin};
+    use bevy::{
+        asset::AssetPlugin, mesh::MeshPlugin, scene::ScenePlugin, time::TimeUpdateStrategy,
+    };
+    use std::time::Duration;
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            AssetPlugin::default(),
+            MeshPlugin,
+            ScenePlugin,
+            PhysicsPlugins::default(),
+            CharacterControllerPlugin,
+        ));
+        // One fixed tick a frame
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+            1.0 / 64.0,
+        )));
+        app.finish();
+        app.cleanup();
+
+        app.world_mut().spawn((
+            RigidBody::Static,
+            Collider::cuboid(40.0, 1.0, 40.0),
+            Transform::from_xyz(0.0, -0.5, 0.0),
+        ));
+        app
+    }
+
+    /// Walking forward, which is -z
+    fn character(app: &mut App) -> Entity {
+        let mut actions = ActionState::<CharacterAction>::default();
+        actions.set_axis_pair(&CharacterAction::Move, Vec2::Y);
+        app.world_mut()
+            .spawn((
+                CharacterControllerBundle {
+                    position: Transform::from_xyz(0.0, 1.02, 0.0),
+                    ..default()
+                },
+                actions,
+            ))
+            .id()
+    }
+
+    fn run(app: &mut App, frames: usize) {
+        for _ in 0..frames {
+            app.update();
+        }
+    }
+
+    #[test]
+    fn walks_along_the_ground() {
+        let mut app = app();
+        let character = character(&mut app);
+        run(&mut app, 64);
+
+        let world = app.world();
+        let motion = world.get::<CharacterMotion>(character).unwrap();
+        assert!(motion.grounded);
+        let position = world.get::<Position>(character).unwrap().0;
+        // Up to speed well within the second
+        assert!(position.z < -4.0 && position.z > -5.5, "at {position}");
+        assert!(position.x.abs() < 0.01);
+        assert!((position.y - 1.0).abs() < 0.05, "at {position}");
+    }
+
+    #[test]
+    fn steps_up_ledges() {
+        let mut app = app();
+        // A ledge lower than the step height a couple of metres ahead
+        app.world_mut().spawn((
+            RigidBody::Static,
+            Collider::cuboid(40.0, 0.3, 20.0),
+            Transform::from_xyz(0.0, 0.15, -12.0),
+        ));
+        let character = character(&mut app);
+        run(&mut app, 128);
+
+        let world = app.world();
+        assert!(world.get::<CharacterMotion>(character).unwrap().grounded);
+        let position = world.get::<Position>(character).unwrap().0;
+        assert!(position.z < -4.0, "stuck at {position}");
+        assert!((position.y - 1.3).abs() < 0.05, "at {position}");
+    }
+}
@@ -0,0 +1,222 @@
+// Rebindable input, each action enum gets a Bindings resource that every InputMap of it follows
+// Whatever owns the bindings file fills these in, the console's rebind command edits them
+use bevy::{input_focus::InputFocus, prelude::*};
+use leafwing_input_manager::{plugin::InputManagerSystem, prelude::*};
+
+/// Keeps every [`InputMap<A>`] in sync with [`Bindings<A>`]
+pub struct BindingsPlugin<A: Actionlike> {
+    defaults: fn() -> InputMap<A>,
+    /// Whether the actions go quiet while something like the console is being typed in
+    suppress_while_typing: bool,
+}
+
+impl<A: Actionlike> BindingsPlugin<A> {
+    pub fn new(defaults: fn() -> InputMap<A>) -> Self {
+        Self {
+            defaults,
+            suppress_while_typing: true,
+        }
+    }
+
+    /// For actions that have to work while typing, like closing the console
+    pub fn always_active(mut self) -> Self {
+        self.suppress_while_typing = false;
+        self
+    }
+}
+
+impl<A: Actionlike> Plugin for BindingsPlugin<A> {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Bindings::<A>::new((self.defaults)()));
+        app.add_observer(use_bindings::<A>);
+        app.add_systems(
+            PreUpdate,
+            apply_bindings::<A>
+                .run_if(resource_changed::<Bindings<A>>)
+                .before(InputManagerSystem::Update),
+        );
+        if self.suppress_while_typing {
+            app.add_systems(
+                PreUpdate,
+                suppress_while_typing::<A>.in_set(InputManagerSystem::ManualControl),
+            );
+        }
+    }
+}
+
+/// What the player has bound `A` to, starts out as the defaults
+#[derive(Resource, Clone, Debug, Deref, DerefMut)]
+pub struct Bindings<A: Actionlike> {
+    #[deref]
+    pub map: InputMap<A>,
+    pub defaults: InputMap<A>,
+}
+
+impl<A: Actionlike> Bindings<A> {
+    pub fn new(defaults: InputMap<A>) -> Self {
+        Self {
+            map: defaults.clone(),
+            defaults,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.map = self.defaults.clone();
+    }
+
+    /// Swaps whatever `action` was bound to for `inputs`, all names as they're written in RON
+    /// like `Jump Space South`. Only buttons can be rebound this way, sticks and axes can't
+    pub fn rebind(&mut self, action: &str, inputs: &[&str]) -> Result<(), String>
+    where
+        A: serde::de::DeserializeOwned,
+    {
+        let action: A =
+            ron::from_str(action).map_err(|_| format!("There's no action called {action}"))?;
+        if action.input_control_kind() != InputControlKind::Button {
+            return Err(format!("{action:?} isn't a button, edit the bindings file"));
+        }
+        let inputs = inputs
+            .iter()
+            .map(|input| parse_button(input).ok_or(format!("Don't know the input {input}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        if inputs.is_empty() {
+            return Err("Give it at least one input".to_string());
+        }
+        self.map.clear_action(&action);
+        for input in inputs {
+            match input {
+                Button::Key(key) => self.map.insert(action.clone(), key),
+                Button::Mouse(button) => self.map.insert(action.clone(), button),
+                Button::Gamepad(button) => self.map.insert(action.clone(), button),
+            };
+        }
+        Ok(())
+    }
+}
+
+enum Button {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+/// Keys first, so `KeyF` or `Space`, then gamepad buttons like `South`, then mouse buttons
+fn parse_button(input: &str) -> Option<Button> {
+    ron::from_str(input)
+        .map(Button::Key)
+        .or_else(|_| ron::from_str(input).map(Button::Gamepad))
+        .or_else(|_| ron::from_str(input).map(Button::Mouse))
+        .ok()
+}
+
+/// Whoever spawns an input map just puts the defaults on, this swaps in the real bindings
+fn use_bindings<A: Actionlike>(
+    trigger: On<Add, InputMap<A>>,
+    mut commands: Commands,
+    bindings: Res<Bindings<A>>,
+) {
+    commands.entity(trigger.entity).insert(bindings.map.clone());
+}
+
+fn apply_bindings<A: Actionlike>(
+    bindings: Res<Bindings<A>>,
+    global: Option<ResMut<InputMap<A>>>,
+    mut maps: Query<&mut InputMap<A>>,
+) {
+    if let Some(mut global) = global {
+        *global = bindings.map.clone();
+    }
+    for mut map in &mut maps {
+        *map = bindings.map.clone();
+    }
+}
+
+/// Anything with an input map is ours to control, the rest are other players' inputs off the network
+fn suppress_while_typing<A: Actionlike>(
+    focus: Option<Res<InputFocus>>,
+    global: Option<ResMut<ActionState<A>>>,
+    mut states: Query<&mut ActionState<A>, With<InputMap<A>>>,
+) {
+    if focus.is_none_or(|focus| focus.0.is_none()) {
+        return;
+    }
+    if let Some(mut global) = global {
+        global.reset_all();
+    }
+    for mut state in &mut states {
+        state.reset_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Actionlike, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect, Deserialize)]
+    enum Action {
+        Jump,
+        Fire,
+        #[actionlike(DualAxis)]
+        Move,
+    }
+
+    fn bindings() -> Bindings<Action> {
+        Bindings::new(
+            InputMap::default()
+                .with(Action::Jump, KeyCode::Space)
+                .with(Action::Fire, MouseButton::Left)
+                .with_dual_axis(Action::Move, VirtualDPad::wasd()),
+        )
+    }
+
+    #[test]
+    fn buttons_parse_from_their_ron_names() {
+        assert!(matches!(
+            parse_button("KeyF"),
+            Some(Button::Key(KeyCode::KeyF))
+        ));
+        assert!(matches!(
+            parse_button("South"),
+            Some(Button::Gamepad(GamepadButton::South))
+        ));
+        assert!(matches!(
+            parse_button("Right"),
+            Some(Button::Mouse(MouseButton::Right))
+        ));
+        assert!(parse_button("Banana").is_none());
+        assert!(parse_button("").is_none());
+    }
+
+    #[test]
+    fn rebinding_swaps_every_input_of_the_action() {
+        let mut bindings = bindings();
+        bindings
+            .rebind("Jump", &["KeyF", "South", "Right"])
+            .unwrap();
+
+        let expected = InputMap::default()
+            .with(Action::Jump, KeyCode::KeyF)
+            .with(Action::Jump, GamepadButton::South)
+            .with(Action::Jump, MouseButton::Right)
+            .with(Action::Fire, MouseButton::Left)
+            .with_dual_axis(Action::Move, VirtualDPad::wasd());
+        assert_eq!(bindings.map, expected);
+
+        bindings.reset();
+        assert_eq!(bindings.map, bindings.defaults);
+    }
+
+    #[test]
+    fn bad_rebinds_leave_the_bindings_alone() {
+        let mut bindings = bindings();
+        let unknown = bindings.rebind("Dance", &["Space"]).unwrap_err();
+        assert!(unknown.contains("no action called Dance"), "{unknown}");
+        let axis = bindings.rebind("Move", &["KeyW"]).unwrap_err();
+        assert!(axis.contains("isn't a button"), "{axis}");
+        let input = bindings.rebind("Jump", &["KeyF", "Banana"]).unwrap_err();
+        assert!(input.contains("Banana"), "{input}");
+        assert!(bindings.rebind("Jump", &[]).is_err());
+        assert_eq!(bindings.map, bindings.defaults);
+    }
+}
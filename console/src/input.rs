@@ -0,0 +1,17 @@
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Global rather than on an entity, the console is always there
+#[derive(Actionlike, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+pub enum ConsoleAction {
+    Toggle,
+}
+
+impl ConsoleAction {
+    pub fn default_input_map() -> InputMap<Self> {
+        InputMap::default()
+            .with(ConsoleAction::Toggle, KeyCode::F1)
+            .with(ConsoleAction::Toggle, GamepadButton::Select)
+    }
+}
@@ -1,20 +1,32 @@
-use crate::{interface::*, protocol::ConsoleMessage};
+use crate::interface::*;
 use bevy::{ecs::system::SystemId, input_focus::InputFocus, platform::collections::*, prelude::*};
 
 use bevy_ui_text_input::*;
+use leafwing_input_manager::prelude::*;
 use lightyear::prelude::*;
 
+pub mod bindings;
 //mod command;
+mod input;
 mod interface;
 mod protocol;
 mod systems;
 
+pub use bindings::{Bindings, BindingsPlugin};
+pub use input::ConsoleAction;
+pub use protocol::ConsoleMessage;
+
 // Minecraft style text chat to enter in commands like "spawn Player" using reflect potentially
 pub struct ConsolePlugin;
 
 impl Plugin for ConsolePlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(TextInputPlugin);
+        app.add_plugins(InputManagerPlugin::<ConsoleAction>::default());
+        // Has to keep working while the console has focus, or it could never be closed
+        app.add_plugins(BindingsPlugin::new(ConsoleAction::default_input_map).always_active());
+        app.init_resource::<ActionState<ConsoleAction>>();
+        app.insert_resource(ConsoleAction::default_input_map());
         app.init_resource::<ConsoleConfig>();
         app.add_systems(
             Startup,
@@ -35,7 +47,6 @@ impl Plugin for ConsolePlugin {
 #[derive(Resource)]
 pub struct ConsoleConfig {
     prefix: char,
-    open_close_key: KeyCode,
     commands: HashMap<String, (CommandSystem, Option<CommandMetadata>)>,
 }
 
@@ -43,7 +54,6 @@ impl Default for ConsoleConfig {
     fn default() -> Self {
         Self {
             prefix: '/',
-            open_close_key: KeyCode::F1,
             commands: HashMap::new(),
         }
     }
@@ -107,8 +117,8 @@ impl ConsoleConfig {
 }
 
 pub struct CommandMetadata {
-    description: String,
-    usage: String,
+    pub description: String,
+    pub usage: String,
 }
 
 // Used to manage command systems
@@ -163,10 +173,10 @@ pub fn trying_command(
     let (command_name, arguments) = try_command
         .split_once(' ')
         .unwrap_or((try_command.as_str(), ""));
-    if let Some(command) = console_config.get_system(&command_name.to_string()) {
-        if let Some(system_id) = command.get_processed() {
-            commands.run_system_with(system_id, arguments.to_string());
-        }
+    if let Some(command) = console_config.get_system(command_name.to_string())
+        && let Some(system_id) = command.get_processed()
+    {
+        commands.run_system_with(system_id, arguments.to_string());
     }
 }
 
@@ -192,13 +202,12 @@ fn submit_text_routing(
 }
 
 pub fn manage_console(
-    console_config: Res<ConsoleConfig>,
     mut input_focus: ResMut<InputFocus>,
-    input: Res<ButtonInput<KeyCode>>,
+    actions: Res<ActionState<ConsoleAction>>,
     mut visibility: Single<&mut Visibility, With<Console>>,
     console_command_line: Single<(Entity, &mut TextInputNode), With<ConsoleCommandLine>>,
 ) {
-    if input.just_pressed(console_config.open_close_key) {
+    if actions.just_pressed(&ConsoleAction::Toggle) {
         let (entity, mut text_input_node) = console_command_line.into_inner();
         text_input_node.is_enabled = !text_input_node.is_enabled;
         visibility.toggle_visible_hidden();
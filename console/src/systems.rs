@@ -26,19 +26,16 @@ pub fn default_commands(mut console_config: ResMut<ConsoleConfig>) {
 pub fn help(In(argument): In<String>, console_config: Res<ConsoleConfig>, mut commands: Commands) {
     if argument.is_empty() {
         for command in console_config.get_commands() {
-            match console_config.get_metadata(command) {
-                Some(metadata) => {
-                    commands.trigger(ConsoleMessage::new(format!("Command: {}", command)));
-                    commands.trigger(ConsoleMessage::new(format!(
-                        "   ->Description: {}",
-                        metadata.description
-                    )));
-                    commands.trigger(ConsoleMessage::new(format!(
-                        "   ->Usage: {}",
-                        metadata.usage
-                    )));
-                }
-                None => (),
+            if let Some(metadata) = console_config.get_metadata(command) {
+                commands.trigger(ConsoleMessage::new(format!("Command: {}", command)));
+                commands.trigger(ConsoleMessage::new(format!(
+                    "   ->Description: {}",
+                    metadata.description
+                )));
+                commands.trigger(ConsoleMessage::new(format!(
+                    "   ->Usage: {}",
+                    metadata.usage
+                )));
             }
         }
     } else {
@@ -137,68 +134,73 @@ fn set_field(In(arguments): In<String>, world: &mut World) {
 
     // Find entity with this component and mutate it
     let mut entity_iter = world.query::<Entity>();
-    let entities: Vec<Entity> = entity_iter.iter(&world).collect();
+    let entities: Vec<Entity> = entity_iter.iter(world).collect();
 
     let mut found = false;
     for entity in entities {
-        if let Ok(entity_mut) = world.get_entity_mut(entity) {
-            if reflect_component.contains(&entity_mut) {
-                world.resource_scope(|world, registry: Mut<AppTypeRegistry>| {
-                    let registry = registry.read();
-                    if let Ok(mut entity_mut) = world.get_entity_mut(entity) {
-                        if let Some(mut reflected) = reflect_component.reflect_mut(&mut entity_mut) {
-                            // Match on the ReflectMut enum to get the Struct variant
-                            if let ReflectMut::Struct(struct_mut) = reflected.reflect_mut() {
-                                if let Some(field) = struct_mut.field_mut(field_name) {
-                                    // Get the full type path of the component
-                                    let type_path = registry
-                                        .get_with_short_type_path(component_name)
-                                        .map(|reg| reg.type_info().type_path())
-                                        .unwrap_or(component_name);
-
-                                    // Deserialize the string value as RON with full type path and field name
-                                    let ron_str = format!("{{ \"{}\": ( {}: {} ) }}", type_path, field_name, value);
-                                    println!("DEBUG: Attempting to deserialize '{}' as RON", ron_str);
-                                    if let Ok(mut deserializer) = ron::Deserializer::from_str(&ron_str) {
-                                        let reflect_deserializer = ReflectDeserializer::new(&registry);
-                                        match reflect_deserializer.deserialize(&mut deserializer) {
-                                            Ok(new_val) => {
-                                                println!("DEBUG: Successfully deserialized value");
-                                                // Apply the new value to the field
-                                                field.apply(new_val.as_partial_reflect());
-                                                world.trigger(ConsoleMessage::new(
-                                                    format!("Set {}.{} = {}", component_name, field_name, value)
-                                                ));
-                                                found = true;
-                                            }
-                                            Err(e) => {
-                                                println!("DEBUG: Deserialization error: {:?}", e);
-                                                world.trigger(ConsoleMessage::new(
-                                                    format!("Failed to deserialize value for field '{}': {:?}", field_name, e)
-                                                ));
-                                            }
-                                        }
-                                    } else {
-                                        world.trigger(ConsoleMessage::new(
-                                            format!("Failed to create RON deserializer")
-                                        ));
+        if let Ok(entity_mut) = world.get_entity_mut(entity)
+            && reflect_component.contains(&entity_mut)
+        {
+            world.resource_scope(|world, registry: Mut<AppTypeRegistry>| {
+                let registry = registry.read();
+                if let Ok(mut entity_mut) = world.get_entity_mut(entity)
+                    && let Some(mut reflected) = reflect_component.reflect_mut(&mut entity_mut)
+                {
+                    // Match on the ReflectMut enum to get the Struct variant
+                    if let ReflectMut::Struct(struct_mut) = reflected.reflect_mut() {
+                        if let Some(field) = struct_mut.field_mut(field_name) {
+                            // Get the full type path of the component
+                            let type_path = registry
+                                .get_with_short_type_path(component_name)
+                                .map(|reg| reg.type_info().type_path())
+                                .unwrap_or(component_name);
+
+                            // Deserialize the string value as RON with full type path and field name
+                            let ron_str =
+                                format!("{{ \"{}\": ( {}: {} ) }}", type_path, field_name, value);
+                            println!("DEBUG: Attempting to deserialize '{}' as RON", ron_str);
+                            if let Ok(mut deserializer) = ron::Deserializer::from_str(&ron_str) {
+                                let reflect_deserializer = ReflectDeserializer::new(&registry);
+                                match reflect_deserializer.deserialize(&mut deserializer) {
+                                    Ok(new_val) => {
+                                        println!("DEBUG: Successfully deserialized value");
+                                        // Apply the new value to the field
+                                        field.apply(new_val.as_partial_reflect());
+                                        world.trigger(ConsoleMessage::new(format!(
+                                            "Set {}.{} = {}",
+                                            component_name, field_name, value
+                                        )));
+                                        found = true;
+                                    }
+                                    Err(e) => {
+                                        println!("DEBUG: Deserialization error: {:?}", e);
+                                        world.trigger(ConsoleMessage::new(format!(
+                                            "Failed to deserialize value for field '{}': {:?}",
+                                            field_name, e
+                                        )));
                                     }
-                                } else {
-                                    world.trigger(ConsoleMessage::new(
-                                        format!("Field '{}' not found on {}", field_name, component_name)
-                                    ));
                                 }
                             } else {
                                 world.trigger(ConsoleMessage::new(
-                                    format!("Component '{}' is not a struct", component_name)
+                                    "Failed to create RON deserializer".to_string(),
                                 ));
                             }
+                        } else {
+                            world.trigger(ConsoleMessage::new(format!(
+                                "Field '{}' not found on {}",
+                                field_name, component_name
+                            )));
                         }
+                    } else {
+                        world.trigger(ConsoleMessage::new(format!(
+                            "Component '{}' is not a struct",
+                            component_name
+                        )));
                     }
-                });
-                if found {
-                    break;
                 }
+            });
+            if found {
+                break;
             }
         }
     }
@@ -0,0 +1,163 @@
+// Every subsystem's bindings live in one RON file, missing sections fall back to the defaults
+use bevy::prelude::*;
+use character_controller::{CameraAction, CharacterAction};
+use console::{
+    Bindings, BindingsPlugin, CommandMetadata, ConsoleAction, ConsoleConfig, ConsoleMessage,
+};
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+use weave::prelude::DebugAction;
+
+const BINDINGS_FILE: &str = "bindings.ron";
+
+pub struct InputBindingsPlugin;
+
+impl Plugin for InputBindingsPlugin {
+    fn build(&self, app: &mut App) {
+        // The console registers its own
+        app.add_plugins((
+            BindingsPlugin::new(CharacterAction::default_input_map),
+            BindingsPlugin::new(CameraAction::default_input_map),
+            BindingsPlugin::new(DebugAction::default_input_map),
+        ));
+        app.add_systems(Startup, (load_bindings, binding_commands));
+        app.add_observer(save_bindings);
+    }
+}
+
+/// Writes the current bindings out
+#[derive(Event)]
+pub struct SaveBindings;
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct BindingsFile {
+    movement: InputMap<CharacterAction>,
+    camera: InputMap<CameraAction>,
+    console: InputMap<ConsoleAction>,
+    debug: InputMap<DebugAction>,
+}
+
+impl Default for BindingsFile {
+    fn default() -> Self {
+        Self {
+            movement: CharacterAction::default_input_map(),
+            camera: CameraAction::default_input_map(),
+            console: ConsoleAction::default_input_map(),
+            debug: DebugAction::default_input_map(),
+        }
+    }
+}
+
+fn load_bindings(
+    mut movement: ResMut<Bindings<CharacterAction>>,
+    mut camera: ResMut<Bindings<CameraAction>>,
+    mut console: ResMut<Bindings<ConsoleAction>>,
+    mut debug: ResMut<Bindings<DebugAction>>,
+) {
+    let Ok(text) = std::fs::read_to_string(BINDINGS_FILE) else {
+        return;
+    };
+    match ron::from_str::<BindingsFile>(&text) {
+        Ok(file) => {
+            movement.map = file.movement;
+            camera.map = file.camera;
+            console.map = file.console;
+            debug.map = file.debug;
+        }
+        Err(error) => warn!("Couldn't read {BINDINGS_FILE}, using the defaults: {error}"),
+    }
+}
+
+fn save_bindings(
+    _trigger: On<SaveBindings>,
+    movement: Res<Bindings<CharacterAction>>,
+    camera: Res<Bindings<CameraAction>>,
+    console: Res<Bindings<ConsoleAction>>,
+    debug: Res<Bindings<DebugAction>>,
+) {
+    let file = BindingsFile {
+        movement: movement.map.clone(),
+        camera: camera.map.clone(),
+        console: console.map.clone(),
+        debug: debug.map.clone(),
+    };
+    let saved = ron::ser::to_string_pretty(&file, default())
+        .map_err(|error| error.to_string())
+        .and_then(|text| std::fs::write(BINDINGS_FILE, text).map_err(|error| error.to_string()));
+    if let Err(error) = saved {
+        warn!("Couldn't save {BINDINGS_FILE}: {error}");
+    }
+}
+
+fn binding_commands(mut console_config: ResMut<ConsoleConfig>) {
+    console_config.insert_command_with_metadata(
+        "rebind",
+        CommandMetadata {
+            description: "Bind a button action to new keys, mouse or gamepad buttons and save it"
+                .to_string(),
+            usage: "rebind <movement|camera|console|debug> <action> <input>...".to_string(),
+        },
+        rebind,
+    );
+    console_config.insert_command_with_metadata(
+        "reset_bindings",
+        CommandMetadata {
+            description: "Put every binding back to the default and save it".to_string(),
+            usage: "reset_bindings".to_string(),
+        },
+        reset_bindings,
+    );
+}
+
+fn rebind(
+    In(arguments): In<String>,
+    mut commands: Commands,
+    mut movement: ResMut<Bindings<CharacterAction>>,
+    mut camera: ResMut<Bindings<CameraAction>>,
+    mut console: ResMut<Bindings<ConsoleAction>>,
+    mut debug: ResMut<Bindings<DebugAction>>,
+) {
+    let parts: Vec<&str> = arguments.split_whitespace().collect();
+    let [section, action, ref inputs @ ..] = parts[..] else {
+        commands.trigger(ConsoleMessage::new(
+            "Usage: rebind <movement|camera|console|debug> <action> <input>...",
+        ));
+        return;
+    };
+    let result = match section {
+        "movement" => movement.rebind(action, inputs),
+        "camera" => camera.rebind(action, inputs),
+        "console" => console.rebind(action, inputs),
+        "debug" => debug.rebind(action, inputs),
+        _ => Err(format!(
+            "No bindings called {section}, try movement, camera, console or debug"
+        )),
+    };
+    match result {
+        Ok(()) => {
+            commands.trigger(ConsoleMessage::new(format!(
+                "Bound {action} to {}",
+                inputs.join(", ")
+            )));
+            commands.trigger(SaveBindings);
+        }
+        Err(error) => commands.trigger(ConsoleMessage::new(error)),
+    }
+}
+
+fn reset_bindings(
+    _: In<String>,
+    mut commands: Commands,
+    mut movement: ResMut<Bindings<CharacterAction>>,
+    mut camera: ResMut<Bindings<CameraAction>>,
+    mut console: ResMut<Bindings<ConsoleAction>>,
+    mut debug: ResMut<Bindings<DebugAction>>,
+) {
+    movement.reset();
+    camera.reset();
+    console.reset();
+    debug.reset();
+    commands.trigger(ConsoleMessage::new("Bindings are back to the defaults"));
+    commands.trigger(SaveBindings);
+}
@@ -4,11 +4,14 @@ use bevy::{
 };
 use bevy_flycam::prelude::*;
 use bevy_inspector_egui::{bevy_egui::EguiPlugin, quick::WorldInspectorPlugin};
+use character_controller::{CameraRig, CameraRigPlugin};
 use networking::prelude::*;
-use voxel_terrain::prelude::*;
-use weave::WeavePlugin;
+use voxel_terrain::prelude::ImportHeightmap;
+use weave::{area::Observer, prelude::*};
 // Everything and anything in bevy diddy blud
 
+mod bindings;
+
 fn main() -> AppExit {
     let mut app = App::new();
 
@@ -19,12 +22,17 @@ fn main() -> AppExit {
         NoCameraPlayerPlugin,
         EguiPlugin::default(),
         WorldInspectorPlugin::new(),
-        //VoxelTerrainPlugin,
-        //NetworkingPlugin,
-        //WeavePlugin,
+        NetworkingPlugin,
+        WeavePlugin,
+        TerrainDebugPlugin,
+        CameraRigPlugin,
         console::ConsolePlugin,
+        bindings::InputBindingsPlugin,
     ));
-    app.add_systems(Startup, (setup, spawn_example_scene));
+    app.add_systems(Startup, (setup, spawn_example_scene, terrain_commands));
+    app.add_observer(player_visuals);
+    app.add_observer(prop_visuals);
+    app.add_observer(follow_local_player);
     app.run()
 }
 
@@ -42,7 +50,7 @@ fn setup(mut commands: Commands) {
     commands.spawn((
         FlyCam,
         Observer,
-        AreaManaged::Circle(25.0),
+        AreaManaged::default(),
         Camera3d::default(),
         Atmosphere::EARTH,
         AmbientLight {
@@ -66,6 +74,142 @@ fn setup(mut commands: Commands) {
     commands.insert_resource(MovementSettings { ..default() });
 }
 
+fn terrain_commands(mut console_config: ResMut<console::ConsoleConfig>) {
+    console_config.insert_command_with_metadata(
+        "terrain_debug",
+        console::CommandMetadata {
+            description: "Toggle the chunk streaming gizmos and counts".to_string(),
+            usage: "terrain_debug".to_string(),
+        },
+        |_: In<String>, mut commands: Commands| commands.trigger(ToggleTerrainDebug),
+    );
+    console_config.insert_command_with_metadata(
+        "export_terrain",
+        console::CommandMetadata {
+            description: "Write every loaded chunk to an obj and mtl".to_string(),
+            usage: "export_terrain <path>".to_string(),
+        },
+        |In(path): In<String>, mut commands: Commands| {
+            let path = if path.is_empty() {
+                "terrain.obj".to_string()
+            } else {
+                path
+            };
+            commands.trigger(ExportTerrain { path: path.into() });
+        },
+    );
+    console_config.insert_command_with_metadata(
+        "prop",
+        console::CommandMetadata {
+            description: "Drop a networked physics prop in front of the camera".to_string(),
+            usage: "prop <cube|ball> [size]".to_string(),
+        },
+        spawn_prop,
+    );
+    console_config.insert_command_with_metadata(
+        "import_heightmap",
+        console::CommandMetadata {
+            description: "Drive the voxel terrain from a grayscale png or exr in assets"
+                .to_string(),
+            usage: "import_heightmap <path> <world size> <height>".to_string(),
+        },
+        import_heightmap,
+    );
+}
+
+fn import_heightmap(In(arguments): In<String>, mut commands: Commands) {
+    let parts: Vec<&str> = arguments.split_whitespace().collect();
+    let [path, size, height] = parts[..] else {
+        commands.trigger(console::ConsoleMessage::new(
+            "Usage: import_heightmap <path> <world size> <height>",
+        ));
+        return;
+    };
+    let (Ok(size), Ok(height)) = (size.parse::<f32>(), height.parse::<f32>()) else {
+        commands.trigger(console::ConsoleMessage::new(
+            "Size and height need to be numbers",
+        ));
+        return;
+    };
+    commands.trigger(ImportHeightmap {
+        path: path.to_string(),
+        size: Vec2::splat(size),
+        height,
+    });
+}
+
+fn spawn_prop(
+    In(arguments): In<String>,
+    mut commands: Commands,
+    camera: Single<&Transform, With<Camera3d>>,
+) {
+    let mut parts = arguments.split_whitespace();
+    let kind = parts.next().unwrap_or("cube");
+    let Ok(size) = parts.next().map_or(Ok(1.0), str::parse::<f32>) else {
+        commands.trigger(console::ConsoleMessage::new("Size needs to be a number"));
+        return;
+    };
+    let shape = match kind {
+        "cube" => PropShape::Cuboid(Vec3::splat(size)),
+        "ball" => PropShape::Sphere(size * 0.5),
+        _ => {
+            commands.trigger(console::ConsoleMessage::new(
+                "Usage: prop <cube|ball> [size]",
+            ));
+            return;
+        }
+    };
+    commands.trigger(SpawnProp {
+        shape,
+        position: camera.translation + camera.forward() * (2.0 + size),
+    });
+}
+
+/// Players come in from the network with no mesh
+fn player_visuals(
+    trigger: On<Add, Player>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.entity(trigger.entity).insert((
+        Mesh3d(meshes.add(Capsule3d::new(0.5, 1.0))),
+        MeshMaterial3d(materials.add(Color::srgb(0.8, 0.4, 0.2))),
+    ));
+}
+
+fn prop_visuals(
+    trigger: On<Add, Prop>,
+    mut commands: Commands,
+    props: Query<&Prop>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let Ok(prop) = props.get(trigger.entity) else {
+        return;
+    };
+    let mesh = match prop.0 {
+        PropShape::Cuboid(size) => Mesh::from(Cuboid::from_size(size)),
+        PropShape::Sphere(radius) => Mesh::from(Sphere::new(radius)),
+    };
+    commands.entity(trigger.entity).insert((
+        Mesh3d(meshes.add(mesh)),
+        MeshMaterial3d(materials.add(Color::srgb(0.3, 0.5, 0.8))),
+    ));
+}
+
+/// Stop flying around once there's a character to play as
+fn follow_local_player(
+    trigger: On<Add, LocalPlayer>,
+    mut commands: Commands,
+    camera: Single<Entity, With<FlyCam>>,
+) {
+    commands
+        .entity(*camera)
+        .remove::<FlyCam>()
+        .insert(CameraRig::new(trigger.entity));
+}
+
 fn spawn_example_scene(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands.spawn((
         SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset("burnout_3_downtown.glb"))),
@@ -0,0 +1,313 @@
+// Netcode keys and connect tokens
+// The private key never leaves the host, clients ask its token service for a signed token instead
+use crate::error::NetworkingError;
+use bevy::asset::ron;
+use bevy::prelude::*;
+use core::net::SocketAddr;
+use core::time::Duration;
+use lightyear::netcode::{ConnectToken, PRIVATE_KEY_BYTES};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+#[cfg(not(target_family = "wasm"))]
+use {
+    bevy::tasks::IoTaskPool,
+    std::io::{Read, Write},
+    std::net::{TcpListener, TcpStream},
+};
+
+/// Where a host keeps the key it generated, relative to the working directory
+pub const KEY_FILE: &str = "netcode_key.ron";
+/// The token service listens on the game port plus this, websockets already use the game port for tcp
+pub const TOKEN_PORT_OFFSET: u16 = 1;
+/// How long a client has to use a token once it's issued
+pub const TOKEN_EXPIRE_SECS: i32 = 30;
+/// Same as the client's netcode timeout
+pub const CONNECTION_TIMEOUT_SECS: i32 = 3;
+/// Longest timeout the token service will put in a token, the server keeps dead clients around that long
+pub const MAX_CONNECTION_TIMEOUT_SECS: i32 = 30;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the token service for a server at `server_addr` is
+pub fn token_addr(server_addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(server_addr.ip(), server_addr.port() + TOKEN_PORT_OFFSET)
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredKey {
+    private_key: [u8; PRIVATE_KEY_BYTES],
+}
+
+/// Reads the key saved in `path`, the first time there isn't one it's generated and saved there
+pub fn load_or_create_key(path: &Path) -> Result<[u8; PRIVATE_KEY_BYTES], NetworkingError> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => ron::from_str::<StoredKey>(&text)
+            .map(|stored| stored.private_key)
+            .map_err(|error| {
+                NetworkingError::BadPrivateKey(format!("{}: {error}", path.display()))
+            }),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            let private_key: [u8; PRIVATE_KEY_BYTES] = rand::random();
+            let text = ron::ser::to_string_pretty(&StoredKey { private_key }, default())
+                .map_err(|error| NetworkingError::Io(error.to_string()))?;
+            write_private(path, &text).map_err(|error| {
+                NetworkingError::Io(format!("Couldn't save {}: {error}", path.display()))
+            })?;
+            info!("🔑 Generated a new private key in {}", path.display());
+            Ok(private_key)
+        }
+        Err(error) => Err(NetworkingError::Io(format!(
+            "Couldn't read {}: {error}",
+            path.display()
+        ))),
+    }
+}
+
+/// Only the owner gets to read it on unix
+fn write_private(path: &Path, text: &str) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    std::io::Write::write_all(&mut options.open(path)?, text.as_bytes())
+}
+
+/// Hands out connect tokens, normally by asking the host's [`TokenService`], swap in a [`LocalIssuer`] for tests
+pub trait TokenIssuer: Send + Sync + 'static {
+    /// This can block, it gets run on the io task pool. `client_id` and `timeout` are only what the
+    /// client would like, an issuer that can't trust the client picks its own id and caps the timeout
+    fn connect_token(
+        &self,
+        client_id: u64,
+        timeout: Duration,
+    ) -> Result<ConnectToken, NetworkingError>;
+}
+
+/// Signs tokens itself so it needs the private key, only the host or a test should have one
+#[derive(Clone, Debug)]
+pub struct LocalIssuer {
+    /// The address clients will connect to, it ends up inside the token
+    pub server_addr: SocketAddr,
+    pub protocol_id: u64,
+    pub private_key: [u8; PRIVATE_KEY_BYTES],
+}
+
+impl TokenIssuer for LocalIssuer {
+    fn connect_token(
+        &self,
+        client_id: u64,
+        timeout: Duration,
+    ) -> Result<ConnectToken, NetworkingError> {
+        ConnectToken::build(
+            self.server_addr,
+            self.protocol_id,
+            client_id,
+            self.private_key,
+        )
+        .expire_seconds(TOKEN_EXPIRE_SECS)
+        .timeout_seconds(timeout.as_secs().max(1) as i32)
+        .generate()
+        .map_err(|error| NetworkingError::Netcode(format!("{error:?}")))
+    }
+}
+
+/// Asks a [`TokenService`] for a token over http, the service decides the client id
+#[cfg(not(target_family = "wasm"))]
+#[derive(Clone, Debug)]
+pub struct HttpIssuer {
+    pub addr: SocketAddr,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl TokenIssuer for HttpIssuer {
+    fn connect_token(
+        &self,
+        _client_id: u64,
+        timeout: Duration,
+    ) -> Result<ConnectToken, NetworkingError> {
+        let io = |error: std::io::Error| {
+            NetworkingError::Io(format!("Token service at {}: {error}", self.addr))
+        };
+        let mut stream = TcpStream::connect_timeout(&self.addr, REQUEST_TIMEOUT).map_err(io)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT)).map_err(io)?;
+        write!(
+            stream,
+            "GET /token?timeout_secs={} HTTP/1.0\r\nHost: {}\r\n\r\n",
+            timeout.as_secs(),
+            self.addr
+        )
+        .map_err(io)?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).map_err(io)?;
+
+        let Some(body) = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map(|end| end + 4)
+        else {
+            return Err(NetworkingError::Netcode("malformed token response".into()));
+        };
+        if !response.starts_with(b"HTTP/1.0 200") {
+            let status = String::from_utf8_lossy(&response[..body]);
+            return Err(NetworkingError::Netcode(format!(
+                "token service refused: {}",
+                status.lines().next().unwrap_or_default()
+            )));
+        }
+        ConnectToken::try_from_bytes(&response[body..])
+            .map_err(|error| NetworkingError::Netcode(format!("bad token: {error:?}")))
+    }
+}
+
+/// A tiny http endpoint that sits next to the server, `GET /token?timeout_secs=N` answers with a
+/// connect token. The id in it is made up here, if clients picked their own they could take someone
+/// else's and kick them off. The timeout is the client's, up to [`MAX_CONNECTION_TIMEOUT_SECS`]
+#[cfg(not(target_family = "wasm"))]
+#[derive(Component)]
+pub struct TokenService {
+    listener: TcpListener,
+    issuer: LocalIssuer,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl TokenService {
+    pub fn bind(issuer: LocalIssuer) -> Result<Self, NetworkingError> {
+        let addr = token_addr(issuer.server_addr);
+        let listener = TcpListener::bind(addr).map_err(|error| match error.kind() {
+            std::io::ErrorKind::AddrInUse => NetworkingError::PortInUse(addr.port()),
+            _ => NetworkingError::Io(error.to_string()),
+        })?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|error| NetworkingError::Io(error.to_string()))?;
+        info!("🎟️ Handing out connect tokens on {addr}");
+        Ok(Self { listener, issuer })
+    }
+}
+
+/// Picks up waiting requests, each one gets answered off the main thread
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn serve_tokens(services: Query<&TokenService>) {
+    for service in &services {
+        while let Ok((stream, peer)) = service.listener.accept() {
+            let issuer = service.issuer.clone();
+            IoTaskPool::get()
+                .spawn(async move {
+                    if let Err(error) = answer_token_request(stream, issuer) {
+                        warn!("Token request from {peer} failed: {error}");
+                    }
+                })
+                .detach();
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn answer_token_request(mut stream: TcpStream, mut issuer: LocalIssuer) -> std::io::Result<()> {
+    // Some platforms hand out accepted streams that are still nonblocking
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = [0; 1024];
+    let read = stream.read(&mut request)?;
+    let request = String::from_utf8_lossy(&request[..read]);
+    let timeout = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(requested_timeout);
+
+    // The listener is bound to every interface, the token has to name the one this client reached
+    issuer.server_addr.set_ip(stream.local_addr()?.ip());
+    let token = timeout
+        .and_then(|timeout| issuer.connect_token(rand::random(), timeout).ok())
+        .and_then(|token| token.try_into_bytes().ok());
+    match token {
+        Some(token) => {
+            write!(
+                stream,
+                "HTTP/1.0 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+                token.len()
+            )?;
+            stream.write_all(&token)
+        }
+        None => write!(
+            stream,
+            "HTTP/1.0 400 Bad Request\r\nContent-Length: 0\r\n\r\n"
+        ),
+    }
+}
+
+/// The timeout a token request path asks for, `None` if it isn't one
+#[cfg(not(target_family = "wasm"))]
+fn requested_timeout(path: &str) -> Option<Duration> {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    if path != "/token" {
+        return None;
+    }
+    let secs = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("timeout_secs="))
+        .and_then(|secs| secs.parse::<i32>().ok())
+        .unwrap_or(CONNECTION_TIMEOUT_SECS)
+        .clamp(1, MAX_CONNECTION_TIMEOUT_SECS);
+    Some(Duration::from_secs(secs as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path nobody else is using, cleaned up first in case an earlier run died
+    fn temp_key(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}_{name}.ron", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn keys_are_generated_once() {
+        let path = temp_key("generated_key");
+        let key = load_or_create_key(&path).unwrap();
+        assert_ne!(key, [0; PRIVATE_KEY_BYTES]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_eq!(load_or_create_key(&path).unwrap(), key);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn garbled_keys_are_an_error() {
+        let path = temp_key("garbled_key");
+        std::fs::write(&path, "not a key").unwrap();
+        assert!(matches!(
+            load_or_create_key(&path),
+            Err(NetworkingError::BadPrivateKey(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[test]
+    fn timeouts_are_capped() {
+        let secs = |secs| Some(Duration::from_secs(secs));
+        assert_eq!(
+            requested_timeout("/token"),
+            secs(CONNECTION_TIMEOUT_SECS as u64)
+        );
+        assert_eq!(requested_timeout("/token?timeout_secs=10"), secs(10));
+        assert_eq!(
+            requested_timeout("/token?timeout_secs=600"),
+            secs(MAX_CONNECTION_TIMEOUT_SECS as u64)
+        );
+        assert_eq!(requested_timeout("/token?timeout_secs=-5"), secs(1));
+        assert_eq!(requested_timeout("/token?timeout_secs=0"), secs(1));
+        assert_eq!(
+            requested_timeout("/token?client=1&timeout_secs=junk"),
+            secs(CONNECTION_TIMEOUT_SECS as u64)
+        );
+        assert_eq!(requested_timeout("/favicon.ico"), None);
+    }
+}
@@ -2,22 +2,31 @@
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 use core::net::{Ipv4Addr, SocketAddr};
+use core::time::Duration;
 
+use crate::auth::{CONNECTION_TIMEOUT_SECS, TokenIssuer};
+#[cfg(not(target_family = "wasm"))]
+use crate::auth::{HttpIssuer, token_addr};
+use crate::error::{ConnectFailed, NetworkingError};
+use crate::lifecycle::{Reconnect, ReconnectPolicy, dial, retry_failed};
 use crate::shared::SharedSettings;
 
 use bevy::{
     ecs::{lifecycle::HookContext, world::DeferredWorld},
     prelude::*,
+    tasks::{IoTaskPool, Task, block_on, futures_lite::future},
 };
 use lightyear::{
-    netcode::{NetcodeClient, client_plugin::NetcodeConfig},
+    netcode::{ConnectToken, NetcodeClient, client_plugin::NetcodeConfig},
     prelude::{client::*, *},
 };
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
-#[derive(Event)]
+#[derive(Event, Clone)]
 pub struct ConnectClient {
+    /// Only used with [`ClientAuth::Manual`] or an issuer that trusts it, the token service picks its own
     pub client_id: u64,
     /// The client port to listen on
     pub client_port: u16,
@@ -26,6 +35,26 @@ pub struct ConnectClient {
     /// Which transport to use
     pub transport: ClientTransports,
     pub shared: SharedSettings,
+    /// WebTransport only accepts a server whose [`CertificateDigest`](crate::host::CertificateDigest) matches this
+    pub certificate_digest: Option<String>,
+    pub auth: ClientAuth,
+    /// Gives up on a silent server after this long, the token service caps it at
+    /// [`MAX_CONNECTION_TIMEOUT_SECS`](crate::auth::MAX_CONNECTION_TIMEOUT_SECS)
+    pub timeout: Duration,
+    /// Try again when the connection drops or can't be made, `None` leaves that to you
+    pub reconnect: Option<ReconnectPolicy>,
+}
+
+/// Where the client's connect token comes from
+#[derive(Default, Clone)]
+pub enum ClientAuth {
+    /// Ask the [`TokenService`](crate::auth::TokenService) running next to the server
+    #[default]
+    TokenService,
+    /// Get one from somewhere else, like a matchmaker or a [`LocalIssuer`](crate::auth::LocalIssuer) in tests
+    Issuer(Arc<dyn TokenIssuer>),
+    /// Sign our own token with [`SharedSettings::private_key`], anyone with the key can join so only for local testing
+    Manual,
 }
 
 impl Default for ConnectClient {
@@ -33,11 +62,15 @@ impl Default for ConnectClient {
         use crate::shared::*;
         let client_id = rand::random::<u64>();
         Self {
-            client_id: client_id,
+            client_id,
             client_port: CLIENT_PORT,
             server_addr: SERVER_ADDR,
             transport: ClientTransports::Udp,
             shared: SHARED_SETTINGS,
+            certificate_digest: None,
+            auth: ClientAuth::default(),
+            timeout: Duration::from_secs(CONNECTION_TIMEOUT_SECS as u64),
+            reconnect: None,
         }
     }
 }
@@ -62,45 +95,45 @@ pub(crate) fn handle_connecting_client(trigger: On<ConnectClient>, mut cmds: Com
             Name::from(format!("Client {:?}", settings.client_id)),
         ))
         .id();
-    let mut add_netcode = || {
-        let auth = Authentication::Manual {
-            server_addr: settings.server_addr,
-            client_id: settings.client_id,
-            private_key: settings.shared.private_key,
-            protocol_id: settings.shared.protocol_id,
-        };
-        let netcode_config = NetcodeConfig {
-            // Make sure that the server times out clients when their connection is closed
-            client_timeout_secs: 3,
-            token_expire_secs: -1,
-            ..default()
-        };
+    if let Some(policy) = settings.reconnect {
         cmds.entity(client)
-            .insert(NetcodeClient::new(auth, netcode_config).unwrap());
-    };
+            .insert(Reconnect::new(settings.clone(), policy));
+    }
+
+    if let Err(reason) = setup_client(&mut cmds, client, settings) {
+        connect_failed(&mut cmds, client, reason);
+    }
+}
+
+pub(crate) fn connect_failed(cmds: &mut Commands, client: Entity, reason: NetworkingError) {
+    error!("Couldn't connect: {reason}");
+    cmds.entity(client).despawn();
+    cmds.trigger(ConnectFailed { reason });
+}
+
+/// Adds the transport, then either connects right away or waits on a token
+pub(crate) fn setup_client(
+    cmds: &mut Commands,
+    client: Entity,
+    settings: &ConnectClient,
+) -> Result<(), NetworkingError> {
     match settings.transport {
         #[cfg(not(target_family = "wasm"))]
         ClientTransports::Udp => {
-            add_netcode();
             cmds.entity(client).insert(UdpIo::default());
         }
         ClientTransports::WebTransport => {
-            add_netcode();
-            let certificate_digest = {
+            let certificate_digest = match &settings.certificate_digest {
+                Some(digest) => digest.clone(),
                 #[cfg(target_family = "wasm")]
-                {
-                    include_str!("../../../certificates/digest.txt").to_string()
-                }
+                None => include_str!("../../../certificates/digest.txt").to_string(),
                 #[cfg(not(target_family = "wasm"))]
-                {
-                    "".to_string()
-                }
+                None => "".to_string(),
             };
             cmds.entity(client)
                 .insert(WebTransportClientIo { certificate_digest });
         }
         ClientTransports::WebSocket => {
-            add_netcode();
             let config = {
                 #[cfg(target_family = "wasm")]
                 {
@@ -115,13 +148,93 @@ pub(crate) fn handle_connecting_client(trigger: On<ConnectClient>, mut cmds: Com
         }
         #[cfg(feature = "steam")]
         ClientTransports::Steam => {
-            entity_mut.insert(SteamClientIo {
+            // Steam does its own auth
+            cmds.entity(client).insert(SteamClientIo {
                 target: ConnectTarget::Addr(settings.server_addr),
                 config: Default::default(),
             });
+            dial(cmds, client);
+            return Ok(());
         }
     };
-    cmds.trigger(Connect { entity: client });
+
+    let issuer: Arc<dyn TokenIssuer> = match &settings.auth {
+        ClientAuth::Manual => {
+            cmds.entity(client).insert(netcode_client(
+                Authentication::Manual {
+                    server_addr: settings.server_addr,
+                    client_id: settings.client_id,
+                    private_key: settings.shared.private_key,
+                    protocol_id: settings.shared.protocol_id,
+                },
+                settings.timeout,
+            )?);
+            dial(cmds, client);
+            return Ok(());
+        }
+        #[cfg(not(target_family = "wasm"))]
+        ClientAuth::TokenService => Arc::new(HttpIssuer {
+            addr: token_addr(settings.server_addr),
+        }),
+        #[cfg(target_family = "wasm")]
+        ClientAuth::TokenService => {
+            return Err(NetworkingError::Transport(
+                "the token service needs tcp, pass a ClientAuth::Issuer on wasm".into(),
+            ));
+        }
+        ClientAuth::Issuer(issuer) => issuer.clone(),
+    };
+    let (client_id, timeout) = (settings.client_id, settings.timeout);
+    let task = IoTaskPool::get().spawn(async move { issuer.connect_token(client_id, timeout) });
+    cmds.entity(client).insert(PendingToken {
+        task,
+        timeout: settings.timeout,
+    });
+    Ok(())
+}
+
+fn netcode_client(
+    auth: Authentication,
+    timeout: Duration,
+) -> Result<NetcodeClient, NetworkingError> {
+    let netcode_config = NetcodeConfig {
+        // Make sure that the server times out clients when their connection is closed
+        client_timeout_secs: timeout.as_secs().max(1) as i32,
+        token_expire_secs: -1,
+        ..default()
+    };
+    NetcodeClient::new(auth, netcode_config)
+        .map_err(|error| NetworkingError::Netcode(format!("{error:?}")))
+}
+
+/// A client still waiting on its connect token
+#[derive(Component)]
+pub(crate) struct PendingToken {
+    task: Task<Result<ConnectToken, NetworkingError>>,
+    timeout: Duration,
+}
+
+pub(crate) fn finish_pending_tokens(
+    mut cmds: Commands,
+    mut pending: Query<(Entity, &mut PendingToken, Option<&mut Reconnect>)>,
+) {
+    for (client, mut pending, reconnect) in &mut pending {
+        let Some(token) = block_on(future::poll_once(&mut pending.task)) else {
+            continue;
+        };
+        cmds.entity(client).remove::<PendingToken>();
+        match token.and_then(|token| netcode_client(Authentication::Token(token), pending.timeout))
+        {
+            Ok(netcode) => {
+                cmds.entity(client).insert(netcode);
+                dial(&mut cmds, client);
+            }
+            Err(reason) => match reconnect {
+                Some(mut reconnect) => retry_failed(&mut cmds, client, &mut reconnect, reason),
+                None => connect_failed(&mut cmds, client, reason),
+            },
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -0,0 +1,443 @@
+// Finding servers without typing an address in
+// Hosts shout a beacon across the LAN every second, and can also list themselves with a master server
+use crate::error::NetworkingError;
+use bevy::asset::ron;
+use bevy::prelude::*;
+use bevy::tasks::{IoTaskPool, Task, block_on, futures_lite::future};
+use core::net::{Ipv4Addr, SocketAddr};
+use core::time::Duration;
+use lightyear::prelude::{server::*, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+#[cfg(not(target_family = "wasm"))]
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex};
+
+/// Beacons go to and are listened for on this port
+pub const DISCOVERY_PORT: u16 = 5890;
+/// Anything else that shows up on the port gets ignored
+const BEACON_MAGIC: &[u8] = b"WEAVE\0";
+const BEACON_INTERVAL: Duration = Duration::from_secs(1);
+/// Servers that haven't been heard from in this long are dropped from [`DiscoveredServers`]
+const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct DiscoveryPlugin;
+
+impl Plugin for DiscoveryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DiscoveredServers>();
+        app.add_observer(start_discovery);
+        app.add_observer(stop_discovery);
+        app.add_observer(refresh_server_list);
+        app.add_systems(
+            Update,
+            (broadcast_beacons, forget_stale_servers, receive_server_list),
+        );
+        #[cfg(not(target_family = "wasm"))]
+        app.add_systems(Update, listen_for_beacons);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TransportKind {
+    Udp,
+    WebTransport,
+    WebSocket,
+    Steam,
+}
+
+/// What a host tells everyone about itself
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerBeacon {
+    pub name: String,
+    pub players: u32,
+    pub protocol_id: u64,
+    /// The game port, the beacon itself comes from some other one
+    pub port: u16,
+    pub transports: Vec<TransportKind>,
+}
+
+impl ServerBeacon {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = BEACON_MAGIC.to_vec();
+        // Can't fail, it's plain data
+        bytes.extend(ron::to_string(self).unwrap_or_default().into_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(bytes.strip_prefix(BEACON_MAGIC)?).ok()?;
+        ron::from_str(text).ok()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiscoverySource {
+    Lan,
+    MasterServer,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiscoveredServer {
+    pub beacon: ServerBeacon,
+    pub source: DiscoverySource,
+    /// [`Time::elapsed`] when it was last heard from
+    pub last_seen: Duration,
+}
+
+/// Every server heard from recently, keyed by the address to connect to
+#[derive(Resource, Default, Debug, Deref)]
+pub struct DiscoveredServers(pub HashMap<SocketAddr, DiscoveredServer>);
+
+impl DiscoveredServers {
+    /// Servers this build can actually join
+    pub fn compatible(
+        &self,
+        protocol_id: u64,
+    ) -> impl Iterator<Item = (&SocketAddr, &DiscoveredServer)> {
+        self.0
+            .iter()
+            .filter(move |(_, server)| server.beacon.protocol_id == protocol_id)
+    }
+}
+
+/// Put on the server entity by [`Host`](crate::host::Host) to advertise it on the LAN
+#[derive(Component)]
+pub struct Advertise {
+    pub name: String,
+    pub protocol_id: u64,
+    pub port: u16,
+    pub transport: TransportKind,
+    timer: Timer,
+}
+
+impl Advertise {
+    pub fn new(name: String, protocol_id: u64, port: u16, transport: TransportKind) -> Self {
+        Self {
+            name,
+            protocol_id,
+            port,
+            transport,
+            timer: Timer::new(BEACON_INTERVAL, TimerMode::Repeating),
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn beacon_socket() -> Result<UdpSocket, NetworkingError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .map_err(|error| NetworkingError::Io(error.to_string()))?;
+    socket
+        .set_broadcast(true)
+        .map_err(|error| NetworkingError::Io(error.to_string()))?;
+    Ok(socket)
+}
+
+/// Also heartbeats the master server if there is one, a listing there expires just like a beacon would
+fn broadcast_beacons(
+    time: Res<Time>,
+    mut socket: Local<Option<std::net::UdpSocket>>,
+    registry: Option<Res<MasterServerRegistry>>,
+    mut servers: Query<&mut Advertise, (With<Server>, With<Started>)>,
+    players: Query<(), (With<ClientOf>, With<Connected>)>,
+) {
+    for mut advertise in &mut servers {
+        if !advertise.timer.tick(time.delta()).just_finished() {
+            continue;
+        }
+        let beacon = ServerBeacon {
+            name: advertise.name.clone(),
+            players: players.iter().count() as u32,
+            protocol_id: advertise.protocol_id,
+            port: advertise.port,
+            transports: vec![advertise.transport],
+        };
+
+        #[cfg(not(target_family = "wasm"))]
+        {
+            if socket.is_none() {
+                match beacon_socket() {
+                    Ok(new) => *socket = Some(new),
+                    Err(error) => warn!("Can't send LAN beacons: {error}"),
+                }
+            }
+            if let Some(socket) = socket.as_ref() {
+                let target = (Ipv4Addr::BROADCAST, DISCOVERY_PORT);
+                if let Err(error) = socket.send_to(&beacon.to_bytes(), target) {
+                    debug!("LAN beacon didn't go out: {error}");
+                }
+            }
+        }
+
+        if let Some(registry) = &registry {
+            let registry = registry.0.clone();
+            IoTaskPool::get()
+                .spawn(async move {
+                    if let Err(error) = registry.register(beacon) {
+                        warn!("Master server didn't take our listing: {error}");
+                    }
+                })
+                .detach();
+        }
+    }
+}
+
+/// Start filling [`DiscoveredServers`] from LAN beacons
+#[derive(Event, Clone, Copy, Debug, Default)]
+pub struct DiscoverServers;
+
+#[derive(Event, Clone, Copy, Debug, Default)]
+pub struct StopDiscovery;
+
+#[cfg(not(target_family = "wasm"))]
+#[derive(Resource)]
+struct BeaconListener(UdpSocket);
+
+fn start_discovery(_trigger: On<DiscoverServers>, mut commands: Commands) {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)).and_then(|socket| {
+            socket.set_nonblocking(true)?;
+            Ok(socket)
+        });
+        match socket {
+            Ok(socket) => {
+                info!("📡 Listening for LAN servers on {DISCOVERY_PORT}");
+                commands.insert_resource(BeaconListener(socket));
+            }
+            Err(error) => warn!("Can't listen for LAN servers: {error}"),
+        }
+    }
+    #[cfg(target_family = "wasm")]
+    warn!("Browsers can't listen for LAN servers, use a master server");
+}
+
+fn stop_discovery(_trigger: On<StopDiscovery>, mut commands: Commands) {
+    #[cfg(not(target_family = "wasm"))]
+    commands.remove_resource::<BeaconListener>();
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn listen_for_beacons(
+    time: Res<Time>,
+    listener: Option<Res<BeaconListener>>,
+    mut discovered: ResMut<DiscoveredServers>,
+) {
+    let Some(listener) = listener else {
+        return;
+    };
+    let mut buffer = [0; 1024];
+    while let Ok((read, from)) = listener.0.recv_from(&mut buffer) {
+        let Some(beacon) = ServerBeacon::from_bytes(&buffer[..read]) else {
+            continue;
+        };
+        // The beacon comes from a throwaway port, the game port is inside it
+        let addr = SocketAddr::new(from.ip(), beacon.port);
+        discovered.0.insert(
+            addr,
+            DiscoveredServer {
+                beacon,
+                source: DiscoverySource::Lan,
+                last_seen: time.elapsed(),
+            },
+        );
+    }
+}
+
+fn forget_stale_servers(time: Res<Time>, mut discovered: ResMut<DiscoveredServers>) {
+    let now = time.elapsed();
+    if discovered
+        .values()
+        .any(|server| now.saturating_sub(server.last_seen) > SERVER_TIMEOUT)
+    {
+        discovered
+            .0
+            .retain(|_, server| now.saturating_sub(server.last_seen) <= SERVER_TIMEOUT);
+    }
+}
+
+/// Somewhere servers list themselves so players outside the LAN can find them
+/// Calls can block, they're run on the io task pool
+pub trait MasterServer: Send + Sync + 'static {
+    /// Called every beacon interval while hosting, also works as the heartbeat
+    fn register(&self, beacon: ServerBeacon) -> Result<(), NetworkingError>;
+    fn list(&self) -> Result<Vec<(SocketAddr, ServerBeacon)>, NetworkingError>;
+}
+
+/// Insert this to register with and list from a master server
+#[derive(Resource, Clone)]
+pub struct MasterServerRegistry(pub Arc<dyn MasterServer>);
+
+/// Keeps listings in memory, hosts register from `addr`. Clone it into both apps to fake a real one
+#[derive(Clone, Default)]
+pub struct MockMasterServer {
+    pub addr: Option<SocketAddr>,
+    listings: Arc<Mutex<HashMap<SocketAddr, ServerBeacon>>>,
+}
+
+impl MockMasterServer {
+    /// A handle that registers as a host at `addr`, sharing the same listings
+    pub fn as_host(&self, addr: SocketAddr) -> Self {
+        Self {
+            addr: Some(addr),
+            listings: self.listings.clone(),
+        }
+    }
+}
+
+impl MasterServer for MockMasterServer {
+    fn register(&self, beacon: ServerBeacon) -> Result<(), NetworkingError> {
+        let addr = self.addr.ok_or(NetworkingError::Transport(
+            "mock master server needs an address to register".into(),
+        ))?;
+        self.listings
+            .lock()
+            .map_err(|_| NetworkingError::Io("mock master server poisoned".into()))?
+            .insert(addr, beacon);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<(SocketAddr, ServerBeacon)>, NetworkingError> {
+        let listings = self
+            .listings
+            .lock()
+            .map_err(|_| NetworkingError::Io("mock master server poisoned".into()))?;
+        Ok(listings
+            .iter()
+            .map(|(addr, beacon)| (*addr, beacon.clone()))
+            .collect())
+    }
+}
+
+/// Ask the [`MasterServerRegistry`] for its list, results land in [`DiscoveredServers`]
+#[derive(Event, Clone, Copy, Debug, Default)]
+pub struct RefreshServerList;
+
+#[derive(Resource)]
+struct PendingServerList(Task<Result<Vec<(SocketAddr, ServerBeacon)>, NetworkingError>>);
+
+fn refresh_server_list(
+    _trigger: On<RefreshServerList>,
+    mut commands: Commands,
+    registry: Option<Res<MasterServerRegistry>>,
+) {
+    let Some(registry) = registry else {
+        warn!("No master server to ask for servers");
+        return;
+    };
+    let registry = registry.0.clone();
+    let task = IoTaskPool::get().spawn(async move { registry.list() });
+    commands.insert_resource(PendingServerList(task));
+}
+
+fn receive_server_list(
+    time: Res<Time>,
+    mut commands: Commands,
+    pending: Option<ResMut<PendingServerList>>,
+    mut discovered: ResMut<DiscoveredServers>,
+) {
+    let Some(mut pending) = pending else {
+        return;
+    };
+    let Some(list) = block_on(future::poll_once(&mut pending.0)) else {
+        return;
+    };
+    commands.remove_resource::<PendingServerList>();
+    match list {
+        Ok(list) => {
+            for (addr, beacon) in list {
+                discovered.0.insert(
+                    addr,
+                    DiscoveredServer {
+                        beacon,
+                        source: DiscoverySource::MasterServer,
+                        last_seen: time.elapsed(),
+                    },
+                );
+            }
+        }
+        Err(error) => warn!("Couldn't get the server list: {error}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beacon(name: &str) -> ServerBeacon {
+        ServerBeacon {
+            name: name.into(),
+            players: 3,
+            protocol_id: 7,
+            port: 5888,
+            transports: vec![TransportKind::WebTransport, TransportKind::WebSocket],
+        }
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)
+    }
+
+    #[test]
+    fn beacons_round_trip() {
+        let beacon = beacon("Weave Server");
+        let bytes = beacon.to_bytes();
+        assert!(bytes.starts_with(BEACON_MAGIC));
+        assert_eq!(ServerBeacon::from_bytes(&bytes), Some(beacon));
+    }
+
+    #[test]
+    fn other_traffic_is_ignored() {
+        let mut bytes = beacon("Weave Server").to_bytes();
+        bytes[0] = b'X';
+        assert_eq!(ServerBeacon::from_bytes(&bytes), None);
+        // Right magic, nothing after it
+        assert_eq!(ServerBeacon::from_bytes(BEACON_MAGIC), None);
+        assert_eq!(ServerBeacon::from_bytes(b"WEAVE"), None);
+    }
+
+    #[test]
+    fn mock_master_server_lists_what_was_registered() {
+        let master = MockMasterServer::default();
+        // Clients can't register, they have no address
+        assert!(master.register(beacon("Nowhere")).is_err());
+
+        master.as_host(addr(1)).register(beacon("First")).unwrap();
+        master.as_host(addr(2)).register(beacon("Second")).unwrap();
+        // The heartbeat replaces the old listing
+        master.as_host(addr(1)).register(beacon("Renamed")).unwrap();
+
+        let mut list = master.list().unwrap();
+        list.sort_by_key(|(addr, _)| *addr);
+        assert_eq!(
+            list,
+            vec![(addr(1), beacon("Renamed")), (addr(2), beacon("Second"))]
+        );
+    }
+
+    #[test]
+    fn refreshing_fills_discovered_servers() {
+        let master = MockMasterServer::default();
+        master.as_host(addr(1)).register(beacon("Listed")).unwrap();
+
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, DiscoveryPlugin));
+        app.insert_resource(MasterServerRegistry(Arc::new(master)));
+        app.world_mut().trigger(RefreshServerList);
+        // The list comes back on the io pool
+        for _ in 0..100 {
+            app.update();
+            if !app.world().contains_resource::<PendingServerList>() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let discovered = app.world().resource::<DiscoveredServers>();
+        let server = &discovered[&addr(1)];
+        assert_eq!(server.beacon, beacon("Listed"));
+        assert_eq!(server.source, DiscoverySource::MasterServer);
+        assert_eq!(discovered.compatible(7).count(), 1);
+        assert_eq!(discovered.compatible(8).count(), 0);
+    }
+}
@@ -0,0 +1,48 @@
+use crate::host::CertificateError;
+use bevy::prelude::*;
+
+/// Everything that can go wrong setting up a host or a client, meant to be shown to the player
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkingError {
+    /// Something else is already listening on it
+    PortInUse(u16),
+    BadPrivateKey(String),
+    Certificate(CertificateError),
+    Netcode(String),
+    /// The transport couldn't be configured
+    Transport(String),
+    Io(String),
+}
+
+impl std::fmt::Display for NetworkingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetworkingError::PortInUse(port) => write!(f, "port {port} is already in use"),
+            NetworkingError::BadPrivateKey(reason) => write!(f, "bad private key: {reason}"),
+            NetworkingError::Certificate(error) => write!(f, "{error}"),
+            NetworkingError::Netcode(reason) => write!(f, "netcode: {reason}"),
+            NetworkingError::Transport(reason) => write!(f, "transport: {reason}"),
+            NetworkingError::Io(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl std::error::Error for NetworkingError {}
+
+impl From<CertificateError> for NetworkingError {
+    fn from(error: CertificateError) -> Self {
+        NetworkingError::Certificate(error)
+    }
+}
+
+/// Hosting didn't start, the server entity is already gone
+#[derive(Event, Debug, Clone)]
+pub struct HostFailed {
+    pub reason: NetworkingError,
+}
+
+/// Connecting didn't start, the client entity is already gone
+#[derive(Event, Debug, Clone)]
+pub struct ConnectFailed {
+    pub reason: NetworkingError,
+}
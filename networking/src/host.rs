@@ -1,20 +1,24 @@
 #![allow(unused_imports)] // stop the feature gate warnings
 #![allow(unused_variables)] // ^
-#![allow(unreachable_code)] // stop the TODO warning
 
 use core::net::{Ipv4Addr, SocketAddr};
 
 use bevy::asset::ron;
 use bevy::prelude::*;
 use core::time::Duration;
+use std::path::PathBuf;
 
-use crate::shared::SharedSettings;
+#[cfg(not(target_family = "wasm"))]
+use crate::auth::TokenService;
+use crate::auth::{LocalIssuer, load_or_create_key};
+use crate::discovery::{Advertise, TransportKind};
+use crate::error::{HostFailed, NetworkingError};
+use crate::shared::{SEND_INTERVAL, SharedSettings, ZERO_KEY};
 #[cfg(not(target_family = "wasm"))]
 use async_compat::Compat;
 use bevy::ecs::lifecycle::HookContext;
 use bevy::ecs::world::DeferredWorld;
-#[cfg(not(target_family = "wasm"))]
-use bevy::tasks::IoTaskPool;
+use bevy::tasks::{IoTaskPool, Task, block_on, futures_lite::future};
 use lightyear::netcode::{NetcodeServer, PRIVATE_KEY_BYTES};
 use lightyear::prelude::server::*;
 use lightyear::prelude::*;
@@ -25,6 +29,15 @@ use tracing::warn;
 pub struct Host {
     pub transport: ServerTransports,
     pub shared: SharedSettings,
+    /// Also play on this instance, dedicated servers turn this off
+    pub local_client: bool,
+    /// Key to load or generate, `None` uses [`SharedSettings::private_key`].
+    /// `LIGHTYEAR_PRIVATE_KEY` beats both
+    pub key_file: Option<PathBuf>,
+    /// Run a [`TokenService`] next to the server so remote clients can get connect tokens
+    pub token_service: bool,
+    /// Advertised on the LAN and any master server under this name, `None` keeps it hidden
+    pub advertise: Option<String>,
 }
 
 impl Default for Host {
@@ -36,83 +49,182 @@ impl Default for Host {
                 certificate: WebTransportCertificateSettings::default(),
             },
             shared: SHARED_SETTINGS,
+            local_client: true,
+            key_file: Some(crate::auth::KEY_FILE.into()),
+            token_service: true,
+            advertise: Some("Weave Server".to_string()),
         }
     }
 }
 
-/// This spawns both the host server and the host client
+/// This spawns the host server and, unless it's dedicated, the host client
 pub(crate) fn handle_spawning_host(trigger: On<Host>, mut cmds: Commands) {
     let server = cmds.spawn(Name::new("Host Server")).id();
     let settings = trigger.event();
 
-    let mut add_netcode = || {
-        let private_key = if let Some(key) = parse_private_key_from_env() {
+    if let Err(reason) = setup_server(&mut cmds, server, settings) {
+        error!("Couldn't host: {reason}");
+        cmds.entity(server).despawn();
+        cmds.trigger(HostFailed { reason });
+        return;
+    }
+    cmds.trigger(Start { entity: server });
+
+    if settings.local_client {
+        let client = cmds
+            .spawn((
+                crate::client::LocalClient,
+                Client::default(),
+                LinkOf { server },
+                Name::new("Host Client"),
+            ))
+            .id();
+        // Lightyear connects it straight away as long as the server has started by now
+        crate::lifecycle::dial(&mut cmds, client);
+    }
+}
+
+fn setup_server(
+    cmds: &mut Commands,
+    server: Entity,
+    settings: &Host,
+) -> Result<(), NetworkingError> {
+    let private_key = match parse_private_key_from_env()? {
+        Some(key) => {
             info!("Using private key from LIGHTYEAR_PRIVATE_KEY env var");
             key
-        } else {
-            settings.shared.private_key
-        };
-        cmds.entity(server)
-            .insert(NetcodeServer::new(NetcodeConfig {
-                protocol_id: settings.shared.protocol_id,
-                private_key,
-                ..default()
-            }));
+        }
+        None => match &settings.key_file {
+            Some(path) => load_or_create_key(path)?,
+            None => {
+                if settings.shared.private_key == ZERO_KEY {
+                    warn!(
+                        "⚠️ Hosting with the all zero private key, anyone can make connect tokens for this server. Set a key file or LIGHTYEAR_PRIVATE_KEY"
+                    );
+                }
+                settings.shared.private_key
+            }
+        },
     };
+    let netcode = NetcodeServer::new(NetcodeConfig {
+        protocol_id: settings.shared.protocol_id,
+        private_key,
+        ..default()
+    });
 
     match settings.transport.clone() {
         #[cfg(feature = "udp")]
         ServerTransports::Udp { local_port } => {
-            add_netcode();
             let server_addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), local_port);
+            check_port(server_addr, false)?;
             cmds.entity(server)
-                .insert((LocalAddr(server_addr), ServerUdpIo::default()));
+                .insert((netcode, LocalAddr(server_addr), ServerUdpIo::default()));
         }
         ServerTransports::WebTransport {
             local_port,
             certificate,
         } => {
-            add_netcode();
             let server_addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), local_port);
+            // Quic runs over udp
+            check_port(server_addr, false)?;
+            let identity = certificate.load()?;
+            let digest = CertificateDigest::of(&identity);
+            info!("🔐 Certificate digest: {}", digest.0);
+            cmds.insert_resource(digest);
+
             cmds.entity(server).insert((
+                netcode,
                 LocalAddr(server_addr),
                 WebTransportServerIo {
-                    certificate: (&certificate).into(),
+                    certificate: identity,
                 },
             ));
+            if let WebTransportCertificateSettings::FromFile { cert, key } = certificate {
+                cmds.entity(server).insert(CertificateWatch::new(cert, key));
+            }
         }
         ServerTransports::WebSocket { local_port } => {
-            add_netcode();
             let server_addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), local_port);
+            check_port(server_addr, true)?;
             let sans = vec![
                 "localhost".to_string(),
                 "127.0.0.1".to_string(),
                 "::1".to_string(),
             ];
+            let identity = lightyear::websocket::server::Identity::self_signed(sans)
+                .map_err(|error| NetworkingError::Transport(format!("{error:?}")))?;
             let config = ServerConfig::builder()
                 .with_bind_address(server_addr)
-                .with_identity(lightyear::websocket::server::Identity::self_signed(sans).unwrap());
-            cmds.entity(server)
-                .insert((LocalAddr(server_addr), WebSocketServerIo { config }));
+                .with_identity(identity);
+            cmds.entity(server).insert((
+                netcode,
+                LocalAddr(server_addr),
+                WebSocketServerIo { config },
+            ));
         }
         #[cfg(feature = "steam")]
         ServerTransports::Steam { local_port } => {
             let server_addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), local_port);
-            entity_mut.insert(SteamServerIo {
+            cmds.entity(server).insert(SteamServerIo {
                 target: ListenTarget::Addr(server_addr),
                 config: SessionConfig::default(),
             });
         }
     }
 
-    cmds.spawn((
-        crate::client::LocalClient,
-        Client::default(),
-        LinkOf { server },
-        Name::new("Host Client"),
+    if let Some(name) = &settings.advertise {
+        cmds.entity(server).insert(Advertise::new(
+            name.clone(),
+            settings.shared.protocol_id,
+            settings.transport.local_port(),
+            settings.transport.kind(),
+        ));
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    if settings.token_service {
+        let server_addr = SocketAddr::new(
+            Ipv4Addr::UNSPECIFIED.into(),
+            settings.transport.local_port(),
+        );
+        cmds.entity(server).insert(TokenService::bind(LocalIssuer {
+            server_addr,
+            protocol_id: settings.shared.protocol_id,
+            private_key,
+        })?);
+    }
+    Ok(())
+}
+
+/// Every client link needs a sender before anything gets replicated to it
+/// The bandwidth cap sends the highest priority changes first and holds the rest for later
+pub(crate) fn replicate_to_client(trigger: On<Add, LinkOf>, mut cmds: Commands) {
+    cmds.entity(trigger.entity).insert(ReplicationSender::new(
+        SEND_INTERVAL,
+        SendUpdatesMode::SinceLastAck,
+        true,
     ));
 }
 
+/// Lightyear binds later where a failure can't be reported back, so try the port here first
+#[cfg(not(target_family = "wasm"))]
+fn check_port(addr: SocketAddr, tcp: bool) -> Result<(), NetworkingError> {
+    let bound = if tcp {
+        std::net::TcpListener::bind(addr).map(drop)
+    } else {
+        std::net::UdpSocket::bind(addr).map(drop)
+    };
+    bound.map_err(|error| match error.kind() {
+        std::io::ErrorKind::AddrInUse => NetworkingError::PortInUse(addr.port()),
+        _ => NetworkingError::Io(error.to_string()),
+    })
+}
+
+#[cfg(target_family = "wasm")]
+fn check_port(_addr: SocketAddr, _tcp: bool) -> Result<(), NetworkingError> {
+    Ok(())
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[non_exhaustive]
 pub enum ServerTransports {
@@ -133,6 +245,30 @@ pub enum ServerTransports {
     },
 }
 
+impl ServerTransports {
+    pub fn local_port(&self) -> u16 {
+        match self {
+            #[cfg(feature = "udp")]
+            ServerTransports::Udp { local_port } => *local_port,
+            ServerTransports::WebTransport { local_port, .. } => *local_port,
+            ServerTransports::WebSocket { local_port } => *local_port,
+            #[cfg(feature = "steam")]
+            ServerTransports::Steam { local_port } => *local_port,
+        }
+    }
+
+    pub fn kind(&self) -> TransportKind {
+        match self {
+            #[cfg(feature = "udp")]
+            ServerTransports::Udp { .. } => TransportKind::Udp,
+            ServerTransports::WebTransport { .. } => TransportKind::WebTransport,
+            ServerTransports::WebSocket { .. } => TransportKind::WebSocket,
+            #[cfg(feature = "steam")]
+            ServerTransports::Steam { .. } => TransportKind::Steam,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum WebTransportCertificateSettings {
     /// Generate a self-signed certificate, with given SANs list to add to the certifictate
@@ -158,9 +294,9 @@ impl Default for WebTransportCertificateSettings {
     }
 }
 
-impl From<&WebTransportCertificateSettings> for Identity {
-    fn from(wt: &WebTransportCertificateSettings) -> Identity {
-        match wt {
+impl WebTransportCertificateSettings {
+    pub fn load(&self) -> Result<Identity, CertificateError> {
+        match self {
             WebTransportCertificateSettings::AutoSelfSigned(sans) => {
                 // In addition to and Subject Alternate Names (SAN) added via the config,
                 // we add the public ip and domain for edgegap, if detected, and also
@@ -178,64 +314,288 @@ impl From<&WebTransportCertificateSettings> for Identity {
                     println!("🔐 SAN += SELF_SIGNED_SANS: {san}");
                     sans.extend(san.split(',').map(|s| s.to_string()));
                 }
-                //println!("🔐 Generating self-signed certificate with SANs: {sans:?}");
-                let identity = Identity::self_signed(sans).unwrap();
-                let digest = identity.certificate_chain().as_slice()[0].hash();
-                //println!("🔐 Certificate digest: {digest}");
-                identity
+                Identity::self_signed(sans)
+                    .map_err(|error| CertificateError::SelfSigned(error.to_string()))
+            }
+            WebTransportCertificateSettings::FromFile { cert, key } => {
+                // Checked up front so a typo'd path says which file, not just "io error"
+                for path in [cert, key] {
+                    if let Err(error) = std::fs::File::open(path) {
+                        return Err(match error.kind() {
+                            std::io::ErrorKind::NotFound => CertificateError::Missing(path.clone()),
+                            _ => CertificateError::Unreadable {
+                                path: path.clone(),
+                                reason: error.to_string(),
+                            },
+                        });
+                    }
+                }
+                info!("Reading certificate PEM files:\n * cert: {cert}\n * key: {key}");
+                load_pemfiles(cert, key)
+            }
+        }
+    }
+}
+
+// wtransport expects a tokio reactor, async_compat gives it one
+#[cfg(not(target_family = "wasm"))]
+fn load_pemfiles(cert: &str, key: &str) -> Result<Identity, CertificateError> {
+    block_on(Compat::new(Identity::load_pemfiles(cert, key)))
+        .map_err(|error| CertificateError::Invalid(error.to_string()))
+}
+
+#[cfg(target_family = "wasm")]
+fn load_pemfiles(_cert: &str, _key: &str) -> Result<Identity, CertificateError> {
+    Err(CertificateError::Invalid(
+        "PEM files can't be read in the browser".to_string(),
+    ))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CertificateError {
+    Missing(String),
+    Unreadable {
+        path: String,
+        reason: String,
+    },
+    /// The files were there but didn't hold a usable cert and key
+    Invalid(String),
+    SelfSigned(String),
+}
+
+impl std::fmt::Display for CertificateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CertificateError::Missing(path) => write!(f, "certificate file {path} doesn't exist"),
+            CertificateError::Unreadable { path, reason } => {
+                write!(f, "couldn't read certificate file {path}: {reason}")
+            }
+            CertificateError::Invalid(reason) => write!(f, "invalid certificate: {reason}"),
+            CertificateError::SelfSigned(reason) => {
+                write!(f, "couldn't generate a self-signed certificate: {reason}")
             }
-            WebTransportCertificateSettings::FromFile {
-                cert: cert_pem_path,
-                key: private_key_pem_path,
-            } => {
-                todo!(
-                    "This don't work in any of my tests... stolen from lightyear common cli example"
-                );
-
-                println!(
-                    "Reading certificate PEM files:\n * cert: {cert_pem_path}\n * key: {private_key_pem_path}",
-                );
-                // this is async because we need to load the certificate from io
-                // we need async_compat because wtransport expects a tokio reactor
-                let identity = IoTaskPool::get()
-                    .scope(|s| {
-                        s.spawn(Compat::new(async {
-                            Identity::load_pemfiles(cert_pem_path, private_key_pem_path)
-                                .await
-                                .unwrap()
-                        }));
-                    })
-                    .pop()
-                    .unwrap();
-                let digest = identity.certificate_chain().as_slice()[0].hash();
-                println!("🔐 Certificate digest: {digest}");
-                identity
+        }
+    }
+}
+
+impl std::error::Error for CertificateError {}
+
+/// Hash of the certificate the server is using, clients pass it to [`ConnectClient`](crate::client::ConnectClient) to pin it
+#[derive(Resource, Clone, Debug, Deref)]
+pub struct CertificateDigest(pub String);
+
+impl CertificateDigest {
+    pub fn of(identity: &Identity) -> Self {
+        Self(
+            identity.certificate_chain().as_slice()[0]
+                .hash()
+                .to_string(),
+        )
+    }
+}
+
+/// How often the pem files get checked for changes
+const CERTIFICATE_POLL: Duration = Duration::from_secs(5);
+
+/// Restarts the server with the new certificate when its pem files change on disk
+#[derive(Component)]
+pub struct CertificateWatch {
+    cert: String,
+    key: String,
+    modified: Option<std::time::SystemTime>,
+    timer: Timer,
+    /// Pem files being read off the main thread, with the modified time they were read at
+    loading: Option<(
+        Option<std::time::SystemTime>,
+        Task<Result<Identity, CertificateError>>,
+    )>,
+    /// Loaded and waiting for the server to finish stopping
+    restart_with: Option<Identity>,
+}
+
+impl CertificateWatch {
+    pub fn new(cert: String, key: String) -> Self {
+        let modified = last_modified(&cert, &key);
+        Self {
+            cert,
+            key,
+            modified,
+            timer: Timer::new(CERTIFICATE_POLL, TimerMode::Repeating),
+            loading: None,
+            restart_with: None,
+        }
+    }
+}
+
+fn last_modified(cert: &str, key: &str) -> Option<std::time::SystemTime> {
+    let modified = |path: &str| {
+        std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    };
+    modified(cert).max(modified(key))
+}
+
+pub(crate) fn reload_certificates(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut servers: Query<(Entity, &mut CertificateWatch)>,
+) {
+    for (server, mut watch) in &mut servers {
+        if let Some((modified, task)) = &mut watch.loading {
+            let Some(loaded) = block_on(future::poll_once(task)) else {
+                continue;
+            };
+            let modified = *modified;
+            watch.loading = None;
+            match loaded {
+                Ok(identity) => {
+                    // Only remembered once it loads, a half written file gets retried next poll
+                    watch.modified = modified;
+                    let digest = CertificateDigest::of(&identity);
+                    info!("🔐 Certificate changed on disk, new digest: {}", digest.0);
+                    commands.insert_resource(digest);
+                    // Lightyear has to see the server stopped before it'll start again
+                    watch.restart_with = Some(identity);
+                    commands.trigger(Stop { entity: server });
+                }
+                Err(error) => warn!("Keeping the old certificate: {error}"),
             }
+            continue;
         }
+        if !watch.timer.tick(time.delta()).just_finished() || watch.restart_with.is_some() {
+            continue;
+        }
+        let modified = last_modified(&watch.cert, &watch.key);
+        if modified == watch.modified {
+            continue;
+        }
+
+        let settings = WebTransportCertificateSettings::FromFile {
+            cert: watch.cert.clone(),
+            key: watch.key.clone(),
+        };
+        let task = IoTaskPool::get().spawn(async move { settings.load() });
+        watch.loading = Some((modified, task));
+    }
+}
+
+/// Second half of a certificate reload, starts the server again once it has actually stopped
+pub(crate) fn restart_with_new_certificate(
+    trigger: On<Add, Stopped>,
+    mut commands: Commands,
+    mut servers: Query<&mut CertificateWatch>,
+) {
+    let Ok(mut watch) = servers.get_mut(trigger.entity) else {
+        return;
+    };
+    if let Some(identity) = watch.restart_with.take() {
+        commands
+            .entity(trigger.entity)
+            .insert(WebTransportServerIo {
+                certificate: identity,
+            });
+        commands.trigger(Start {
+            entity: trigger.entity,
+        });
     }
 }
 
 /// Reads and parses the LIGHTYEAR_PRIVATE_KEY environment variable into a private key.
-pub fn parse_private_key_from_env() -> Option<[u8; PRIVATE_KEY_BYTES]> {
+pub fn parse_private_key_from_env() -> Result<Option<[u8; PRIVATE_KEY_BYTES]>, NetworkingError> {
     let Ok(key_str) = std::env::var("LIGHTYEAR_PRIVATE_KEY") else {
-        return None;
+        return Ok(None);
     };
-    let private_key: Vec<u8> = key_str
+    parse_private_key(&key_str).map(Some)
+}
+
+/// Parses a key written as 32 comma separated numbers, like `1,2,3,...`
+pub fn parse_private_key(key_str: &str) -> Result<[u8; PRIVATE_KEY_BYTES], NetworkingError> {
+    let private_key = key_str
         .chars()
         .filter(|c| c.is_ascii_digit() || *c == ',')
         .collect::<String>()
         .split(',')
         .map(|s| {
             s.parse::<u8>()
-                .expect("Failed to parse number in private key")
+                .map_err(|_| NetworkingError::BadPrivateKey(format!("{s:?} isn't a byte")))
         })
-        .collect();
+        .collect::<Result<Vec<u8>, _>>()?;
+
+    private_key.try_into().map_err(|key: Vec<u8>| {
+        NetworkingError::BadPrivateKey(format!(
+            "needs exactly {PRIVATE_KEY_BYTES} numbers, got {}",
+            key.len()
+        ))
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        client::LocalClient,
+        lifecycle::LifecyclePlugin,
+        player::{LocalPlayer, Player, PlayerPlugin},
+        shared::{FIXED_TIMESTEP_HZ, SHARED_SETTINGS},
+    };
+    use avian3d::prelude::PhysicsPlugins;
+    use bevy::{app::Plugins, asset::AssetPlugin, mesh::MeshPlugin, scene::ScenePlugin};
+    use lightyear::{connection::host::HostClient, prelude::client::ClientPlugins};
 
-    if private_key.len() != PRIVATE_KEY_BYTES {
-        panic!("Private key must contain exactly {PRIVATE_KEY_BYTES} numbers",);
+    /// Enough of [`NetworkingPlugin`](crate::NetworkingPlugin) to host and play without a window,
+    /// plus whatever the test needs since nothing can be added once it's finished
+    pub(crate) fn app<M>(plugins: impl Plugins<M>) -> App {
+        let tick_duration = Duration::from_secs_f64(1.0 / FIXED_TIMESTEP_HZ);
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            AssetPlugin::default(),
+            MeshPlugin,
+            ScenePlugin,
+            PhysicsPlugins::default(),
+            ClientPlugins { tick_duration },
+            ServerPlugins { tick_duration },
+        ));
+        app.add_plugins((LifecyclePlugin, PlayerPlugin, plugins));
+        app.add_observer(handle_spawning_host);
+        app.add_observer(replicate_to_client);
+        app.finish();
+        app.cleanup();
+        app
     }
 
-    let mut bytes = [0u8; PRIVATE_KEY_BYTES];
-    bytes.copy_from_slice(&private_key);
-    Some(bytes)
+    /// Hosts on any free port with the host client playing
+    pub(crate) fn host(app: &mut App) {
+        app.world_mut().trigger(Host {
+            transport: ServerTransports::WebSocket { local_port: 0 },
+            shared: SHARED_SETTINGS,
+            key_file: None,
+            token_service: false,
+            advertise: None,
+            ..default()
+        });
+        app.update();
+    }
+
+    #[test]
+    fn host_plays_too() {
+        let mut app = app(());
+        host(&mut app);
+
+        let world = app.world_mut();
+        let client = world
+            .query_filtered::<Entity, With<LocalClient>>()
+            .single(world)
+            .unwrap();
+        let client = world.entity(client);
+        assert!(client.contains::<HostClient>());
+        assert!(client.contains::<Connected>());
+        let player = world
+            .query_filtered::<&Player, With<LocalPlayer>>()
+            .single(world)
+            .unwrap();
+        assert_eq!(player.0, PeerId::Local(0));
+    }
 }
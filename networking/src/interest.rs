@@ -0,0 +1,359 @@
+// Area of interest, every voxel_terrain chunk column gets a lightyear room
+// Entities with AreaOfInterest sit in the room for the column they're in and clients are in every
+// room within `radius` columns of their player, so lightyear spawns and despawns things on the
+// client as either side moves. Only looks at links and positions, mock ClientOf links work too
+use crate::player::Player;
+use avian3d::prelude::*;
+use bevy::{platform::collections::*, prelude::*};
+use lightyear::prelude::{server::*, *};
+use voxel_terrain::prelude::Chunk;
+
+/// Bigger goes first when the send interval can't fit everything, whatever misses out builds up
+/// priority in lightyear's accumulator until it does
+pub(crate) const PLAYER_PRIORITY: f32 = 10.0;
+pub(crate) const PROP_PRIORITY: f32 = 1.0;
+
+pub struct InterestPlugin;
+
+impl Plugin for InterestPlugin {
+    fn build(&self, app: &mut App) {
+        // Lightyear doesn't add rooms itself
+        if !app.is_plugin_added::<RoomPlugin>() {
+            app.add_plugins(RoomPlugin);
+        }
+        app.init_resource::<InterestSettings>();
+        app.init_resource::<ChunkRooms>();
+        app.add_observer(track_interest);
+        app.add_observer(forget_interest);
+        app.add_observer(leave_room);
+        app.add_systems(PostUpdate, (move_between_rooms, follow_players).chain());
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct InterestSettings {
+    /// Columns out from the player's own that a client still gets, 0 is just the one it's in
+    pub radius: i32,
+}
+
+impl Default for InterestSettings {
+    fn default() -> Self {
+        Self { radius: 2 }
+    }
+}
+
+/// Only replicated to clients near it, anything replicated without this goes to everyone
+#[derive(Component, Clone, Copy, Debug, Default)]
+#[require(NetworkVisibility)]
+pub struct AreaOfInterest {
+    chunk: Option<IVec2>,
+}
+
+/// On each client link, which rooms it's in
+#[derive(Component, Clone, Debug, Default)]
+pub struct Interest {
+    center: Option<IVec2>,
+    rooms: HashSet<IVec2>,
+}
+
+impl Interest {
+    /// The column the client's player is in, if it has one
+    pub fn center(&self) -> Option<IVec2> {
+        self.center
+    }
+
+    pub fn sees(&self, chunk: IVec2) -> bool {
+        self.rooms.contains(&chunk)
+    }
+}
+
+/// Room for each column anything has been in, made as they're needed
+#[derive(Resource, Default)]
+pub struct ChunkRooms(HashMap<IVec2, Entity>);
+
+impl ChunkRooms {
+    pub fn get(&self, chunk: IVec2) -> Option<Entity> {
+        self.0.get(&chunk).copied()
+    }
+
+    fn get_or_spawn(&mut self, commands: &mut Commands, chunk: IVec2) -> Entity {
+        *self.0.entry(chunk).or_insert_with(|| {
+            commands
+                .spawn((Room::default(), Name::new(format!("Chunk room {chunk}"))))
+                .id()
+        })
+    }
+}
+
+/// Every column within `radius` of `center`, a square so it lines up with the rooms
+pub fn chunks_around(center: IVec2, radius: i32) -> impl Iterator<Item = IVec2> {
+    (-radius..=radius).flat_map(move |x| (-radius..=radius).map(move |y| center + IVec2::new(x, y)))
+}
+
+fn track_interest(trigger: On<Add, ClientOf>, mut commands: Commands) {
+    commands.entity(trigger.entity).insert(Interest::default());
+}
+
+fn forget_interest(
+    trigger: On<Remove, Interest>,
+    mut commands: Commands,
+    rooms: Res<ChunkRooms>,
+    clients: Query<&Interest>,
+) {
+    let Ok(interest) = clients.get(trigger.entity) else {
+        return;
+    };
+    for room in interest.rooms.iter().filter_map(|chunk| rooms.get(*chunk)) {
+        commands.trigger(RoomEvent {
+            room,
+            target: RoomTarget::RemoveSender(trigger.entity),
+        });
+    }
+}
+
+fn leave_room(
+    trigger: On<Remove, AreaOfInterest>,
+    mut commands: Commands,
+    rooms: Res<ChunkRooms>,
+    entities: Query<&AreaOfInterest>,
+) {
+    if let Ok(area) = entities.get(trigger.entity)
+        && let Some(room) = area.chunk.and_then(|chunk| rooms.get(chunk))
+    {
+        commands.trigger(RoomEvent {
+            room,
+            target: RoomTarget::RemoveEntity(trigger.entity),
+        });
+    }
+}
+
+/// Runs after physics has moved things for the frame
+fn move_between_rooms(
+    mut commands: Commands,
+    mut rooms: ResMut<ChunkRooms>,
+    mut entities: Query<(Entity, &Position, &mut AreaOfInterest), Changed<Position>>,
+) {
+    for (entity, position, mut area) in &mut entities {
+        let chunk = Chunk::containing(position.0).0;
+        if area.chunk == Some(chunk) {
+            continue;
+        }
+        if let Some(old) = area.chunk.and_then(|old| rooms.get(old)) {
+            commands.trigger(RoomEvent {
+                room: old,
+                target: RoomTarget::RemoveEntity(entity),
+            });
+        }
+        let room = rooms.get_or_spawn(&mut commands, chunk);
+        commands.trigger(RoomEvent {
+            room,
+            target: RoomTarget::AddEntity(entity),
+        });
+        area.chunk = Some(chunk);
+    }
+}
+
+/// Moves each client's rooms along with its player, only touching the ones at the edges
+fn follow_players(
+    mut commands: Commands,
+    settings: Res<InterestSettings>,
+    mut rooms: ResMut<ChunkRooms>,
+    players: Query<(&Player, &Position)>,
+    mut clients: Query<(Entity, &RemoteId, &mut Interest)>,
+) {
+    let centers: HashMap<PeerId, IVec2> = players
+        .iter()
+        .map(|(player, position)| (player.0, Chunk::containing(position.0).0))
+        .collect();
+    for (client, id, mut interest) in &mut clients {
+        let center = centers.get(&id.0).copied();
+        if center == interest.center && !settings.is_changed() {
+            continue;
+        }
+        let wanted: HashSet<IVec2> = center
+            .map(|center| chunks_around(center, settings.radius).collect())
+            .unwrap_or_default();
+        for chunk in interest.rooms.difference(&wanted) {
+            if let Some(room) = rooms.get(*chunk) {
+                commands.trigger(RoomEvent {
+                    room,
+                    target: RoomTarget::RemoveSender(client),
+                });
+            }
+        }
+        for chunk in wanted.difference(&interest.rooms) {
+            let room = rooms.get_or_spawn(&mut commands, *chunk);
+            commands.trigger(RoomEvent {
+                room,
+                target: RoomTarget::AddSender(client),
+            });
+        }
+        interest.center = center;
+        interest.rooms = wanted;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use voxel_terrain::prelude::CHUNK_SIZE;
+
+    /// Middle of a column, on the ground
+    fn in_column(chunk: IVec2) -> Position {
+        let position = (chunk * CHUNK_SIZE).as_vec2();
+        let position = Position(Vec3::new(position.x, 0.0, position.y));
+        assert_eq!(Chunk::containing(position.0).0, chunk);
+        position
+    }
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, InterestPlugin));
+        app.insert_resource(InterestSettings { radius: 1 });
+        app
+    }
+
+    /// A mock link, only what interest looks at
+    fn spawn_client(app: &mut App, id: u64) -> Entity {
+        app.world_mut()
+            .spawn((ClientOf, RemoteId(PeerId::Netcode(id))))
+            .id()
+    }
+
+    fn spawn_player(app: &mut App, id: u64, chunk: IVec2) -> Entity {
+        let position = in_column(chunk);
+        app.world_mut()
+            .spawn((Player(PeerId::Netcode(id)), position))
+            .id()
+    }
+
+    fn interest_of(app: &App, client: Entity) -> &Interest {
+        app.world().get::<Interest>(client).unwrap()
+    }
+
+    /// Whether the room for `chunk` has `client` in it, no room counts as no
+    fn in_room(app: &App, chunk: IVec2, client: Entity) -> bool {
+        app.world()
+            .resource::<ChunkRooms>()
+            .get(chunk)
+            .and_then(|room| app.world().get::<Room>(room))
+            .is_some_and(|room| room.clients.contains(&client))
+    }
+
+    #[test]
+    fn chunks_around_is_a_square() {
+        let center = IVec2::new(3, -2);
+        assert_eq!(chunks_around(center, 0).collect::<Vec<_>>(), vec![center]);
+
+        let chunks: HashSet<IVec2> = chunks_around(center, 2).collect();
+        assert_eq!(chunks.len(), 25);
+        assert!(chunks.contains(&(center + IVec2::new(-2, 2))));
+        assert!(chunks.contains(&(center + IVec2::new(2, -2))));
+        assert!(!chunks.contains(&(center + IVec2::new(3, 0))));
+    }
+
+    #[test]
+    fn clients_see_around_their_own_player() {
+        let mut app = app();
+        let near = spawn_client(&mut app, 1);
+        let far = spawn_client(&mut app, 2);
+        spawn_player(&mut app, 1, IVec2::ZERO);
+        spawn_player(&mut app, 2, IVec2::new(10, 0));
+        app.update();
+
+        let interest = interest_of(&app, near);
+        assert_eq!(interest.center(), Some(IVec2::ZERO));
+        assert!(interest.sees(IVec2::new(1, -1)));
+        assert!(!interest.sees(IVec2::new(2, 0)));
+        assert!(in_room(&app, IVec2::new(-1, 1), near));
+        assert!(!in_room(&app, IVec2::new(-1, 1), far));
+
+        let interest = interest_of(&app, far);
+        assert_eq!(interest.center(), Some(IVec2::new(10, 0)));
+        assert!(!interest.sees(IVec2::ZERO));
+        assert!(in_room(&app, IVec2::new(11, 1), far));
+    }
+
+    #[test]
+    fn moving_swaps_the_rooms_at_the_edge() {
+        let mut app = app();
+        let client = spawn_client(&mut app, 1);
+        let player = spawn_player(&mut app, 1, IVec2::ZERO);
+        app.update();
+
+        app.world_mut()
+            .entity_mut(player)
+            .insert(in_column(IVec2::new(1, 0)));
+        app.update();
+
+        let interest = interest_of(&app, client);
+        assert_eq!(interest.center(), Some(IVec2::new(1, 0)));
+        for y in -1..=1 {
+            // The column behind is dropped, the one ahead is picked up, the middle stays
+            assert!(!interest.sees(IVec2::new(-1, y)));
+            assert!(!in_room(&app, IVec2::new(-1, y), client));
+            assert!(interest.sees(IVec2::new(2, y)));
+            assert!(in_room(&app, IVec2::new(2, y), client));
+            assert!(in_room(&app, IVec2::new(0, y), client));
+        }
+    }
+
+    #[test]
+    fn entities_follow_their_position_between_rooms() {
+        let mut app = app();
+        let prop = app
+            .world_mut()
+            .spawn((AreaOfInterest::default(), in_column(IVec2::ZERO)))
+            .id();
+        app.update();
+
+        let rooms = app.world().resource::<ChunkRooms>();
+        let first = rooms.get(IVec2::ZERO).unwrap();
+        assert!(
+            app.world()
+                .get::<Room>(first)
+                .unwrap()
+                .entities
+                .contains(&prop)
+        );
+
+        app.world_mut()
+            .entity_mut(prop)
+            .insert(in_column(IVec2::new(0, 3)));
+        app.update();
+
+        let rooms = app.world().resource::<ChunkRooms>();
+        let second = rooms.get(IVec2::new(0, 3)).unwrap();
+        assert!(
+            !app.world()
+                .get::<Room>(first)
+                .unwrap()
+                .entities
+                .contains(&prop)
+        );
+        assert!(
+            app.world()
+                .get::<Room>(second)
+                .unwrap()
+                .entities
+                .contains(&prop)
+        );
+    }
+
+    #[test]
+    fn clients_without_a_player_leave_every_room() {
+        let mut app = app();
+        let client = spawn_client(&mut app, 1);
+        let player = spawn_player(&mut app, 1, IVec2::ZERO);
+        app.update();
+        assert!(in_room(&app, IVec2::ZERO, client));
+
+        app.world_mut().despawn(player);
+        app.update();
+
+        let interest = interest_of(&app, client);
+        assert_eq!(interest.center(), None);
+        assert!(!interest.sees(IVec2::ZERO));
+        assert!(!in_room(&app, IVec2::ZERO, client));
+    }
+}
@@ -2,9 +2,19 @@ use bevy::prelude::*;
 use lightyear::prelude::{client::ClientPlugins, server::ServerPlugins};
 use std::time::Duration;
 
+pub mod auth;
 pub mod client;
+pub mod discovery;
+pub mod error;
 pub mod host;
+pub mod interest;
+pub mod lifecycle;
+pub mod lobby;
+pub mod physics;
+pub mod player;
 pub mod shared;
+#[cfg(feature = "terrain")]
+pub mod terrain;
 
 pub struct NetworkingPlugin;
 
@@ -17,13 +27,47 @@ impl Plugin for NetworkingPlugin {
             ClientPlugins { tick_duration },
             ServerPlugins { tick_duration },
         ));
+        app.add_plugins((
+            discovery::DiscoveryPlugin,
+            interest::InterestPlugin,
+            lifecycle::LifecyclePlugin,
+            lobby::LobbyPlugin,
+            physics::PhysicsReplicationPlugin,
+            player::PlayerPlugin,
+        ));
+        #[cfg(feature = "terrain")]
+        app.add_plugins(terrain::TerrainSyncPlugin);
+        app.add_systems(
+            Update,
+            (host::reload_certificates, client::finish_pending_tokens),
+        );
+        #[cfg(not(target_family = "wasm"))]
+        app.add_systems(Update, auth::serve_tokens);
         app.add_observer(client::handle_connecting_client);
         app.add_observer(host::handle_spawning_host);
+        app.add_observer(host::restart_with_new_certificate);
+        app.add_observer(host::replicate_to_client);
     }
 }
 
 pub mod prelude {
     pub use crate::NetworkingPlugin;
-    pub use crate::client::{ConnectClient, LocalClient};
-    pub use crate::host::Host;
+    pub use crate::auth::{LocalIssuer, TokenIssuer};
+    pub use crate::client::{ClientAuth, ConnectClient, LocalClient};
+    pub use crate::discovery::{
+        DiscoverServers, DiscoveredServers, MasterServerRegistry, MockMasterServer,
+        RefreshServerList, StopDiscovery,
+    };
+    pub use crate::error::{ConnectFailed, HostFailed, NetworkingError};
+    pub use crate::host::{CertificateDigest, Host};
+    pub use crate::interest::{AreaOfInterest, InterestSettings};
+    pub use crate::lifecycle::{
+        ClientConnected, ClientDisconnected, DisconnectReason, LeaveServer, PeerJoined, PeerLeft,
+        ReconnectPolicy,
+    };
+    pub use crate::lobby::{Lobby, MatchStarted, SetReady, StartMatch, in_match};
+    pub use crate::physics::{Prop, PropShape, SpawnProp};
+    pub use crate::player::{LocalPlayer, Player, PlayerSpawn};
+    #[cfg(feature = "terrain")]
+    pub use crate::terrain::RequestTerrainEdit;
 }
@@ -0,0 +1,443 @@
+// High level connection events so gameplay code doesn't have to watch lightyear's components
+// Clients hear about each other through the host, they never talk directly
+use crate::client::{LocalClient, setup_client};
+use crate::error::NetworkingError;
+use bevy::prelude::*;
+use core::time::Duration;
+use lightyear::prelude::{server::*, *};
+use serde::{Deserialize, Serialize};
+
+pub struct LifecyclePlugin;
+
+impl Plugin for LifecyclePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_channel::<PeerChannel>(ChannelSettings {
+            mode: ChannelMode::OrderedReliable(ReliableSettings::default()),
+            ..default()
+        })
+        .add_direction(NetworkDirection::ServerToClient);
+        app.register_message::<PeerNotice>()
+            .add_direction(NetworkDirection::ServerToClient);
+
+        app.add_observer(client_connected);
+        app.add_observer(client_disconnected);
+        app.add_observer(peer_joined);
+        app.add_observer(peer_left);
+        app.add_observer(leave_server);
+        app.add_systems(Update, (receive_peer_notices, retry_connections));
+    }
+}
+
+/// Our client made it onto the server, fires on the host for its own client too
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ClientConnected {
+    pub entity: Entity,
+}
+
+/// Our client lost or gave up its connection
+#[derive(Event, Clone, Debug)]
+pub struct ClientDisconnected {
+    pub entity: Entity,
+    pub reason: DisconnectReason,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// We asked to with [`LeaveServer`]
+    Left,
+    /// Never got through, usually the server isn't there or it timed out
+    CouldNotConnect(Option<String>),
+    /// Was connected, then wasn't
+    Lost(Option<String>),
+}
+
+/// Someone else joined, on the host `entity` is their link
+#[derive(Event, Clone, Copy, Debug)]
+pub struct PeerJoined {
+    pub peer: PeerId,
+    pub entity: Option<Entity>,
+}
+
+#[derive(Event, Clone, Copy, Debug)]
+pub struct PeerLeft {
+    pub peer: PeerId,
+}
+
+/// Disconnect our client on purpose, it won't try to reconnect
+#[derive(Event, Clone, Copy, Debug, Default)]
+pub struct LeaveServer;
+
+pub struct PeerChannel;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum PeerNotice {
+    Joined(PeerId),
+    Left(PeerId),
+}
+
+/// Set on [`ConnectClient`](crate::client::ConnectClient) to retry dropped or failed connections
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Gives up after this many tries in a row, a successful connection resets it
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Each retry waits this much longer than the last
+    pub backoff: f32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            backoff: 2.0,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// How long to wait before `attempt`, counting from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let scale = self.backoff.powi(attempt.saturating_sub(1) as i32);
+        self.initial_delay.mul_f32(scale).min(self.max_delay)
+    }
+}
+
+/// Marks the client as trying to connect, so its first [`Disconnected`] isn't mistaken for a failure
+#[derive(Component, Default)]
+pub(crate) struct Dialing;
+
+/// Had a connection at some point
+#[derive(Component, Default)]
+pub(crate) struct Online;
+
+#[derive(Component, Default)]
+pub(crate) struct Leaving;
+
+/// Keeps what the client was started with so it can be started again
+#[derive(Component)]
+pub(crate) struct Reconnect {
+    pub(crate) settings: crate::client::ConnectClient,
+    pub(crate) policy: ReconnectPolicy,
+    attempt: u32,
+    retry: Option<Timer>,
+}
+
+impl Reconnect {
+    pub(crate) fn new(settings: crate::client::ConnectClient, policy: ReconnectPolicy) -> Self {
+        Self {
+            settings,
+            policy,
+            attempt: 0,
+            retry: None,
+        }
+    }
+
+    /// False once it's out of attempts
+    pub(crate) fn schedule(&mut self) -> bool {
+        if self.attempt >= self.policy.max_attempts {
+            warn!("Giving up on reconnecting after {} attempts", self.attempt);
+            return false;
+        }
+        self.attempt += 1;
+        let delay = self.policy.delay(self.attempt);
+        info!(
+            "Reconnecting in {:.1}s, attempt {}/{}",
+            delay.as_secs_f32(),
+            self.attempt,
+            self.policy.max_attempts
+        );
+        self.retry = Some(Timer::new(delay, TimerMode::Once));
+        true
+    }
+}
+
+/// Kicks off the connection, use this rather than triggering [`Connect`] directly
+pub(crate) fn dial(cmds: &mut Commands, client: Entity) {
+    cmds.entity(client).insert(Dialing);
+    cmds.trigger(Connect { entity: client });
+}
+
+fn client_connected(
+    trigger: On<Add, Connected>,
+    mut commands: Commands,
+    mut clients: Query<Option<&mut Reconnect>, (With<LocalClient>, With<Client>)>,
+) {
+    let Ok(reconnect) = clients.get_mut(trigger.entity) else {
+        return;
+    };
+    if let Some(mut reconnect) = reconnect {
+        reconnect.attempt = 0;
+    }
+    commands
+        .entity(trigger.entity)
+        .remove::<Dialing>()
+        .insert(Online);
+    commands.trigger(ClientConnected {
+        entity: trigger.entity,
+    });
+}
+
+fn client_disconnected(
+    trigger: On<Add, Disconnected>,
+    mut commands: Commands,
+    mut clients: Query<
+        (
+            &Disconnected,
+            Has<Dialing>,
+            Has<Online>,
+            Has<Leaving>,
+            Option<&mut Reconnect>,
+        ),
+        (With<LocalClient>, With<Client>),
+    >,
+) {
+    let Ok((disconnected, dialing, online, leaving, reconnect)) = clients.get_mut(trigger.entity)
+    else {
+        return;
+    };
+    let reason = if leaving {
+        DisconnectReason::Left
+    } else if online {
+        DisconnectReason::Lost(disconnected.reason.clone())
+    } else if dialing {
+        DisconnectReason::CouldNotConnect(disconnected.reason.clone())
+    } else {
+        // Clients start out disconnected, that's not news
+        return;
+    };
+    info!("Disconnected: {reason:?}");
+    commands
+        .entity(trigger.entity)
+        .remove::<(Dialing, Online, Leaving)>();
+
+    if let Some(mut reconnect) = reconnect.filter(|_| reason != DisconnectReason::Left) {
+        reconnect.schedule();
+    }
+    commands.trigger(ClientDisconnected {
+        entity: trigger.entity,
+        reason,
+    });
+}
+
+fn retry_connections(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut clients: Query<(Entity, &mut Reconnect)>,
+) {
+    for (client, mut reconnect) in &mut clients {
+        let Some(retry) = reconnect.retry.as_mut() else {
+            continue;
+        };
+        if !retry.tick(time.delta()).just_finished() {
+            continue;
+        }
+        reconnect.retry = None;
+        if let Err(reason) = setup_client(&mut commands, client, &reconnect.settings) {
+            retry_failed(&mut commands, client, &mut reconnect, reason);
+        }
+    }
+}
+
+/// A retry that didn't even get to dialing, like the token service being down with the server
+pub(crate) fn retry_failed(
+    cmds: &mut Commands,
+    client: Entity,
+    reconnect: &mut Reconnect,
+    reason: NetworkingError,
+) {
+    warn!("Reconnect attempt failed: {reason}");
+    if !reconnect.schedule() {
+        crate::client::connect_failed(cmds, client, reason);
+    }
+}
+
+fn leave_server(
+    _trigger: On<LeaveServer>,
+    mut commands: Commands,
+    clients: Query<Entity, (With<LocalClient>, With<Client>, Without<Disconnected>)>,
+) {
+    for client in &clients {
+        commands.entity(client).insert(Leaving);
+        commands.trigger(Disconnect { entity: client });
+    }
+}
+
+fn peer_joined(
+    trigger: On<Add, Connected>,
+    mut commands: Commands,
+    server: Option<Single<&Server, With<Started>>>,
+    mut broadcast: ServerMultiMessageSender,
+    peers: Query<&RemoteId, (With<ClientOf>, With<Connected>)>,
+) {
+    let Ok(id) = peers.get(trigger.entity) else {
+        return;
+    };
+    let peer = id.0;
+    commands.trigger(PeerJoined {
+        peer,
+        entity: Some(trigger.entity),
+    });
+    let Some(server) = server else {
+        return;
+    };
+    let mut send = |notice: PeerNotice, target: NetworkTarget| {
+        if let Err(error) = broadcast.send::<_, PeerChannel>(&notice, &server, &target) {
+            warn!("Couldn't send {notice:?}: {error:?}");
+        }
+    };
+    send(
+        PeerNotice::Joined(peer),
+        NetworkTarget::AllExceptSingle(peer),
+    );
+    // Catch the newcomer up on who's already here
+    for other in &peers {
+        if other.0 != peer {
+            send(PeerNotice::Joined(other.0), NetworkTarget::Single(peer));
+        }
+    }
+}
+
+/// Removing [`Connected`] covers both disconnecting and the link getting despawned
+fn peer_left(
+    trigger: On<Remove, Connected>,
+    mut commands: Commands,
+    server: Option<Single<&Server, With<Started>>>,
+    mut broadcast: ServerMultiMessageSender,
+    peers: Query<&RemoteId, With<ClientOf>>,
+) {
+    let Ok(id) = peers.get(trigger.entity) else {
+        return;
+    };
+    let peer = id.0;
+    commands.trigger(PeerLeft { peer });
+    let Some(server) = server else {
+        return;
+    };
+    if let Err(error) = broadcast.send::<_, PeerChannel>(
+        &PeerNotice::Left(peer),
+        &server,
+        &NetworkTarget::AllExceptSingle(peer),
+    ) {
+        warn!("Couldn't send that {peer:?} left: {error:?}");
+    }
+}
+
+fn receive_peer_notices(
+    mut commands: Commands,
+    server: Option<Single<(), (With<Server>, With<Started>)>>,
+    mut receivers: Query<&mut MessageReceiver<PeerNotice>, With<Client>>,
+) {
+    let is_host = server.is_some();
+    for mut receiver in &mut receivers {
+        for notice in receiver.receive() {
+            // The host already fired these from the links themselves
+            if is_host {
+                continue;
+            }
+            match notice {
+                PeerNotice::Joined(peer) => commands.trigger(PeerJoined { peer, entity: None }),
+                PeerNotice::Left(peer) => commands.trigger(PeerLeft { peer }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ConnectClient;
+
+    #[test]
+    fn delays_back_off_up_to_the_cap() {
+        let policy = ReconnectPolicy::default();
+        let delays: Vec<_> = (1..=7)
+            .map(|attempt| policy.delay(attempt).as_secs())
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 30, 30]);
+
+        let steady = ReconnectPolicy {
+            backoff: 1.0,
+            ..default()
+        };
+        assert_eq!(steady.delay(10), steady.initial_delay);
+    }
+
+    #[test]
+    fn reconnecting_gives_up_eventually() {
+        let policy = ReconnectPolicy {
+            max_attempts: 3,
+            ..default()
+        };
+        let mut reconnect = Reconnect::new(ConnectClient::default(), policy);
+        for attempt in 1..=3 {
+            assert!(reconnect.schedule());
+            let retry = reconnect.retry.as_ref().unwrap();
+            assert_eq!(retry.duration(), policy.delay(attempt));
+        }
+        assert!(!reconnect.schedule());
+    }
+
+    #[derive(Resource, Default)]
+    struct Reasons(Vec<DisconnectReason>);
+
+    fn client_app() -> (App, Entity) {
+        let mut app = crate::host::tests::app(());
+        app.init_resource::<Reasons>();
+        app.add_observer(
+            |trigger: On<ClientDisconnected>, mut reasons: ResMut<Reasons>| {
+                reasons.0.push(trigger.event().reason.clone());
+            },
+        );
+        let client = app
+            .world_mut()
+            .spawn((LocalClient, Client::default(), RemoteId(PeerId::Server)))
+            .id();
+        (app, client)
+    }
+
+    fn disconnect(app: &mut App, client: Entity, reason: &str) -> Option<DisconnectReason> {
+        app.world_mut().entity_mut(client).insert(Disconnected {
+            reason: Some(reason.into()),
+        });
+        app.world_mut().resource_mut::<Reasons>().0.pop()
+    }
+
+    #[test]
+    fn disconnects_say_why() {
+        let (mut app, client) = client_app();
+        // Starting out disconnected isn't news
+        assert!(app.world().resource::<Reasons>().0.is_empty());
+
+        app.world_mut()
+            .entity_mut(client)
+            .insert((Dialing, Connecting));
+        assert_eq!(
+            disconnect(&mut app, client, "timed out"),
+            Some(DisconnectReason::CouldNotConnect(Some("timed out".into())))
+        );
+
+        app.world_mut()
+            .entity_mut(client)
+            .insert((Dialing, Connected));
+        assert!(app.world().entity(client).contains::<Online>());
+        assert_eq!(
+            disconnect(&mut app, client, "kicked"),
+            Some(DisconnectReason::Lost(Some("kicked".into())))
+        );
+
+        app.world_mut().entity_mut(client).insert(Connected);
+        app.world_mut().entity_mut(client).insert(Leaving);
+        assert_eq!(
+            disconnect(&mut app, client, "bye"),
+            Some(DisconnectReason::Left)
+        );
+        // Nothing left over to confuse the next connection
+        let client = app.world().entity(client);
+        assert!(!client.contains::<Dialing>());
+        assert!(!client.contains::<Online>());
+        assert!(!client.contains::<Leaving>());
+    }
+}
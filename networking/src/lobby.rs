@@ -0,0 +1,256 @@
+// Everyone waits here until the host starts the match
+// Clients flag themselves ready, the host sees who's ready and kicks things off
+use bevy::prelude::*;
+use lightyear::connection::host::HostClient;
+use lightyear::prelude::{server::*, *};
+use serde::{Deserialize, Serialize};
+
+pub struct LobbyPlugin;
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_channel::<LobbyChannel>(ChannelSettings {
+            mode: ChannelMode::OrderedReliable(ReliableSettings::default()),
+            ..default()
+        })
+        .add_direction(NetworkDirection::Bidirectional);
+        app.register_message::<ReadyUp>()
+            .add_direction(NetworkDirection::ClientToServer);
+        app.register_message::<LobbyUpdate>()
+            .add_direction(NetworkDirection::ServerToClient);
+
+        app.init_resource::<Lobby>();
+        app.add_observer(join_lobby);
+        app.add_observer(set_ready);
+        app.add_observer(start_match);
+        app.add_systems(
+            Update,
+            (receive_ready, publish_lobby, receive_lobby).chain(),
+        );
+    }
+}
+
+pub struct LobbyChannel;
+
+/// Host side, one on each connected client
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Deref, DerefMut)]
+pub struct Ready(pub bool);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LobbyPlayer {
+    pub id: PeerId,
+    pub ready: bool,
+}
+
+/// Who's waiting and whether the match has started, the host's copy is sent to every client
+#[derive(Resource, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lobby {
+    /// Remote players only, the host's own client never shows up here and is always ready
+    pub players: Vec<LobbyPlayer>,
+    pub started: bool,
+}
+
+impl Lobby {
+    pub fn all_ready(&self) -> bool {
+        self.players.iter().all(|player| player.ready)
+    }
+}
+
+/// Run condition for gameplay systems that should wait for the match
+pub fn in_match(lobby: Res<Lobby>) -> bool {
+    lobby.started
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReadyUp(pub bool);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LobbyUpdate(pub Lobby);
+
+/// Tell the host whether we're ready
+#[derive(Event, Clone, Copy, Debug)]
+pub struct SetReady(pub bool);
+
+/// Host only, `force` starts even if someone isn't ready
+#[derive(Event, Clone, Copy, Debug, Default)]
+pub struct StartMatch {
+    pub force: bool,
+}
+
+/// Fired on every peer once the host starts the match
+#[derive(Event, Clone, Copy, Debug)]
+pub struct MatchStarted;
+
+// Lightyear gives the host client ClientOf and HostClient along with Connected
+fn join_lobby(
+    trigger: On<Add, Connected>,
+    mut commands: Commands,
+    clients: Query<(), (With<ClientOf>, Without<HostClient>)>,
+) {
+    if clients.contains(trigger.entity) {
+        commands.entity(trigger.entity).insert(Ready::default());
+    }
+}
+
+fn set_ready(
+    trigger: On<SetReady>,
+    server: Option<Single<(), (With<Server>, With<Started>)>>,
+    mut clients: Query<&mut MessageSender<ReadyUp>, (With<Client>, With<Connected>)>,
+) {
+    if server.is_some() {
+        return;
+    }
+    for mut sender in &mut clients {
+        sender.send::<LobbyChannel>(ReadyUp(trigger.event().0));
+    }
+}
+
+fn receive_ready(mut clients: Query<(&mut Ready, &mut MessageReceiver<ReadyUp>), With<ClientOf>>) {
+    for (mut ready, mut receiver) in &mut clients {
+        for ReadyUp(flag) in receiver.receive() {
+            ready.set_if_neq(Ready(flag));
+        }
+    }
+}
+
+/// Rebuilt every frame on the host, only sent out when something changed
+fn publish_lobby(
+    mut lobby: ResMut<Lobby>,
+    server: Option<Single<&Server, With<Started>>>,
+    mut broadcast: ServerMultiMessageSender,
+    clients: Query<(&RemoteId, &Ready), (With<ClientOf>, Without<HostClient>)>,
+) {
+    let Some(server) = server else {
+        return;
+    };
+    let players: Vec<_> = clients
+        .iter()
+        .map(|(id, ready)| LobbyPlayer {
+            id: id.0,
+            ready: ready.0,
+        })
+        .collect();
+    // Query order can shift around, so compare as sets
+    if players.len() == lobby.players.len()
+        && players.iter().all(|player| lobby.players.contains(player))
+    {
+        return;
+    }
+    lobby.players = players;
+    send_lobby(&mut broadcast, &server, &lobby);
+}
+
+fn send_lobby(broadcast: &mut ServerMultiMessageSender, server: &Server, lobby: &Lobby) {
+    if let Err(error) =
+        broadcast.send::<_, LobbyChannel>(&LobbyUpdate(lobby.clone()), server, &NetworkTarget::All)
+    {
+        warn!("Couldn't send the lobby: {error:?}");
+    }
+}
+
+fn start_match(
+    trigger: On<StartMatch>,
+    mut commands: Commands,
+    mut lobby: ResMut<Lobby>,
+    server: Option<Single<&Server, With<Started>>>,
+    mut broadcast: ServerMultiMessageSender,
+) {
+    let Some(server) = server else {
+        warn!("Only the host can start the match");
+        return;
+    };
+    if lobby.started {
+        return;
+    }
+    if !trigger.event().force && !lobby.all_ready() {
+        info!("Not everyone is ready yet");
+        return;
+    }
+    lobby.started = true;
+    send_lobby(&mut broadcast, &server, &lobby);
+    info!("Match started with {} remote players", lobby.players.len());
+    commands.trigger(MatchStarted);
+}
+
+fn receive_lobby(
+    mut commands: Commands,
+    mut lobby: ResMut<Lobby>,
+    server: Option<Single<(), (With<Server>, With<Started>)>>,
+    mut receivers: Query<&mut MessageReceiver<LobbyUpdate>, With<Client>>,
+) {
+    let is_host = server.is_some();
+    for mut receiver in &mut receivers {
+        for LobbyUpdate(update) in receiver.receive() {
+            // The host client gets these too, the host's copy is already right
+            if is_host {
+                continue;
+            }
+            // Late joiners find out straight away
+            let starting = update.started && !lobby.started;
+            *lobby = update;
+            if starting {
+                commands.trigger(MatchStarted);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::tests::{app, host};
+
+    fn hosting() -> App {
+        let mut app = app(LobbyPlugin);
+        host(&mut app);
+        app
+    }
+
+    /// A remote client as far as the lobby cares
+    fn join(app: &mut App, id: u64) -> Entity {
+        let client = app
+            .world_mut()
+            .spawn((ClientOf, RemoteId(PeerId::Netcode(id)), Connected))
+            .id();
+        app.update();
+        client
+    }
+
+    fn started(app: &mut App) -> bool {
+        app.world_mut().trigger(StartMatch::default());
+        app.world().resource::<Lobby>().started
+    }
+
+    #[test]
+    fn host_alone_can_start() {
+        let mut app = hosting();
+        assert!(app.world().resource::<Lobby>().players.is_empty());
+        assert!(started(&mut app));
+    }
+
+    #[test]
+    fn waits_for_remote_players() {
+        let mut app = hosting();
+        let client = join(&mut app, 1);
+        assert_eq!(
+            app.world().resource::<Lobby>().players,
+            vec![LobbyPlayer {
+                id: PeerId::Netcode(1),
+                ready: false
+            }]
+        );
+        assert!(!started(&mut app));
+
+        app.world_mut().entity_mut(client).insert(Ready(true));
+        app.update();
+        assert!(started(&mut app));
+    }
+
+    #[test]
+    fn forcing_skips_the_wait() {
+        let mut app = hosting();
+        join(&mut app, 1);
+        app.world_mut().trigger(StartMatch { force: true });
+        assert!(app.world().resource::<Lobby>().started);
+    }
+}
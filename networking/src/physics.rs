@@ -0,0 +1,433 @@
+// Dynamic bodies the host simulates and every client predicts, so shoving a prop reacts straight away
+// When the host disagrees the client rolls back, small differences are let slide and bigger ones
+// are blended out over a few ticks instead of snapping
+// Nothing here renders anything, it runs the same on a headless server
+use crate::interest::{AreaOfInterest, PROP_PRIORITY};
+use avian3d::{physics_transform::position_to_transform, prelude::*};
+use bevy::prelude::*;
+use lightyear::{
+    avian3d::prelude::*,
+    frame_interpolation::prelude::*,
+    prelude::{server::*, *},
+};
+use serde::{Deserialize, Serialize};
+
+/// Anything closer than this to the host's answer isn't worth a rollback
+const POSITION_TOLERANCE: f32 = 0.01;
+const ROTATION_TOLERANCE: f32 = 0.01;
+const VELOCITY_TOLERANCE: f32 = 0.05;
+
+pub struct PhysicsReplicationPlugin;
+
+impl Plugin for PhysicsReplicationPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_component::<Position>()
+            .add_prediction()
+            .add_should_rollback(position_should_rollback)
+            .add_linear_correction_fn()
+            .add_linear_interpolation();
+        app.register_component::<Rotation>()
+            .add_prediction()
+            .add_should_rollback(rotation_should_rollback)
+            .add_linear_correction_fn()
+            .add_linear_interpolation();
+        app.register_component::<LinearVelocity>()
+            .add_prediction()
+            .add_should_rollback(linear_velocity_should_rollback);
+        app.register_component::<AngularVelocity>()
+            .add_prediction()
+            .add_should_rollback(angular_velocity_should_rollback);
+        // The shape never changes, it goes straight onto the predicted copy
+        app.register_component::<Prop>();
+
+        // Corrections are blended on top of the frame interpolated position, so that's what smooths
+        // predicted props instead of avian's interpolation
+        app.add_plugins((
+            FrameInterpolationPlugin::<Position>::default(),
+            FrameInterpolationPlugin::<Rotation>::default(),
+            // Just the system ordering, avian still syncs transforms itself
+            LightyearAvianPlugin {
+                update_syncs_manually: true,
+                ..default()
+            },
+        ));
+        // Avian only writes transforms after a physics step, the blended position has to get there
+        // every frame
+        app.add_systems(
+            PostUpdate,
+            position_to_transform.in_set(PhysicsSystems::Writeback),
+        );
+
+        app.add_observer(spawn_prop);
+        app.add_observer(setup_replicated_prop);
+    }
+}
+
+/// Collider for a [`Prop`], colliders themselves don't go over the wire
+#[derive(Clone, Copy, Debug, PartialEq, Reflect, Serialize, Deserialize)]
+pub enum PropShape {
+    Cuboid(Vec3),
+    Sphere(f32),
+}
+
+impl PropShape {
+    pub fn collider(self) -> Collider {
+        match self {
+            PropShape::Cuboid(size) => Collider::cuboid(size.x, size.y, size.z),
+            PropShape::Sphere(radius) => Collider::sphere(radius),
+        }
+    }
+}
+
+/// A dynamic body the host owns, add visuals to this
+#[derive(Component, Clone, Copy, Debug, PartialEq, Deref, Serialize, Deserialize)]
+pub struct Prop(pub PropShape);
+
+/// Host only, drops a replicated prop into the world
+#[derive(Event, Clone, Copy, Debug)]
+pub struct SpawnProp {
+    pub shape: PropShape,
+    pub position: Vec3,
+}
+
+fn spawn_prop(
+    trigger: On<SpawnProp>,
+    mut commands: Commands,
+    server: Option<Single<(), (With<Server>, With<Started>)>>,
+) {
+    if server.is_none() {
+        warn!("Only the host can spawn props");
+        return;
+    }
+    let SpawnProp { shape, position } = *trigger.event();
+    commands.spawn((
+        Name::new("Prop"),
+        Prop(shape),
+        RigidBody::Dynamic,
+        shape.collider(),
+        Transform::from_translation(position),
+        Replicate::to_clients(NetworkTarget::All),
+        AreaOfInterest::default(),
+        ReplicationGroup::new_from_entity().set_priority(PROP_PRIORITY),
+        // Anyone might bump into it, so everyone predicts it
+        PredictionTarget::to_clients(NetworkTarget::All),
+    ));
+}
+
+/// Same body as the host's, frame interpolated so it doesn't step at the tick rate. Should a prop
+/// ever only be interpolated it's kinematic, but still solid. The host's own has Replicate and is
+/// left alone
+fn setup_replicated_prop(
+    trigger: On<Add, Prop>,
+    mut commands: Commands,
+    props: Query<(&Prop, Has<Predicted>, Has<Interpolated>), Without<Replicate>>,
+) {
+    let Ok((prop, predicted, interpolated)) = props.get(trigger.entity) else {
+        return;
+    };
+    if predicted {
+        commands.entity(trigger.entity).insert((
+            RigidBody::Dynamic,
+            prop.collider(),
+            FrameInterpolate::<Position>::default(),
+            FrameInterpolate::<Rotation>::default(),
+        ));
+    } else if interpolated {
+        commands
+            .entity(trigger.entity)
+            .insert((RigidBody::Kinematic, prop.collider()));
+    }
+}
+
+fn position_should_rollback(this: &Position, that: &Position) -> bool {
+    this.0.distance(that.0) >= POSITION_TOLERANCE
+}
+
+fn rotation_should_rollback(this: &Rotation, that: &Rotation) -> bool {
+    this.0.angle_between(that.0) >= ROTATION_TOLERANCE
+}
+
+fn linear_velocity_should_rollback(this: &LinearVelocity, that: &LinearVelocity) -> bool {
+    this.0.distance(that.0) >= VELOCITY_TOLERANCE
+}
+
+fn angular_velocity_should_rollback(this: &AngularVelocity, that: &AngularVelocity) -> bool {
+    this.0.distance(that.0) >= VELOCITY_TOLERANCE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interest::InterestPlugin, player::Player, shared::FIXED_TIMESTEP_HZ};
+    use bevy::{
+        asset::AssetPlugin, mesh::MeshPlugin, scene::ScenePlugin, time::TimeUpdateStrategy,
+    };
+    use core::{
+        net::{Ipv4Addr, SocketAddr},
+        time::Duration,
+    };
+    use lightyear::{
+        link::SendPayload,
+        prelude::client::{ClientPlugins, InputTimeline, RawClient},
+    };
+    use std::collections::VecDeque;
+
+    const CLIENT_ADDR: SocketAddr = SocketAddr::new(core::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 1);
+
+    /// Frames a packet spends getting across, without any the client is never far enough ahead to
+    /// have something to blend from
+    const LATENCY_FRAMES: usize = 6;
+
+    /// A host and one client wired straight together, payloads are handed across between frames
+    struct Pair {
+        server: App,
+        client: App,
+        link_of: Entity,
+        link: Entity,
+        /// To the client and to the server, oldest frame first
+        in_flight: VecDeque<(Vec<SendPayload>, Vec<SendPayload>)>,
+    }
+
+    impl Pair {
+        fn new() -> Self {
+            let mut server = app();
+            let host = server.world_mut().spawn((RawServer, Linked)).id();
+            let link_of = server
+                .world_mut()
+                .spawn((
+                    LinkOf { server: host },
+                    Link::new(None),
+                    PeerAddr(CLIENT_ADDR),
+                    // Every tick so corrections don't wait on the send interval
+                    ReplicationSender::new(Duration::ZERO, SendUpdatesMode::SinceLastAck, false),
+                    PingManager::new(PingConfig {
+                        ping_interval: Duration::ZERO,
+                    }),
+                    Linked,
+                ))
+                .id();
+            let mut client = app();
+            let link = client
+                .world_mut()
+                .spawn((
+                    RawClient,
+                    Link::new(None),
+                    LocalAddr(CLIENT_ADDR),
+                    ReplicationReceiver::default(),
+                    PredictionManager::default(),
+                    PingManager::new(PingConfig {
+                        ping_interval: Duration::ZERO,
+                    }),
+                    Linked,
+                ))
+                .id();
+            let mut pair = Self {
+                server,
+                client,
+                link_of,
+                link,
+                in_flight: VecDeque::new(),
+            };
+            pair.step_until(|pair| {
+                pair.client
+                    .world()
+                    .entity(pair.link)
+                    .contains::<IsSynced<InputTimeline>>()
+            });
+            pair
+        }
+
+        fn step(&mut self) {
+            self.server.update();
+            self.client.update();
+            let to_client = self.link_mut(true).send.drain().collect();
+            let to_server = self.link_mut(false).send.drain().collect();
+            self.in_flight.push_back((to_client, to_server));
+            if self.in_flight.len() <= LATENCY_FRAMES {
+                return;
+            }
+            let (to_client, to_server) = self.in_flight.pop_front().unwrap();
+            for payload in to_client {
+                self.link_mut(false).recv.push_raw(payload);
+            }
+            for payload in to_server {
+                self.link_mut(true).recv.push_raw(payload);
+            }
+        }
+
+        fn step_until(&mut self, done: impl Fn(&Self) -> bool) {
+            for _ in 0..500 {
+                if done(self) {
+                    return;
+                }
+                self.step();
+            }
+            panic!("Gave up waiting");
+        }
+
+        fn link_mut(&mut self, server: bool) -> Mut<'_, Link> {
+            if server {
+                self.server.world_mut().get_mut(self.link_of).unwrap()
+            } else {
+                self.client.world_mut().get_mut(self.link).unwrap()
+            }
+        }
+
+        fn rollbacks(&self) -> u32 {
+            self.client
+                .world()
+                .resource::<PredictionMetrics>()
+                .rollbacks
+        }
+
+        /// The host's prop and the client's predicted copy of it
+        fn spawn_prop(&mut self, position: Vec3) -> (Entity, Entity) {
+            // Interest only sends it to clients with a player nearby
+            self.server
+                .world_mut()
+                .spawn((Player(PeerId::Raw(CLIENT_ADDR)), Position(position)));
+            self.server.world_mut().trigger(SpawnProp {
+                shape: PropShape::Sphere(0.5),
+                position,
+            });
+            self.step_until(|pair| pair.predicted(&pair.client).is_some());
+            let host = self.predicted(&self.server).unwrap();
+            let predicted = self.predicted(&self.client).unwrap();
+            (host, predicted)
+        }
+
+        fn predicted(&self, app: &App) -> Option<Entity> {
+            let world = app.world();
+            let mut props = world.try_query_filtered::<Entity, With<Prop>>()?;
+            let prop = props.iter(world).next()?;
+            let entity = world.entity(prop);
+            (entity.contains::<Replicate>() || entity.contains::<Predicted>()).then_some(prop)
+        }
+
+        fn position(&self, app: &App, entity: Entity) -> Vec3 {
+            app.world().get::<Position>(entity).unwrap().0
+        }
+    }
+
+    fn app() -> App {
+        let tick_duration = Duration::from_secs_f64(1.0 / FIXED_TIMESTEP_HZ);
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            AssetPlugin::default(),
+            MeshPlugin,
+            ScenePlugin,
+            PhysicsPlugins::default(),
+            ClientPlugins { tick_duration },
+            ServerPlugins { tick_duration },
+        ));
+        app.add_plugins((InterestPlugin, PhysicsReplicationPlugin));
+        // Nothing to land on, props stay where they're put
+        app.insert_resource(Gravity(Vec3::ZERO));
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(tick_duration));
+        // What run would do before the first frame
+        app.finish();
+        app.cleanup();
+        app
+    }
+
+    #[test]
+    fn small_differences_are_let_slide() {
+        let here = Position(Vec3::ZERO);
+        assert!(!position_should_rollback(&here, &Position(Vec3::X * 0.005)));
+        assert!(position_should_rollback(&here, &Position(Vec3::X * 0.02)));
+
+        let turned = |angle| Rotation(Quat::from_rotation_y(angle));
+        assert!(!rotation_should_rollback(&turned(0.0), &turned(0.005)));
+        assert!(rotation_should_rollback(&turned(0.0), &turned(0.02)));
+
+        let still = LinearVelocity(Vec3::ZERO);
+        assert!(!linear_velocity_should_rollback(
+            &still,
+            &LinearVelocity(Vec3::Y * 0.04)
+        ));
+        assert!(linear_velocity_should_rollback(
+            &still,
+            &LinearVelocity(Vec3::Y * 0.1)
+        ));
+        let still = AngularVelocity(Vec3::ZERO);
+        assert!(!angular_velocity_should_rollback(
+            &still,
+            &AngularVelocity(Vec3::Y * 0.04)
+        ));
+        assert!(angular_velocity_should_rollback(
+            &still,
+            &AngularVelocity(Vec3::Y * 0.1)
+        ));
+    }
+
+    #[test]
+    fn predicted_props_are_solid_and_smoothed() {
+        let mut pair = Pair::new();
+        let (_, predicted) = pair.spawn_prop(Vec3::new(1.0, 2.0, 3.0));
+
+        let entity = pair.client.world().entity(predicted);
+        assert_eq!(entity.get::<RigidBody>(), Some(&RigidBody::Dynamic));
+        assert!(entity.contains::<Collider>());
+        assert!(entity.contains::<FrameInterpolate<Position>>());
+        assert!(entity.contains::<FrameInterpolate<Rotation>>());
+        assert!(
+            pair.position(&pair.client, predicted)
+                .distance(Vec3::new(1.0, 2.0, 3.0))
+                < POSITION_TOLERANCE
+        );
+    }
+
+    #[test]
+    fn nudges_under_the_tolerance_dont_roll_back() {
+        let mut pair = Pair::new();
+        let (host, predicted) = pair.spawn_prop(Vec3::ZERO);
+        let rollbacks = pair.rollbacks();
+
+        pair.server.world_mut().get_mut::<Position>(host).unwrap().0 = Vec3::X * 0.005;
+        for _ in 0..30 {
+            pair.step();
+        }
+
+        assert_eq!(pair.rollbacks(), rollbacks);
+        // The client keeps its own answer rather than chasing the host's
+        assert_eq!(pair.position(&pair.client, predicted), Vec3::ZERO);
+    }
+
+    #[test]
+    fn big_corrections_are_blended_in() {
+        let mut pair = Pair::new();
+        let (host, predicted) = pair.spawn_prop(Vec3::ZERO);
+        let rollbacks = pair.rollbacks();
+
+        let target = Vec3::X;
+        pair.server.world_mut().get_mut::<Position>(host).unwrap().0 = target;
+        pair.step_until(|pair| pair.rollbacks() > rollbacks);
+
+        // The frame it rolled back on still shows it near where it was
+        let mut distance = pair.position(&pair.client, predicted).distance(target);
+        assert!(
+            distance > 0.5,
+            "Snapped straight to the host, {distance} away"
+        );
+        assert!(
+            pair.client
+                .world()
+                .entity(predicted)
+                .contains::<VisualCorrection<Position>>()
+        );
+
+        // Then closes in on it a bit every frame
+        for _ in 0..200 {
+            pair.step();
+            let closer = pair.position(&pair.client, predicted).distance(target);
+            assert!(
+                closer <= distance + f32::EPSILON,
+                "Moved away from the host, {closer} after {distance}"
+            );
+            distance = closer;
+        }
+        assert!(distance < POSITION_TOLERANCE);
+    }
+}
@@ -0,0 +1,143 @@
+// A character for every connected client, owned by the host
+// The owner predicts its own character and rolls back when the host disagrees, everyone else is interpolated
+use crate::client::LocalClient;
+use crate::interest::{AreaOfInterest, PLAYER_PRIORITY};
+use crate::physics::PhysicsReplicationPlugin;
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use character_controller::{
+    CharacterAction, CharacterController, CharacterControllerBundle, CharacterControllerPlugin,
+    CharacterMotion,
+};
+use leafwing_input_manager::prelude::*;
+use lightyear::prelude::{
+    client::*,
+    input::{InputConfig, leafwing::InputPlugin},
+    server::*,
+    *,
+};
+use serde::{Deserialize, Serialize};
+
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<CharacterControllerPlugin>() {
+            app.add_plugins(CharacterControllerPlugin);
+        }
+        app.add_plugins(InputPlugin::<CharacterAction> {
+            config: InputConfig::<CharacterAction> {
+                // Other clients' inputs help predict them a bit better
+                rebroadcast_inputs: true,
+                ..default()
+            },
+        });
+        // Never changes, so it goes straight onto predicted and interpolated copies too
+        app.register_component::<Player>();
+        app.register_component::<CharacterController>()
+            .add_prediction();
+        // Position, rotation and velocity come from here
+        if !app.is_plugin_added::<PhysicsReplicationPlugin>() {
+            app.add_plugins(PhysicsReplicationPlugin);
+        }
+        app.register_component::<CharacterMotion>().add_prediction();
+
+        app.init_resource::<PlayerSpawn>();
+        app.add_observer(spawn_player);
+        app.add_observer(setup_predicted_player);
+        app.add_observer(setup_interpolated_player);
+    }
+}
+
+/// The character a client controls
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Player(pub PeerId);
+
+/// Where the host puts new players
+#[derive(Resource, Clone, Copy, Debug, Deref, DerefMut)]
+pub struct PlayerSpawn(pub Vec3);
+
+impl Default for PlayerSpawn {
+    fn default() -> Self {
+        Self(Vec3::Y * 20.0)
+    }
+}
+
+/// Our own character, add a camera or visuals to this
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct LocalPlayer;
+
+fn spawn_player(
+    trigger: On<Add, Connected>,
+    mut commands: Commands,
+    spawn: Res<PlayerSpawn>,
+    clients: Query<(&RemoteId, Has<LocalClient>), With<ClientOf>>,
+) {
+    let Ok((id, local)) = clients.get(trigger.entity) else {
+        return;
+    };
+    let peer = id.0;
+    info!("Spawning a player for {peer:?}");
+    let player = commands
+        .spawn((
+            Name::new(format!("Player {peer:?}")),
+            Player(peer),
+            CharacterControllerBundle {
+                position: Transform::from_translation(spawn.0),
+                ..default()
+            },
+            ActionState::<CharacterAction>::default(),
+            Replicate::to_clients(NetworkTarget::All),
+            // Only to clients near it, its owner always is
+            AreaOfInterest::default(),
+            ReplicationGroup::new_from_entity().set_priority(PLAYER_PRIORITY),
+            PredictionTarget::to_clients(NetworkTarget::Single(peer)),
+            InterpolationTarget::to_clients(NetworkTarget::AllExceptSingle(peer)),
+            // Goes away with the client
+            ControlledBy {
+                owner: trigger.entity,
+                lifetime: Lifetime::SessionBased,
+            },
+        ))
+        .id();
+    // The host's own player isn't predicted, it's the real thing
+    if local {
+        commands
+            .entity(player)
+            .insert((CharacterAction::default_input_map(), LocalPlayer));
+    }
+}
+
+/// Colliders and bodies don't go over the wire, the owner rebuilds them and starts sending inputs
+/// Lightyear marks an entity predicted before its components go on, so this waits for the Player.
+/// The host's own copies have Replicate and already have everything
+fn setup_predicted_player(
+    trigger: On<Add, Player>,
+    mut commands: Commands,
+    players: Query<(), (With<Predicted>, Without<Replicate>)>,
+) {
+    if !players.contains(trigger.entity) {
+        return;
+    }
+    let bundle = CharacterControllerBundle::default();
+    commands.entity(trigger.entity).insert((
+        bundle.rigidbody,
+        bundle.collider,
+        CharacterAction::default_input_map(),
+        LocalPlayer,
+    ));
+}
+
+/// Other players only need to be something to bump into, the host moves them
+fn setup_interpolated_player(
+    trigger: On<Add, Player>,
+    mut commands: Commands,
+    players: Query<(), (With<Interpolated>, Without<Replicate>)>,
+) {
+    if players.contains(trigger.entity) {
+        commands.entity(trigger.entity).insert((
+            RigidBody::Kinematic,
+            CharacterControllerBundle::default().collider,
+        ));
+    }
+}
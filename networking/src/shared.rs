@@ -6,13 +6,14 @@ pub const SERVER_PORT: u16 = 5888;
 /// 0 means that the OS will assign any available port
 pub const CLIENT_PORT: u16 = 0;
 pub const SERVER_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), SERVER_PORT);
+/// Bump the last byte whenever peers from different builds can't talk to each other anymore
+pub const PROTOCOL_ID: u64 = u64::from_be_bytes(*b"WEAVE\0\0\x01");
 pub const SHARED_SETTINGS: SharedSettings = SharedSettings {
-    protocol_id: 0,
-    private_key: [
-        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-        0, 0,
-    ],
+    protocol_id: PROTOCOL_ID,
+    // Only good for trying things out, hosts should load or generate a real one
+    private_key: ZERO_KEY,
 };
+pub const ZERO_KEY: [u8; 32] = [0; 32];
 
 pub const SEND_INTERVAL: Duration = Duration::from_millis(100);
 
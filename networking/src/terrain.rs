@@ -0,0 +1,372 @@
+// Keeps every peer's terrain the same, the host is the only one that actually edits it
+// Clients ask for edits, the host applies them and sends them back out in order
+// Undo and redo stay local for now, they'd need the host to own the history
+use bevy::prelude::*;
+use lightyear::prelude::{server::*, *};
+use serde::{Deserialize, Serialize};
+use voxel_terrain::prelude::{ImportedHeightmap, VoxelTerrainSettings};
+use weave::{
+    area::CHUNK_SIZE,
+    marching_cubes::{
+        DensityField, Mesher, NoiseParams, RemeshChunks, SculptedDensity, TerrainChunks,
+        TerrainRoot,
+    },
+    prelude::*,
+    terrain::field_compute::FIELD_LEN,
+};
+
+/// Clamped to this before quantizing, the surface only cares about values near zero
+const DENSITY_RANGE: f32 = 8.0;
+/// Chunks per catch-up message so a heavily sculpted world doesn't turn into one giant packet
+const CATCH_UP_BATCH: usize = 16;
+
+pub struct TerrainSyncPlugin;
+
+impl Plugin for TerrainSyncPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_channel::<TerrainChannel>(ChannelSettings {
+            mode: ChannelMode::OrderedReliable(ReliableSettings::default()),
+            ..default()
+        })
+        .add_direction(NetworkDirection::Bidirectional);
+        app.register_message::<WorldSync>()
+            .add_direction(NetworkDirection::ServerToClient);
+        app.register_message::<TerrainEdit>()
+            .add_direction(NetworkDirection::Bidirectional);
+        app.register_message::<ChunkCatchUp>()
+            .add_direction(NetworkDirection::ServerToClient);
+
+        app.add_observer(request_terrain_edit);
+        app.add_observer(sync_new_client);
+        app.add_systems(
+            Update,
+            (
+                receive_edit_requests,
+                receive_world_sync,
+                receive_edits,
+                receive_catch_up,
+            ),
+        );
+    }
+}
+
+/// Terrain edits and world state, ordered so every peer applies strokes in the same sequence
+pub struct TerrainChannel;
+
+/// Everything a client needs to generate the same terrain as the host, the noise is the same
+/// everywhere so the preset is all it takes
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorldSync {
+    pub preset: TerrainPreset,
+}
+
+/// A brush stroke, `chunk` is where it's centered so it can be checked against what a peer has loaded
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TerrainEdit {
+    pub chunk: IVec3,
+    pub brush: Brush,
+}
+
+impl TerrainEdit {
+    pub fn new(brush: Brush) -> Self {
+        Self {
+            chunk: (brush.center() / CHUNK_SIZE as f32).floor().as_ivec3(),
+            brush,
+        }
+    }
+}
+
+/// Sculpted chunks for a late joiner, see [`compress_field`], plus strokes still waiting on chunks
+/// the host hasn't generated
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChunkCatchUp {
+    pub chunks: Vec<(IVec3, Vec<u8>)>,
+    pub pending: Vec<(IVec3, Vec<Brush>)>,
+}
+
+/// Sculpt the terrain on every peer, use this instead of [`ApplyBrush`] while networked
+#[derive(Event, Clone, Debug)]
+pub struct RequestTerrainEdit(pub Brush);
+
+fn request_terrain_edit(
+    trigger: On<RequestTerrainEdit>,
+    mut commands: Commands,
+    server: Option<Single<&Server, With<Started>>>,
+    mut broadcast: ServerMultiMessageSender,
+    mut clients: Query<&mut MessageSender<TerrainEdit>, (With<Client>, With<Connected>)>,
+) {
+    let edit = TerrainEdit::new(trigger.event().0.clone());
+    match server {
+        // The host's edits go straight through
+        Some(server) => apply_and_broadcast(&mut commands, &mut broadcast, &server, edit),
+        None => {
+            for mut sender in &mut clients {
+                sender.send::<TerrainChannel>(edit.clone());
+            }
+        }
+    }
+}
+
+fn apply_and_broadcast(
+    commands: &mut Commands,
+    broadcast: &mut ServerMultiMessageSender,
+    server: &Server,
+    edit: TerrainEdit,
+) {
+    if let Err(error) = broadcast.send::<_, TerrainChannel>(&edit, server, &NetworkTarget::All) {
+        warn!("Couldn't send terrain edit at {}: {error:?}", edit.chunk);
+    }
+    commands.trigger(ApplyBrush(edit.brush));
+}
+
+/// Edits clients asked for, the host is the authority so it applies them before anyone else sees them
+fn receive_edit_requests(
+    mut commands: Commands,
+    server: Option<Single<&Server, With<Started>>>,
+    mut broadcast: ServerMultiMessageSender,
+    mut requests: Query<&mut MessageReceiver<TerrainEdit>, With<ClientOf>>,
+) {
+    let Some(server) = server else {
+        return;
+    };
+    for mut receiver in &mut requests {
+        for edit in receiver.receive() {
+            apply_and_broadcast(&mut commands, &mut broadcast, &server, edit);
+        }
+    }
+}
+
+/// A client just connected, send it the world and every chunk that's been sculpted so far
+fn sync_new_client(
+    trigger: On<Add, Connected>,
+    backend: Res<SurfaceBackend>,
+    marching: Res<NoiseParams>,
+    root: Res<TerrainRoot>,
+    meshers: Query<&Mesher>,
+    voxel: Res<VoxelTerrainSettings>,
+    sculpted: Res<SculptedDensity>,
+    mut clients: Query<
+        (
+            &mut MessageSender<WorldSync>,
+            &mut MessageSender<ChunkCatchUp>,
+        ),
+        With<ClientOf>,
+    >,
+) {
+    let Ok((mut world, mut catch_up)) = clients.get_mut(trigger.entity) else {
+        return;
+    };
+    world.send::<TerrainChannel>(WorldSync {
+        preset: TerrainPreset {
+            backend: *backend,
+            marching: marching.clone(),
+            mesher: meshers.get(root.entity).copied().unwrap_or_default(),
+            voxel: *voxel,
+        },
+    });
+
+    let chunks: Vec<_> = sculpted
+        .iter()
+        .map(|(position, field)| (*position, compress_field(field)))
+        .collect();
+    let pending: Vec<_> = sculpted
+        .pending
+        .iter()
+        .map(|(position, brushes)| (*position, brushes.clone()))
+        .collect();
+    info!(
+        "Catching {} up on {} sculpted chunks and {} waiting on generation",
+        trigger.entity,
+        chunks.len(),
+        pending.len()
+    );
+    for batch in chunks.chunks(CATCH_UP_BATCH) {
+        catch_up.send::<TerrainChannel>(ChunkCatchUp {
+            chunks: batch.to_vec(),
+            ..default()
+        });
+    }
+    for batch in pending.chunks(CATCH_UP_BATCH) {
+        catch_up.send::<TerrainChannel>(ChunkCatchUp {
+            pending: batch.to_vec(),
+            ..default()
+        });
+    }
+}
+
+fn receive_world_sync(
+    mut commands: Commands,
+    root: Res<TerrainRoot>,
+    mut voxel: ResMut<VoxelTerrainSettings>,
+    server: Option<Single<(), (With<Server>, With<Started>)>>,
+    mut receivers: Query<&mut MessageReceiver<WorldSync>, With<Client>>,
+) {
+    let is_host = server.is_some();
+    for mut receiver in &mut receivers {
+        for sync in receiver.receive() {
+            // The host client gets it too, it already has the world
+            if is_host {
+                continue;
+            }
+            info!("Got world from the host");
+            let preset = sync.preset;
+            // The host's preset wins over whatever was loaded locally
+            commands.remove_resource::<ActiveTerrainPreset>();
+            commands.remove_resource::<ImportedHeightmap>();
+            commands.insert_resource(preset.marching);
+            commands.insert_resource(preset.backend);
+            commands.entity(root.entity).insert(preset.mesher);
+            *voxel = preset.voxel;
+            // Voxel heights follow the new ground on their own once it lands
+            commands.trigger(weave::marching_cubes::RegenerateChunks);
+        }
+    }
+}
+
+/// Strokes the host accepted, the host itself already applied them
+fn receive_edits(
+    mut commands: Commands,
+    server: Option<Single<(), (With<Server>, With<Started>)>>,
+    mut receivers: Query<&mut MessageReceiver<TerrainEdit>, With<Client>>,
+) {
+    let is_host = server.is_some();
+    for mut receiver in &mut receivers {
+        for edit in receiver.receive() {
+            if !is_host {
+                commands.trigger(ApplyBrush(edit.brush));
+            }
+        }
+    }
+}
+
+fn receive_catch_up(
+    mut commands: Commands,
+    mut sculpted: ResMut<SculptedDensity>,
+    chunks: Res<TerrainChunks>,
+    mut fields: Query<&mut DensityField>,
+    mut receivers: Query<&mut MessageReceiver<ChunkCatchUp>, With<Client>>,
+) {
+    for mut receiver in &mut receivers {
+        for catch_up in receiver.receive() {
+            let mut loaded = Vec::new();
+            for (position, data) in catch_up.chunks {
+                let Some(field) = decompress_field(&data) else {
+                    warn!("Dropped a corrupt catch-up chunk at {position}");
+                    continue;
+                };
+                // Unloaded chunks pick the sculpted density up when they load
+                if let Some(mut chunk_field) = chunks
+                    .get(&position)
+                    .and_then(|entity| fields.get_mut(*entity).ok())
+                {
+                    chunk_field.0.clone_from(&field);
+                    loaded.push(position);
+                }
+                sculpted.insert(position, field);
+            }
+            for (position, brushes) in catch_up.pending {
+                sculpted.pending.insert(position, brushes);
+                // Loaded here even though the host never generated it
+                if let Some(mut chunk_field) = chunks
+                    .get(&position)
+                    .and_then(|entity| fields.get_mut(*entity).ok())
+                {
+                    chunk_field.0 = sculpted.density_for(position, &chunk_field.0);
+                    loaded.push(position);
+                }
+            }
+            commands.trigger(RemeshChunks::new(loaded));
+        }
+    }
+}
+
+/// Quantizes to i16 and run length encodes it, deep solid and open air clamp to the same value
+/// so untouched stretches collapse into a few runs. Each run is a count byte then the value
+pub fn compress_field(field: &[f32]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut samples = field.iter().map(|density| {
+        (density.clamp(-DENSITY_RANGE, DENSITY_RANGE) / DENSITY_RANGE * i16::MAX as f32) as i16
+    });
+    let Some(mut current) = samples.next() else {
+        return data;
+    };
+    let mut run: u8 = 1;
+    for sample in samples {
+        if sample == current && run < u8::MAX {
+            run += 1;
+        } else {
+            data.push(run);
+            data.extend_from_slice(&current.to_le_bytes());
+            current = sample;
+            run = 1;
+        }
+    }
+    data.push(run);
+    data.extend_from_slice(&current.to_le_bytes());
+    data
+}
+
+/// Undoes [`compress_field`], `None` if the data was cut short or isn't a whole chunk's field
+pub fn decompress_field(data: &[u8]) -> Option<Vec<f32>> {
+    if !data.len().is_multiple_of(3) {
+        return None;
+    }
+    let mut field = Vec::with_capacity(FIELD_LEN);
+    for run in data.chunks_exact(3) {
+        let sample = i16::from_le_bytes([run[1], run[2]]);
+        let density = sample as f32 / i16::MAX as f32 * DENSITY_RANGE;
+        field.extend(std::iter::repeat_n(density, run[0] as usize));
+    }
+    // Losing whole runs still leaves a multiple of 3
+    (field.len() == FIELD_LEN).then_some(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A flat floor with a few sculpted bits, mostly long runs like the real thing
+    fn field() -> Vec<f32> {
+        (0..FIELD_LEN)
+            .map(|i| match i % 289 {
+                0..100 => -DENSITY_RANGE,
+                100..110 => (i % 7) as f32 - 3.0,
+                _ => DENSITY_RANGE,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fields_round_trip() {
+        let field = field();
+        let data = compress_field(&field);
+        assert!(data.len() < FIELD_LEN);
+        let back = decompress_field(&data).unwrap();
+        assert_eq!(back.len(), FIELD_LEN);
+        // Only quantizing loses anything
+        let step = DENSITY_RANGE / i16::MAX as f32;
+        for (a, b) in field.iter().zip(&back) {
+            assert!((a - b).abs() <= step, "{a} came back as {b}");
+        }
+    }
+
+    #[test]
+    fn far_densities_are_clamped() {
+        let field = vec![100.0; FIELD_LEN];
+        let back = decompress_field(&compress_field(&field)).unwrap();
+        assert!(back.iter().all(|density| *density == DENSITY_RANGE));
+    }
+
+    #[test]
+    fn cut_short_fields_are_rejected() {
+        let data = compress_field(&field());
+        // Mid run and on a run boundary
+        assert_eq!(decompress_field(&data[..data.len() - 1]), None);
+        assert_eq!(decompress_field(&data[..data.len() - 3]), None);
+        assert_eq!(decompress_field(&[]), None);
+        // Too much is just as wrong
+        let mut long = data.clone();
+        long.extend_from_slice(&data[..3]);
+        assert_eq!(decompress_field(&long), None);
+    }
+}
@@ -0,0 +1,135 @@
+// Where the dedicated server gets its settings, a ron file with cli flags on top
+use bevy::prelude::Resource;
+use networking::{
+    auth::KEY_FILE,
+    error::NetworkingError,
+    host::{ServerTransports, WebTransportCertificateSettings, parse_private_key},
+    shared::{SERVER_PORT, SHARED_SETTINGS, SharedSettings},
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage: server [--config <file.ron>] [--name <name>] [--port <port>] [--transport udp|webtransport|websocket] [--key <32 comma separated numbers>] [--key-file <file.ron>] [--protocol-id <id>]";
+
+// WebTransport is just what it's called
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Transport {
+    #[default]
+    Udp,
+    WebTransport,
+    WebSocket,
+}
+
+/// Anything missing from the file keeps its default
+#[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Shown to players browsing for servers, empty keeps it off the LAN and master server
+    pub name: String,
+    pub port: u16,
+    pub transport: Transport,
+    /// Same format as `LIGHTYEAR_PRIVATE_KEY`, which still wins if it's set
+    pub private_key: Option<String>,
+    /// Where the generated key is kept when there's no `private_key`
+    pub key_file: PathBuf,
+    pub protocol_id: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            name: "Dedicated Server".to_string(),
+            port: SERVER_PORT,
+            transport: Transport::default(),
+            private_key: None,
+            key_file: KEY_FILE.into(),
+            protocol_id: SHARED_SETTINGS.protocol_id,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads `--config` first so the other flags can override it
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let args: Vec<String> = args.collect();
+        let flag = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .map(|index| {
+                    args.get(index + 1)
+                        .ok_or(format!("{name} needs a value\n{USAGE}"))
+                })
+                .transpose()
+        };
+
+        if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+            return Err(USAGE.to_string());
+        }
+
+        let mut config = match flag("--config")? {
+            Some(path) => Self::load(PathBuf::from(path))?,
+            None => Self::default(),
+        };
+        if let Some(name) = flag("--name")? {
+            config.name = name.clone();
+        }
+        if let Some(port) = flag("--port")? {
+            config.port = port.parse().map_err(|_| format!("Bad port {port}"))?;
+        }
+        if let Some(transport) = flag("--transport")? {
+            config.transport = match transport.as_str() {
+                "udp" => Transport::Udp,
+                "webtransport" => Transport::WebTransport,
+                "websocket" => Transport::WebSocket,
+                _ => return Err(format!("Unknown transport {transport}\n{USAGE}")),
+            };
+        }
+        if let Some(key) = flag("--key")? {
+            config.private_key = Some(key.clone());
+        }
+        if let Some(path) = flag("--key-file")? {
+            config.key_file = PathBuf::from(path);
+        }
+        if let Some(id) = flag("--protocol-id")? {
+            config.protocol_id = id.parse().map_err(|_| format!("Bad protocol id {id}"))?;
+        }
+        Ok(config)
+    }
+
+    fn load(path: PathBuf) -> Result<Self, String> {
+        let text = std::fs::read_to_string(&path)
+            .map_err(|error| format!("Couldn't read {}: {error}", path.display()))?;
+        ron::from_str(&text).map_err(|error| format!("Couldn't parse {}: {error}", path.display()))
+    }
+
+    pub fn transport(&self) -> ServerTransports {
+        match self.transport {
+            Transport::Udp => ServerTransports::Udp {
+                local_port: self.port,
+            },
+            Transport::WebTransport => ServerTransports::WebTransport {
+                local_port: self.port,
+                certificate: WebTransportCertificateSettings::default(),
+            },
+            Transport::WebSocket => ServerTransports::WebSocket {
+                local_port: self.port,
+            },
+        }
+    }
+
+    /// The key file only gets used when no key was given
+    pub fn key_file(&self) -> Option<PathBuf> {
+        self.private_key.is_none().then(|| self.key_file.clone())
+    }
+
+    pub fn shared(&self) -> Result<SharedSettings, NetworkingError> {
+        Ok(SharedSettings {
+            protocol_id: self.protocol_id,
+            private_key: match self.private_key.as_deref() {
+                Some(key) => parse_private_key(key)?,
+                None => SHARED_SETTINGS.private_key,
+            },
+        })
+    }
+}
@@ -0,0 +1,138 @@
+// Dedicated server, no window and no rendering so it can sit on a headless box
+// Physics and networking run like they would on a host, there's just nobody playing on it
+// Terrain sync needs weave's gpu noise so it isn't on here yet
+use avian3d::prelude::*;
+use bevy::{
+    app::{ScheduleRunnerPlugin, ctrlc},
+    asset::AssetPlugin,
+    log::LogPlugin,
+    mesh::MeshPlugin,
+    prelude::*,
+    scene::ScenePlugin,
+};
+use config::ServerConfig;
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use lightyear::prelude::server::*;
+use networking::{prelude::*, shared::FIXED_TIMESTEP_HZ};
+
+mod config;
+
+/// Set by the Ctrl+C handler, bevy's own would exit before the server got to stop
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Certificate reloads stop the server too, this says the next stop is for good
+#[derive(Resource)]
+struct ShuttingDown;
+
+fn main() -> AppExit {
+    let config = match ServerConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(message) => {
+            eprintln!("{message}");
+            return AppExit::error();
+        }
+    };
+
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+            1.0 / FIXED_TIMESTEP_HZ,
+        ))),
+        LogPlugin::default(),
+        TransformPlugin,
+        // Colliders can still be built from meshes without anything to draw them
+        AssetPlugin::default(),
+        MeshPlugin,
+        // Avian looks for scenes to build colliders for
+        ScenePlugin,
+        PhysicsPlugins::default(),
+        NetworkingPlugin,
+    ));
+    app.insert_resource(config);
+    app.add_systems(Startup, start_server);
+    if let Err(error) = ctrlc::try_set_handler(|| SHUTDOWN_REQUESTED.store(true, Ordering::Relaxed))
+    {
+        warn!("Couldn't set the Ctrl+C handler: {error}");
+    }
+    app.add_systems(Update, shutdown);
+    app.add_observer(exit_once_stopped);
+    app.add_observer(host_failed);
+    app.add_observer(log_connect);
+    app.add_observer(log_disconnect);
+    app.run()
+}
+
+fn start_server(
+    mut commands: Commands,
+    config: Res<ServerConfig>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    let shared = match config.shared() {
+        Ok(shared) => shared,
+        Err(error) => {
+            error!("Can't start the server: {error}");
+            exit.write(AppExit::error());
+            return;
+        }
+    };
+    info!(
+        "Starting dedicated server on port {} over {:?}",
+        config.port, config.transport
+    );
+    commands.trigger(Host {
+        transport: config.transport(),
+        shared,
+        local_client: false,
+        key_file: config.key_file(),
+        token_service: true,
+        advertise: (!config.name.is_empty()).then(|| config.name.clone()),
+    });
+}
+
+/// Nothing to do without a server, the reason was already logged
+fn host_failed(_trigger: On<HostFailed>, mut exit: MessageWriter<AppExit>) {
+    exit.write(AppExit::error());
+}
+
+fn log_connect(trigger: On<PeerJoined>) {
+    info!("Client {:?} connected", trigger.event().peer);
+}
+
+fn log_disconnect(trigger: On<PeerLeft>) {
+    info!("Client {:?} disconnected", trigger.event().peer);
+}
+
+/// Ctrl+C stops the server first so clients get told instead of timing out, the app exits once
+/// it has actually stopped
+fn shutdown(
+    mut commands: Commands,
+    servers: Query<Entity, (With<Server>, With<Started>)>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    if !SHUTDOWN_REQUESTED.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    info!("Shutting down");
+    commands.insert_resource(ShuttingDown);
+    if servers.is_empty() {
+        exit.write(AppExit::from_code(130));
+        return;
+    }
+    for server in &servers {
+        commands.trigger(Stop { entity: server });
+    }
+}
+
+/// Netcode only says it's stopped the frame after the disconnect packets went out
+fn exit_once_stopped(
+    _trigger: On<Add, Stopped>,
+    shutting_down: Option<Res<ShuttingDown>>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    if shutting_down.is_some() {
+        exit.write(AppExit::from_code(130));
+    }
+}
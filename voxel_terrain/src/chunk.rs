@@ -9,7 +9,6 @@ use bevy::{
     prelude::*,
     tasks::{AsyncComputeTaskPool, Task},
 };
-use noiz::prelude::*;
 
 pub const CHUNK_SIZE: i32 = 64;
 // DONT CHANGE THIS!!!! it dont work
@@ -22,6 +21,18 @@ impl Chunk {
     pub fn new(x: i32, y: i32) -> Self {
         Chunk(IVec2::new(x, y))
     }
+
+    /// The column a world position falls in, columns are centered on multiples of CHUNK_SIZE
+    pub fn containing(translation: Vec3) -> Self {
+        let size = CHUNK_SIZE as f32 * VOXEL_SIZE.x;
+        Chunk((translation.xz() / size + 0.5).floor().as_ivec2())
+    }
+
+    /// World xz of the column's lowest corner, half a chunk back from its center
+    pub fn corner(&self) -> Vec2 {
+        let size = CHUNK_SIZE as f32 * VOXEL_SIZE.x;
+        (self.0.as_vec2() - 0.5) * size
+    }
 }
 
 //#[derive(Component)]
@@ -33,17 +44,18 @@ impl Chunk {
 
 pub fn spawn_generator_task(
     chunk: Chunk,
-    noise: TerrainNoise,
+    height: TerrainHeight,
     pool: &AsyncComputeTaskPool,
 ) -> Task<(Mesh, Collider)> {
     pool.spawn(async move {
         let mut points = vec![];
+        let corner = chunk.corner();
         for i in 0..CHUNK_SIZE {
             for j in 0..CHUNK_SIZE {
-                // Offset by chunk location
-                let x = (i as Scalar) + (chunk.x * CHUNK_SIZE) as f32;
-                let z = (j as Scalar) + (chunk.y * CHUNK_SIZE) as f32;
-                let y = noise.sample_for::<f32>(Vec2::new(x, z) * 0.005) * 40.0;
+                // Sampled where it's drawn, the mesh sits at the chunk's corner
+                let x = (i as Scalar) + corner.x;
+                let z = (j as Scalar) + corner.y;
+                let y = height.height(Vec2::new(x, z));
                 let point = Vector::new(i as Scalar, y, j as Scalar); // Local coords
                 //for depth in 0..100 {
                 //    let point = point + Vector::new(0.0, -depth as Scalar, 0.0);
@@ -57,10 +69,7 @@ pub fn spawn_generator_task(
 
         // Compute the mesh for rendering.
         let (vertices, indices) = collider.shape().as_voxels().unwrap().to_trimesh();
-        let vertices: Vec<[f32; 3]> = vertices
-            .iter()
-            .map(|v| [v.x as f32, v.y as f32, v.z as f32])
-            .collect();
+        let vertices: Vec<[f32; 3]> = vertices.iter().map(|v| [v.x, v.y, v.z]).collect();
         let indices: Vec<u32> = indices.into_iter().flatten().collect();
         let mesh = Mesh::new(
             PrimitiveTopology::TriangleList,
@@ -0,0 +1,225 @@
+// Heights from an image instead of noise, for DEM data or hand painted maps
+// 16 bit pngs and exrs keep enough precision to not look terraced, 8 bit works but steps
+use crate::{
+    manager::RegenerateTerrain,
+    terrain::{HeightSource, TerrainHeight},
+};
+use bevy::{
+    asset::{LoadState, RenderAssetUsages},
+    image::ImageLoaderSettings,
+    prelude::*,
+    render::render_resource::TextureFormat,
+};
+
+/// Loads a grayscale image and makes it the [`TerrainHeight`] once it's ready
+#[derive(Event, Clone, Debug)]
+pub struct ImportHeightmap {
+    /// Relative to the assets folder
+    pub path: String,
+    /// World units the whole image covers, centered on the origin
+    pub size: Vec2,
+    /// Height of a white pixel in world units
+    pub height: f32,
+}
+
+/// The heightmap currently driving the terrain, while this exists other height sources leave it alone
+#[derive(Resource, Clone, Debug)]
+pub struct ImportedHeightmap {
+    pub path: String,
+}
+
+#[derive(Resource)]
+pub(crate) struct PendingHeightmap {
+    image: Handle<Image>,
+    import: ImportHeightmap,
+}
+
+pub(crate) fn import_heightmap(
+    trigger: On<ImportHeightmap>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    let import = trigger.event().clone();
+    // Only the cpu ever reads it, and gamma would squash the heights
+    let image = asset_server.load_with_settings(
+        import.path.clone(),
+        |settings: &mut ImageLoaderSettings| {
+            settings.is_srgb = false;
+            settings.asset_usage = RenderAssetUsages::MAIN_WORLD;
+        },
+    );
+    commands.insert_resource(PendingHeightmap { image, import });
+}
+
+pub(crate) fn apply_heightmap(
+    mut commands: Commands,
+    pending: Res<PendingHeightmap>,
+    asset_server: Res<AssetServer>,
+    images: Res<Assets<Image>>,
+) {
+    let import = &pending.import;
+    if let LoadState::Failed(error) = asset_server.load_state(&pending.image) {
+        error!("Couldn't load heightmap {}: {error}", import.path);
+        commands.remove_resource::<PendingHeightmap>();
+        return;
+    }
+    let Some(image) = images.get(&pending.image) else {
+        return;
+    };
+
+    commands.remove_resource::<PendingHeightmap>();
+    let Some(heightmap) = Heightmap::from_image(image, import.size, import.height) else {
+        error!(
+            "Heightmap {} has an unsupported format {:?}",
+            import.path, image.texture_descriptor.format
+        );
+        return;
+    };
+
+    info!(
+        "Imported heightmap {} ({}x{})",
+        import.path, heightmap.width, heightmap.depth
+    );
+    commands.insert_resource(TerrainHeight::new(heightmap));
+    commands.insert_resource(ImportedHeightmap {
+        path: import.path.clone(),
+    });
+    commands.trigger(RegenerateTerrain);
+}
+
+pub(crate) fn heightmap_pending(pending: Option<Res<PendingHeightmap>>) -> bool {
+    pending.is_some()
+}
+
+/// Normalized samples laid out row by row, bilinearly filtered and clamped at the edges
+#[derive(Clone, Debug)]
+pub struct Heightmap {
+    pub width: u32,
+    pub depth: u32,
+    pub samples: Vec<f32>,
+    pub size: Vec2,
+    pub height: f32,
+}
+
+impl Heightmap {
+    /// Reads the first channel of the image, 0 is the bottom and 1 is `height`
+    pub fn from_image(image: &Image, size: Vec2, height: f32) -> Option<Self> {
+        let data = image.data.as_ref()?;
+        let (width, depth) = (image.width(), image.height());
+        let samples = match image.texture_descriptor.format {
+            TextureFormat::R16Uint | TextureFormat::R16Unorm => data
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]) as f32 / u16::MAX as f32)
+                .collect(),
+            TextureFormat::Rgba16Unorm => data
+                .chunks_exact(8)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]) as f32 / u16::MAX as f32)
+                .collect(),
+            TextureFormat::R32Float => data
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+            TextureFormat::Rgba32Float => data
+                .chunks_exact(16)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+            TextureFormat::R8Unorm => data.iter().map(|b| *b as f32 / 255.0).collect(),
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => {
+                data.chunks_exact(4).map(|b| b[0] as f32 / 255.0).collect()
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            width,
+            depth,
+            samples,
+            size,
+            height,
+        })
+    }
+
+    fn sample(&self, x: u32, z: u32) -> f32 {
+        let x = x.min(self.width - 1);
+        let z = z.min(self.depth - 1);
+        self.samples[(z * self.width + x) as usize]
+    }
+}
+
+impl HeightSource for Heightmap {
+    fn height(&self, position: Vec2) -> f32 {
+        let resolution = UVec2::new(self.width, self.depth).as_vec2();
+        let pixel =
+            ((position / self.size + 0.5) * resolution - 0.5).clamp(Vec2::ZERO, resolution - 1.0);
+        let (x, z) = (pixel.x as u32, pixel.y as u32);
+        let t = pixel.fract();
+
+        let top = self.sample(x, z).lerp(self.sample(x + 1, z), t.x);
+        let bottom = self.sample(x, z + 1).lerp(self.sample(x + 1, z + 1), t.x);
+        top.lerp(bottom, t.y) * self.height
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{Chunk, spawn_generator_task};
+    use bevy::{
+        mesh::VertexAttributeValues,
+        render::render_resource::{Extent3d, TextureDimension},
+        tasks::{AsyncComputeTaskPool, block_on},
+    };
+
+    #[test]
+    fn imported_features_stay_where_the_image_put_them() {
+        // One white block in the middle of an otherwise flat map, a pixel per voxel
+        let data = (0..64 * 64)
+            .map(|i| {
+                let (x, z) = (i % 64, i / 64);
+                if (28..36).contains(&x) && (28..36).contains(&z) {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect();
+        let image = Image::new(
+            Extent3d {
+                width: 64,
+                height: 64,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::R8Unorm,
+            RenderAssetUsages::MAIN_WORLD,
+        );
+        let heightmap = Heightmap::from_image(&image, Vec2::splat(64.0), 10.0).unwrap();
+        assert_eq!(heightmap.height(Vec2::ZERO), 10.0);
+        assert_eq!(heightmap.height(Vec2::splat(-20.0)), 0.0);
+
+        let pool = AsyncComputeTaskPool::get_or_init(Default::default);
+        let chunk = Chunk::new(0, 0);
+        let (mesh, _) = block_on(spawn_generator_task(
+            chunk,
+            TerrainHeight::new(heightmap),
+            pool,
+        ));
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("voxel meshes always have positions");
+        };
+        // Where the mesh ends up once it's placed at the chunk's corner
+        let corner = chunk.corner();
+        let raised: Vec<Vec2> = positions
+            .iter()
+            .filter(|p| p[1] > 5.0)
+            .map(|p| Vec2::new(p[0], p[2]) + corner)
+            .collect();
+        assert!(!raised.is_empty());
+        for p in raised {
+            assert!(p.abs().max_element() <= 6.0, "block drawn at {p}");
+        }
+    }
+}
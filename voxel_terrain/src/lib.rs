@@ -11,6 +11,7 @@ use bevy::prelude::*;
 use manager::*;
 
 mod chunk;
+mod heightmap;
 mod manager;
 mod terrain;
 
@@ -21,12 +22,12 @@ impl Plugin for VoxelTerrainPlugin {
         // These don't have to be fixed, just makes it run a lil less
         app.insert_resource(ChunkManager::default());
         app.insert_resource(ChunkSpawnLimiter::default());
+        app.init_resource::<terrain::VoxelTerrainSettings>();
         app.add_systems(Startup, || {warn!("This plugin is currently pretty inefficient, issues with collider calculations potentially??")});
         app.add_systems(
             Update,
             (
                 adjust_limiter,
-                add_desired_chunks,
                 spawn_with_limits,
                 make_chunks_dormant,
                 make_dormant_chunks_active,
@@ -35,13 +36,24 @@ impl Plugin for VoxelTerrainPlugin {
                 .chain()
                 .run_if(|terrain: Query<&terrain::VoxelTerrain>| !terrain.is_empty()),
         );
+        app.add_systems(
+            Update,
+            heightmap::apply_heightmap.run_if(heightmap::heightmap_pending),
+        );
         app.add_observer(terrain::setup);
+        app.add_observer(regenerate_terrain);
+        app.add_observer(heightmap::import_heightmap);
     }
 }
 
 pub mod prelude {
     pub use crate::VoxelTerrainPlugin;
+    pub use crate::chunk::CHUNK_SIZE;
     pub use crate::chunk::Chunk;
-    pub use crate::manager::{AreaManaged, Observer};
-    pub use crate::terrain::{TerrainMaterial, VoxelTerrain};
+    pub use crate::heightmap::{Heightmap, ImportHeightmap, ImportedHeightmap};
+    pub use crate::manager::{Active, ChunkManager, Dormant, Loading, RegenerateTerrain};
+    pub use crate::terrain::{
+        HeightSource, NoiseHeight, TerrainHeight, TerrainMaterial, VoxelTerrain,
+        VoxelTerrainSettings,
+    };
 }
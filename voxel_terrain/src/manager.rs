@@ -1,11 +1,11 @@
 // Should route how the chunks need to be managed
 // A rewrite is in order!!!
 use crate::{
-    chunk::{CHUNK_SIZE, Chunk, VOXEL_SIZE},
-    terrain::{TerrainMaterial, TerrainNoise, VoxelTerrain},
+    chunk::{Chunk, VOXEL_SIZE},
+    terrain::{TerrainHeight, TerrainMaterial, VoxelTerrain},
 };
 use avian3d::{
-    math::{AsF32, Scalar, Vector},
+    math::{AsF32, Vector},
     prelude::*,
 };
 use bevy::{
@@ -14,22 +14,13 @@ use bevy::{
     tasks::{AsyncComputeTaskPool, Task, block_on, futures_lite::future},
 };
 
-/// Attach this to the entity where the terrain should be loaded.
-#[derive(Component, Reflect, Debug)]
-#[require(AreaManaged::Circle(10.0))]
-pub struct Observer;
-
-/// In what shape and distance the terrain should be loaded.
-#[derive(Component, Reflect, Debug)]
-pub enum AreaManaged {
-    Circle(f32),
-    Rectangle(f32, f32),
-}
-
 #[derive(Resource, Reflect, Default)]
+/// Which chunks should exist is decided from outside, this just tracks and spawns them
 pub struct ChunkManager {
     desired_chunks: HashSet<Chunk>,
     chunk_entities: HashMap<Chunk, Entity>,
+    /// Chunks closest to this get generated first
+    focus: IVec2,
 }
 
 impl ChunkManager {
@@ -37,10 +28,20 @@ impl ChunkManager {
         self.desired_chunks.insert(pos);
     }
 
-    pub fn _unload_chunk(&mut self, pos: Chunk) {
+    pub fn unload_chunk(&mut self, pos: Chunk) {
         self.desired_chunks.remove(&pos);
     }
 
+    pub fn set_focus(&mut self, focus: IVec2) {
+        self.focus = focus;
+    }
+
+    /// Forgets every chunk, for when the terrain entity goes away
+    pub fn clear(&mut self) {
+        self.desired_chunks.clear();
+        self.chunk_entities.clear();
+    }
+
     pub fn should_exist(&self, pos: &Chunk) -> bool {
         self.desired_chunks.contains(pos)
     }
@@ -93,25 +94,23 @@ pub struct Dormant;
 pub fn spawn_with_limits(
     mut manager: ResMut<ChunkManager>,
     limiter: Res<ChunkSpawnLimiter>,
-    generator: Res<TerrainNoise>,
+    height: Res<TerrainHeight>,
     loading_chunks: Query<(), With<Loading>>,
     terrain: Single<Entity, With<VoxelTerrain>>,
-    observer: Single<&GlobalTransform, With<Observer>>,
     mut commands: Commands,
 ) {
     let current_tasks = loading_chunks.iter().count();
     if current_tasks >= limiter.max_concurrent_tasks {
         return;
     }
-    let observer = *observer;
-    let observer_pos = observer.translation().xz().as_ivec2() / IVec2::splat(CHUNK_SIZE);
+    let focus = manager.focus;
 
     // Get chunks sorted by priority
     let mut to_spawn: Vec<_> = manager
         .desired_chunks
         .iter()
         .filter(|pos| manager.get_entity(pos).is_none())
-        .map(|pos| (*pos, pos.distance_squared(observer_pos)))
+        .map(|pos| (*pos, pos.distance_squared(focus)))
         .collect();
 
     to_spawn.sort_by_key(|(_, dist)| *dist);
@@ -121,60 +120,26 @@ pub fn spawn_with_limits(
         .min(limiter.max_concurrent_tasks - current_tasks);
 
     let pool = AsyncComputeTaskPool::get();
-    let noise = *generator.into_inner();
     for (chunk, _) in to_spawn.iter().take(spawn_count) {
-        let task = crate::chunk::spawn_generator_task(*chunk, noise, pool);
+        let task = crate::chunk::spawn_generator_task(*chunk, height.clone(), pool);
         let entity = commands.spawn((*chunk, Loading(task))).id();
         commands.entity(*terrain).add_child(entity);
         manager.register_chunk(*chunk, entity);
     }
 }
 
-pub fn add_desired_chunks(
-    mut manager: ResMut<ChunkManager>,
-    query: Query<(&AreaManaged, &GlobalTransform), (With<Observer>, Changed<GlobalTransform>)>,
-) {
-    manager.desired_chunks.clear();
-
-    for (area, transform) in query {
-        let transform_offset = transform.translation().xz().as_ivec2() / IVec2::splat(CHUNK_SIZE);
-
-        match area {
-            AreaManaged::Circle(r) => {
-                let r = *r as i32;
-                for i in 0..(r * 2 + 1) {
-                    for j in 0..(r * 2 + 1) {
-                        let x = i - r;
-                        let y = j - r;
-
-                        if (x * x + y * y) <= (r * r) {
-                            let chunk_pos = IVec2::new(x, y) + transform_offset;
-                            manager.request_chunk(Chunk(chunk_pos));
-                        }
-                    }
-                }
-            }
-            AreaManaged::Rectangle(_, _) => {
-                // Basically whats above just without hte if statement
-                todo!("Rectangle Not implemented yet!!!");
-            }
-        }
-    }
-}
-
 pub fn _spawn_missing_chunks(
     mut commands: Commands,
     terrian: Single<Entity, With<VoxelTerrain>>,
     mut manager: ResMut<ChunkManager>,
-    generator: Res<TerrainNoise>,
+    height: Res<TerrainHeight>,
 ) {
     let pool = AsyncComputeTaskPool::get();
-    let noise = *generator.into_inner();
     // Collect desired_chunks to release the immutable borrow on manager
     for chunk in manager.iter_desired_chunks() {
         // Only spawn a chunk if it does not already have an entity registered
         if manager.get_entity(&chunk).is_none() {
-            let task = crate::chunk::spawn_generator_task(chunk, noise, pool);
+            let task = crate::chunk::spawn_generator_task(chunk, height.clone(), pool);
             let entity = commands.spawn((chunk, Loading(task))).id();
             commands.entity(*terrian).add_child(entity);
             manager.register_chunk(chunk, entity);
@@ -182,17 +147,38 @@ pub fn _spawn_missing_chunks(
     }
 }
 
+/// Rebuilds every spawned chunk from the current [`TerrainHeight`]
+#[derive(Event)]
+pub struct RegenerateTerrain;
+
+/// Chunks keep their old mesh and collider until the new ones are ready
+pub fn regenerate_terrain(
+    _trigger: On<RegenerateTerrain>,
+    height: Option<Res<TerrainHeight>>,
+    chunks: Query<(Entity, &Chunk)>,
+    mut commands: Commands,
+) {
+    // Nothing has been generated yet
+    let Some(height) = height else {
+        return;
+    };
+
+    let pool = AsyncComputeTaskPool::get();
+    for (entity, chunk) in chunks {
+        let task = crate::chunk::spawn_generator_task(*chunk, height.clone(), pool);
+        commands.entity(entity).insert(Loading(task));
+    }
+}
+
 pub fn handle_spawning_chunk(
     query: Query<(Entity, &Chunk, &mut Loading)>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     material: Res<TerrainMaterial>,
 ) {
-    let chunk_world_size = CHUNK_SIZE as Scalar * VOXEL_SIZE.x;
-
     for (entity, chunk, mut task) in query {
         if let Some((mesh, collider)) = block_on(future::poll_once(&mut task.0)) {
-            let location = chunk.0; // Get location from Chunk component
+            let corner = chunk.corner();
             commands
                 .entity(entity)
                 .insert((
@@ -202,14 +188,7 @@ pub fn handle_spawning_chunk(
                     Mesh3d(meshes.add(mesh)),
                     MeshMaterial3d(material.clone()),
                     Transform::from_translation(
-                        Vector::new(
-                            location.x as f32 * chunk_world_size
-                                - CHUNK_SIZE as Scalar / 2.0 * VOXEL_SIZE.x,
-                            -5.0 * VOXEL_SIZE.y,
-                            location.y as f32 * chunk_world_size
-                                - CHUNK_SIZE as Scalar / 2.0 * VOXEL_SIZE.z,
-                        )
-                        .f32(),
+                        Vector::new(corner.x, -5.0 * VOXEL_SIZE.y, corner.y).f32(),
                     ),
                 ))
                 .remove::<Loading>()
@@ -1,5 +1,7 @@
 use bevy::prelude::*;
 use noiz::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 // Head, this starts everything
 #[derive(Component, Reflect, Debug, Default)]
@@ -15,6 +17,79 @@ pub struct TerrainNoise(
     >,
 );
 
+/// Where the columns get their height from, the voxels only care about the surface
+pub trait HeightSource: Send + Sync + 'static {
+    /// Surface height in voxels at a world position on the xz plane
+    fn height(&self, position: Vec2) -> f32;
+}
+
+/// The height source chunks are generated with, replace it and trigger a [`RegenerateTerrain`](crate::manager::RegenerateTerrain) to apply
+#[derive(Resource, Clone, Deref)]
+pub struct TerrainHeight(pub Arc<dyn HeightSource>);
+
+impl TerrainHeight {
+    pub fn new(source: impl HeightSource) -> Self {
+        Self(Arc::new(source))
+    }
+}
+
+impl From<VoxelTerrainSettings> for TerrainHeight {
+    fn from(settings: VoxelTerrainSettings) -> Self {
+        Self::new(NoiseHeight {
+            noise: settings.noise(),
+            settings,
+        })
+    }
+}
+
+/// The built in worley heightmap
+#[derive(Clone, Copy)]
+pub struct NoiseHeight {
+    pub noise: TerrainNoise,
+    pub settings: VoxelTerrainSettings,
+}
+
+impl HeightSource for NoiseHeight {
+    fn height(&self, position: Vec2) -> f32 {
+        self.noise
+            .sample_for::<f32>(position * self.settings.horizontal_scale)
+            * self.settings.height
+    }
+}
+
+/// Settings for the built in heightmap, they only apply through a [`TerrainHeight`] made from them
+#[derive(Resource, Reflect, Debug, Clone, Copy, Serialize, Deserialize)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct VoxelTerrainSettings {
+    pub frequency: f32,
+    pub period: f32,
+    /// Multiplier on world position before sampling, smaller means wider features
+    pub horizontal_scale: f32,
+    /// Height of the tallest column in voxels
+    pub height: f32,
+}
+
+impl Default for VoxelTerrainSettings {
+    fn default() -> Self {
+        Self {
+            frequency: 0.05,
+            period: 1.0,
+            horizontal_scale: 0.005,
+            height: 40.0,
+        }
+    }
+}
+
+impl VoxelTerrainSettings {
+    pub fn noise(&self) -> TerrainNoise {
+        let mut noise = Noise::from(common_noise::Worley::default());
+        noise.set_frequency(self.frequency);
+        noise.set_period(self.period);
+        TerrainNoise(noise)
+    }
+}
+
 #[derive(Resource, Reflect, Deref, DerefMut)]
 pub struct TerrainMaterial(pub Handle<StandardMaterial>);
 
@@ -22,11 +97,13 @@ pub fn setup(
     trigger: On<Add, VoxelTerrain>,
     mut commands: Commands,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    settings: Res<VoxelTerrainSettings>,
+    height: Option<Res<TerrainHeight>>,
 ) {
-    let mut noise = Noise::from(common_noise::Worley::default());
-    noise.set_frequency(0.05);
-    noise.set_period(1.0);
-    commands.insert_resource(TerrainNoise(noise));
+    // Something else may have already picked where the height comes from
+    if height.is_none() {
+        commands.insert_resource(TerrainHeight::from(*settings));
+    }
     commands.insert_resource(TerrainMaterial(materials.add(StandardMaterial {
         base_color: Color::srgb(0.5, 0.5, 0.5),
         ..default()
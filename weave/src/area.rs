@@ -1,47 +1,213 @@
 #![allow(unused)]
-use bevy::prelude::*;
+use crate::{SurfaceBackend, planet::Planet};
+use bevy::{platform::collections::HashMap, prelude::*};
+
+/// World size of a chunk, matches the 17 sample noise field
+pub const CHUNK_SIZE: i32 = 16;
+
+/// Caps how many new chunks get requested each frame so the gpu isnt flooded
+const MAX_REQUESTS_PER_FRAME: usize = 16;
+
+pub struct AreaPlugin;
+
+impl Plugin for AreaPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LoadedArea>();
+        app.add_systems(
+            Update,
+            (
+                reload_area.run_if(resource_changed::<SurfaceBackend>),
+                area_manager,
+            )
+                .chain(),
+        );
+    }
+}
 
 #[derive(Component)]
 pub struct Observer;
 
 #[derive(Component)]
 pub struct AreaManaged {
-    render_distance: i32,
-    lod_gradient: fn(i32, i32) -> LodLevel,
+    pub render_distance: i32,
+    /// How many chunk layers above and below the observer get loaded
+    pub vertical_distance: i32,
+    pub lod_gradient: fn(i32, i32) -> LodLevel,
+}
+
+impl AreaManaged {
+    pub fn lod(&self, distance: i32) -> LodLevel {
+        (self.lod_gradient)(distance, self.render_distance)
+    }
 }
 
 impl Default for AreaManaged {
     fn default() -> Self {
         Self {
             render_distance: 25,
+            vertical_distance: 1,
             lod_gradient: |distance_from_center, rd| {
                 let distance = distance_from_center.abs();
                 let high_upper_bound = rd / 3;
                 let medium_upper_bound = rd - (rd / 5);
-                let mut lod = LodLevel::Low;
                 if distance < high_upper_bound {
-                    lod = LodLevel::High;
+                    LodLevel::High
+                } else if distance < medium_upper_bound {
+                    LodLevel::Medium
+                } else {
+                    LodLevel::Low
                 }
-                if distance < medium_upper_bound {
-                    lod = LodLevel::Medium;
-                }
-                lod
             },
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect)]
 pub enum LodLevel {
     Low,
     Medium,
+    #[default]
     High,
 }
 
+impl LodLevel {
+    /// Distance between the samples a chunk at this lod is meshed from
+    pub fn step(self) -> u32 {
+        match self {
+            LodLevel::High => 1,
+            LodLevel::Medium => 2,
+            LodLevel::Low => 4,
+        }
+    }
+}
+
 /// Request a chunk of a noise parameter
 #[derive(Event)]
 pub struct RequestArea {
     pub position: IVec2,
 }
 
-// possibly generic???
-pub fn area_manager(area: Query<&AreaManaged, With<Observer>>) {}
+/// A chunk came into range, whichever surface backend is active builds it
+#[derive(Event)]
+pub struct LoadChunk {
+    pub position: IVec3,
+    pub lod: LodLevel,
+}
+
+/// A loaded chunk moved to a different lod, it and its neighbours need re-meshing
+#[derive(Event)]
+pub struct ChunkLodChanged {
+    pub position: IVec3,
+    pub lod: LodLevel,
+}
+
+/// A chunk left every observers range
+#[derive(Event)]
+pub struct UnloadChunk {
+    pub position: IVec3,
+}
+
+/// Every chunk the area manager has requested and the lod it wants it at
+#[derive(Resource, Default)]
+pub struct LoadedArea {
+    lods: HashMap<IVec3, LodLevel>,
+}
+
+impl LoadedArea {
+    pub fn lod(&self, position: IVec3) -> Option<LodLevel> {
+        self.lods.get(&position).copied()
+    }
+
+    pub fn contains(&self, position: IVec3) -> bool {
+        self.lods.contains_key(&position)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (IVec3, LodLevel)> + '_ {
+        self.lods.iter().map(|(position, lod)| (*position, *lod))
+    }
+}
+
+/// The finest lod any observer wants for each chunk, along with how far it is
+pub(crate) type WantedChunks = HashMap<IVec3, (LodLevel, i32)>;
+
+pub(crate) fn want(wanted: &mut WantedChunks, position: IVec3, lod: LodLevel, distance: i32) {
+    wanted
+        .entry(position)
+        .and_modify(|(current, closest)| {
+            if lod.step() < current.step() {
+                *current = lod;
+            }
+            *closest = (*closest).min(distance);
+        })
+        .or_insert((lod, distance));
+}
+
+/// A circle of columns around the observer, a few layers tall
+fn flat_area(translation: Vec3, managed: &AreaManaged, wanted: &mut WantedChunks) {
+    let center = (translation / CHUNK_SIZE as f32).floor().as_ivec3();
+    let rd = managed.render_distance;
+
+    for x in -rd..=rd {
+        for z in -rd..=rd {
+            let distance = ((x * x + z * z) as f32).sqrt() as i32;
+            if distance > rd {
+                continue;
+            }
+            let lod = managed.lod(distance);
+            for y in -managed.vertical_distance..=managed.vertical_distance {
+                want(wanted, center + IVec3::new(x, y, z), lod, distance);
+            }
+        }
+    }
+}
+
+pub fn area_manager(
+    mut commands: Commands,
+    mut area: ResMut<LoadedArea>,
+    observers: Query<(&GlobalTransform, &AreaManaged), With<Observer>>,
+    planet: Option<Res<Planet>>,
+) {
+    let mut wanted = WantedChunks::new();
+    for (transform, managed) in observers {
+        match &planet {
+            Some(planet) => planet.shell_area(transform.translation(), managed, &mut wanted),
+            None => flat_area(transform.translation(), managed, &mut wanted),
+        }
+    }
+
+    area.lods.retain(|position, _| {
+        let keep = wanted.contains_key(position);
+        if !keep {
+            commands.trigger(UnloadChunk {
+                position: *position,
+            });
+        }
+        keep
+    });
+
+    let mut missing = Vec::new();
+    for (position, (lod, distance)) in wanted {
+        match area.lods.get_mut(&position) {
+            Some(current) if *current != lod => {
+                *current = lod;
+                commands.trigger(ChunkLodChanged { position, lod });
+            }
+            Some(_) => {}
+            None => missing.push((distance, position, lod)),
+        }
+    }
+
+    // Closest chunks first
+    missing.sort_unstable_by_key(|(distance, ..)| *distance);
+    for (_, position, lod) in missing.into_iter().take(MAX_REQUESTS_PER_FRAME) {
+        area.lods.insert(position, lod);
+        commands.trigger(LoadChunk { position, lod });
+    }
+}
+
+/// Drops everything so the new backend loads the area from scratch
+fn reload_area(mut commands: Commands, mut area: ResMut<LoadedArea>) {
+    for (position, _) in area.lods.drain() {
+        commands.trigger(UnloadChunk { position });
+    }
+}
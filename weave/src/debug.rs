@@ -0,0 +1,256 @@
+// Gizmos and a little panel for seeing what chunk streaming is up to
+// Off by default, trigger ToggleTerrainDebug (the console has a command for it) or press
+// DebugAction::ToggleTerrainDebug to show it
+use crate::{
+    SurfaceBackend,
+    area::{AreaManaged, CHUNK_SIZE, LoadedArea, LodLevel, Observer},
+    marching_cubes::{TerrainChunks, chunk_translation},
+    terrain::{DensityLayers, field_compute::NoiseRequests},
+};
+use bevy::{color::palettes::css, prelude::*};
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+use voxel_terrain::prelude::{Active, Chunk, Dormant, Loading};
+
+pub struct TerrainDebugPlugin;
+
+impl Plugin for TerrainDebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TerrainDebug>();
+        app.add_plugins(InputManagerPlugin::<DebugAction>::default());
+        app.init_resource::<ActionState<DebugAction>>();
+        app.insert_resource(DebugAction::default_input_map());
+        app.add_systems(Startup, spawn_debug_panel);
+        app.add_systems(
+            Update,
+            (
+                draw_marching_chunks.run_if(backend_is(SurfaceBackend::MarchingCubes)),
+                draw_voxel_chunks.run_if(backend_is(SurfaceBackend::Voxel)),
+                draw_load_radius,
+                update_debug_panel,
+            )
+                .run_if(debug_enabled),
+        );
+        app.add_systems(
+            Update,
+            (
+                debug_actions,
+                show_debug_panel.run_if(resource_changed::<TerrainDebug>),
+            ),
+        );
+        app.add_observer(toggle_terrain_debug);
+    }
+}
+
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct TerrainDebug {
+    pub enabled: bool,
+}
+
+/// Flips the overlay on or off
+#[derive(Event)]
+pub struct ToggleTerrainDebug;
+
+/// Debug overlay keys, global like the console's
+#[derive(Actionlike, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+pub enum DebugAction {
+    ToggleTerrainDebug,
+}
+
+impl DebugAction {
+    pub fn default_input_map() -> InputMap<Self> {
+        InputMap::default()
+            .with(DebugAction::ToggleTerrainDebug, KeyCode::F3)
+            .with(DebugAction::ToggleTerrainDebug, GamepadButton::DPadDown)
+    }
+}
+
+/// Where a weave chunk is between being wanted and being drawn
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkState {
+    /// In the area but not sent to the gpu yet
+    Requested,
+    /// Waiting on density layers to come back
+    InFlight,
+    Meshed,
+}
+
+impl ChunkState {
+    fn color(self) -> Color {
+        match self {
+            ChunkState::Requested => css::GRAY.into(),
+            ChunkState::InFlight => css::ORANGE.into(),
+            ChunkState::Meshed => css::LIME.into(),
+        }
+    }
+}
+
+fn lod_color(lod: LodLevel) -> Color {
+    match lod {
+        LodLevel::High => css::AQUA.into(),
+        LodLevel::Medium => css::YELLOW.into(),
+        LodLevel::Low => css::RED.into(),
+    }
+}
+
+#[derive(Component)]
+struct TerrainDebugPanel;
+
+fn debug_enabled(debug: Res<TerrainDebug>) -> bool {
+    debug.enabled
+}
+
+fn backend_is(backend: SurfaceBackend) -> impl Fn(Res<SurfaceBackend>) -> bool {
+    move |current: Res<SurfaceBackend>| *current == backend
+}
+
+fn toggle_terrain_debug(_trigger: On<ToggleTerrainDebug>, mut debug: ResMut<TerrainDebug>) {
+    debug.enabled = !debug.enabled;
+}
+
+fn debug_actions(mut commands: Commands, actions: Res<ActionState<DebugAction>>) {
+    if actions.just_pressed(&DebugAction::ToggleTerrainDebug) {
+        commands.trigger(ToggleTerrainDebug);
+    }
+}
+
+fn chunk_state(position: IVec3, chunks: &TerrainChunks, layers: &DensityLayers) -> ChunkState {
+    if chunks.contains_key(&position) {
+        ChunkState::Meshed
+    } else if layers.is_pending(position) {
+        ChunkState::InFlight
+    } else {
+        ChunkState::Requested
+    }
+}
+
+/// Chunk borders coloured by state, with a cross in the middle for the lod
+fn draw_marching_chunks(
+    mut gizmos: Gizmos,
+    area: Res<LoadedArea>,
+    chunks: Res<TerrainChunks>,
+    layers: Res<DensityLayers>,
+) {
+    let size = CHUNK_SIZE as f32;
+    for (position, lod) in area.iter() {
+        let center = chunk_translation(position) + Vec3::splat(size / 2.0);
+        let state = chunk_state(position, &chunks, &layers);
+        gizmos.cuboid(
+            Transform::from_translation(center).with_scale(Vec3::splat(size)),
+            state.color(),
+        );
+        gizmos.cross(
+            Isometry3d::from_translation(center),
+            size / 4.0,
+            lod_color(lod),
+        );
+    }
+}
+
+/// Voxel chunks are columns, so just their footprint at sea level
+fn draw_voxel_chunks(
+    mut gizmos: Gizmos,
+    chunks: Query<(&Chunk, Has<Loading>, Has<Active>, Has<Dormant>)>,
+) {
+    let size = voxel_terrain::prelude::CHUNK_SIZE as f32;
+    for (chunk, loading, active, dormant) in chunks {
+        let color: Color = match (loading, active, dormant) {
+            (true, ..) => css::ORANGE.into(),
+            (_, true, _) => css::LIME.into(),
+            (.., true) => css::DARK_SLATE_GRAY.into(),
+            _ => css::GRAY.into(),
+        };
+        let center = chunk.as_vec2() * size;
+        gizmos.rect(
+            Isometry3d::new(
+                Vec3::new(center.x, 0.0, center.y),
+                Quat::from_rotation_x(std::f32::consts::FRAC_PI_2),
+            ),
+            Vec2::splat(size),
+            color,
+        );
+    }
+}
+
+fn draw_load_radius(
+    mut gizmos: Gizmos,
+    observers: Query<(&GlobalTransform, &AreaManaged), With<Observer>>,
+) {
+    for (transform, managed) in observers {
+        gizmos.circle(
+            Isometry3d::new(
+                transform.translation(),
+                Quat::from_rotation_x(std::f32::consts::FRAC_PI_2),
+            ),
+            (managed.render_distance * CHUNK_SIZE) as f32,
+            css::WHITE,
+        );
+    }
+}
+
+fn spawn_debug_panel(mut commands: Commands) {
+    commands.spawn((
+        Name::new("Terrain Debug Panel"),
+        TerrainDebugPanel,
+        Visibility::Hidden,
+        Node {
+            position_type: PositionType::Absolute,
+            top: px(8),
+            right: px(8),
+            padding: UiRect::all(px(6)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.5)),
+        Text::default(),
+        TextFont::from_font_size(14.0),
+    ));
+}
+
+fn show_debug_panel(
+    debug: Res<TerrainDebug>,
+    mut panel: Single<&mut Visibility, With<TerrainDebugPanel>>,
+) {
+    **panel = if debug.enabled {
+        Visibility::Visible
+    } else {
+        Visibility::Hidden
+    };
+}
+
+fn update_debug_panel(
+    mut panel: Single<&mut Text, With<TerrainDebugPanel>>,
+    backend: Res<SurfaceBackend>,
+    area: Res<LoadedArea>,
+    chunks: Res<TerrainChunks>,
+    layers: Res<DensityLayers>,
+    requests: Res<NoiseRequests>,
+    voxels: Query<(Has<Loading>, Has<Active>, Has<Dormant>), With<Chunk>>,
+) {
+    let text = match *backend {
+        SurfaceBackend::MarchingCubes => {
+            let (mut requested, mut in_flight, mut meshed) = (0, 0, 0);
+            for (position, _) in area.iter() {
+                match chunk_state(position, &chunks, &layers) {
+                    ChunkState::Requested => requested += 1,
+                    ChunkState::InFlight => in_flight += 1,
+                    ChunkState::Meshed => meshed += 1,
+                }
+            }
+            format!(
+                "Marching cubes\nRequested: {requested}\nIn flight: {in_flight}\nMeshed: {meshed}\nGpu queue: {}",
+                requests.0.len()
+            )
+        }
+        SurfaceBackend::Voxel => {
+            let (mut loading, mut active, mut dormant) = (0, 0, 0);
+            for (is_loading, is_active, is_dormant) in voxels {
+                loading += is_loading as u32;
+                active += is_active as u32;
+                dormant += is_dormant as u32;
+            }
+            format!("Voxel\nLoading: {loading}\nActive: {active}\nDormant: {dormant}")
+        }
+    };
+    panel.0 = text;
+}
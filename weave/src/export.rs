@@ -0,0 +1,235 @@
+// Dumps every visible chunk into one obj with a matching mtl next to it
+// Both backends end up as plain meshes in world space so anything can open the result
+use crate::marching_cubes::TerrainChunk;
+use bevy::{
+    asset::AssetId,
+    mesh::{Indices, VertexAttributeValues},
+    platform::collections::HashMap,
+    prelude::*,
+    tasks::IoTaskPool,
+};
+use std::{fmt::Write, path::PathBuf};
+use voxel_terrain::prelude::{Chunk, Dormant};
+
+pub struct ExportPlugin;
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_observer(export_terrain);
+    }
+}
+
+/// Writes the visible terrain to `path` as an obj, the mtl goes beside it with the same name
+#[derive(Event, Clone, Debug)]
+pub struct ExportTerrain {
+    pub path: PathBuf,
+}
+
+fn export_terrain(
+    trigger: On<ExportTerrain>,
+    meshes: Res<Assets<Mesh>>,
+    materials: Res<Assets<StandardMaterial>>,
+    chunks: Query<
+        (
+            &Mesh3d,
+            &GlobalTransform,
+            &InheritedVisibility,
+            Option<&MeshMaterial3d<StandardMaterial>>,
+            Option<&Name>,
+        ),
+        (Or<(With<TerrainChunk>, With<Chunk>)>, Without<Dormant>),
+    >,
+) {
+    let obj_path = trigger.event().path.with_extension("obj");
+    let mtl_path = obj_path.with_extension("mtl");
+    let mtl_name = mtl_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut obj = format!("mtllib {mtl_name}\n");
+    let mut used_materials: HashMap<AssetId<StandardMaterial>, String> = HashMap::new();
+    // Obj indices are global and start at one
+    let mut offset = 1;
+    let mut exported = 0;
+
+    for (index, (mesh, transform, visibility, material, name)) in chunks.iter().enumerate() {
+        // Dormant voxel columns keep their mesh around but aren't part of the world anymore
+        if !visibility.get() {
+            continue;
+        }
+        let Some(mesh) = meshes.get(&mesh.0) else {
+            continue;
+        };
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            continue;
+        };
+        if positions.is_empty() {
+            continue;
+        }
+        let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(normals)) => Some(normals),
+            _ => None,
+        };
+        let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uvs)) => Some(uvs),
+            _ => None,
+        };
+
+        let object = name.map_or_else(|| format!("chunk_{index}"), |name| name.replace(' ', "_"));
+        let _ = writeln!(obj, "o {object}");
+        if let Some(material) = material {
+            let next = used_materials.len();
+            let material_name = used_materials
+                .entry(material.id())
+                .or_insert_with(|| format!("terrain_{next}"));
+            let _ = writeln!(obj, "usemtl {material_name}");
+        }
+
+        for position in positions {
+            let p = transform.transform_point(Vec3::from_array(*position));
+            let _ = writeln!(obj, "v {} {} {}", p.x, p.y, p.z);
+        }
+        for normal in normals.into_iter().flatten() {
+            let n = transform
+                .affine()
+                .transform_vector3(Vec3::from_array(*normal))
+                .normalize_or_zero();
+            let _ = writeln!(obj, "vn {} {} {}", n.x, n.y, n.z);
+        }
+        for uv in uvs.into_iter().flatten() {
+            // Obj has v going up, bevy has it going down
+            let _ = writeln!(obj, "vt {} {}", uv[0], 1.0 - uv[1]);
+        }
+
+        let vertex = |i: u32| {
+            let i = i as usize + offset;
+            match (normals.is_some(), uvs.is_some()) {
+                (true, true) => format!("{i}/{i}/{i}"),
+                (true, false) => format!("{i}//{i}"),
+                (false, true) => format!("{i}/{i}"),
+                (false, false) => format!("{i}"),
+            }
+        };
+        let indices: Vec<u32> = match mesh.indices() {
+            Some(Indices::U32(indices)) => indices.clone(),
+            Some(Indices::U16(indices)) => indices.iter().map(|i| *i as u32).collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        for triangle in indices.chunks_exact(3) {
+            let _ = writeln!(
+                obj,
+                "f {} {} {}",
+                vertex(triangle[0]),
+                vertex(triangle[1]),
+                vertex(triangle[2])
+            );
+        }
+
+        offset += positions.len();
+        exported += 1;
+    }
+
+    let mut mtl = String::new();
+    for (id, material_name) in &used_materials {
+        let Some(material) = materials.get(*id) else {
+            continue;
+        };
+        let color = material.base_color.to_linear();
+        let _ = writeln!(mtl, "newmtl {material_name}");
+        let _ = writeln!(mtl, "Kd {} {} {}", color.red, color.green, color.blue);
+        let _ = writeln!(mtl, "d {}", color.alpha);
+    }
+
+    info!("Exporting {exported} chunks to {}", obj_path.display());
+    // Big terrains make big files, keep the disk off the main thread
+    IoTaskPool::get()
+        .spawn(async move {
+            if let Err(error) = std::fs::write(&obj_path, obj) {
+                error!("Couldn't write {}: {error}", obj_path.display());
+            }
+            if let Err(error) = std::fs::write(&mtl_path, mtl) {
+                error!("Couldn't write {}: {error}", mtl_path.display());
+            }
+        })
+        .detach();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn only_visible_chunks_are_exported() {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin::default(),
+            TransformPlugin,
+            ExportPlugin,
+        ));
+        app.init_asset::<Mesh>().init_asset::<StandardMaterial>();
+
+        let triangle = Triangle3d::new(Vec3::X, Vec3::Y, Vec3::Z);
+        let mesh = app.world_mut().resource_mut::<Assets<Mesh>>().add(triangle);
+        app.world_mut().spawn((
+            TerrainChunk(IVec3::ZERO),
+            Mesh3d(mesh.clone()),
+            Transform::from_xyz(10.0, 0.0, 0.0),
+            InheritedVisibility::VISIBLE,
+            Name::new("Visible chunk"),
+        ));
+        // Visibility hasn't caught up with it yet, being dormant is enough
+        app.world_mut().spawn((
+            Chunk::new(1, 0),
+            Dormant,
+            Mesh3d(mesh.clone()),
+            InheritedVisibility::VISIBLE,
+        ));
+        app.world_mut().spawn((
+            TerrainChunk(IVec3::X),
+            Mesh3d(mesh),
+            InheritedVisibility::HIDDEN,
+        ));
+        app.update();
+
+        let path = std::env::temp_dir().join(format!("weave_export_{}", std::process::id()));
+        app.world_mut()
+            .trigger(ExportTerrain { path: path.clone() });
+        let (obj_path, mtl_path) = (path.with_extension("obj"), path.with_extension("mtl"));
+        for _ in 0..500 {
+            if mtl_path.exists() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let obj = std::fs::read_to_string(&obj_path).unwrap();
+        let _ = std::fs::remove_file(obj_path);
+        let _ = std::fs::remove_file(mtl_path);
+
+        let objects: Vec<_> = obj.lines().filter(|line| line.starts_with("o ")).collect();
+        assert_eq!(objects, ["o Visible_chunk"]);
+        // Reading it back gives the triangle where it was in the world
+        let vertices: Vec<Vec3> = obj
+            .lines()
+            .filter_map(|line| line.strip_prefix("v "))
+            .map(|line| {
+                let v: Vec<f32> = line.split(' ').map(|n| n.parse().unwrap()).collect();
+                Vec3::new(v[0], v[1], v[2])
+            })
+            .collect();
+        let offset = Vec3::new(10.0, 0.0, 0.0);
+        assert_eq!(
+            vertices,
+            [
+                triangle.vertices[0] + offset,
+                triangle.vertices[1] + offset,
+                triangle.vertices[2] + offset
+            ]
+        );
+        assert_eq!(obj.lines().filter(|line| line.starts_with("f ")).count(), 1);
+    }
+}
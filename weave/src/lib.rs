@@ -1,29 +1,60 @@
 // This crate is meant to represent the composition of the world
 // The *weave* so to speak, currently there shuold be voxel and marching
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-mod area;
-mod marching_cubes;
-mod terrain;
-mod voxel;
+pub mod area;
+pub mod debug;
+pub mod export;
+pub mod marching_cubes;
+pub mod planet;
+pub mod preset;
+pub mod terrain;
+pub mod voxel;
 
 /// Adds all weave implementations
 /// This includes voxel and marching and their respective terrains
-
 pub struct WeavePlugin;
 
 impl Plugin for WeavePlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, hello);
+        app.init_resource::<SurfaceBackend>();
 
         app.add_plugins((
+            area::AreaPlugin,
             marching_cubes::MarchingCubesPlugin,
             voxel::VoxelPlugin,
             terrain::field_compute::NoiseFieldComputePlugin,
+            terrain::DensityLayersPlugin,
+            preset::TerrainPresetPlugin,
+            export::ExportPlugin,
         ));
     }
 }
 
+/// What loaded chunks get built as, they all share the same observer, area and noise
+/// Swapping it reloads the whole area with the new backend
+#[derive(Resource, Reflect, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[reflect(Resource)]
+pub enum SurfaceBackend {
+    #[default]
+    MarchingCubes,
+    /// Blocky columns from voxel_terrain
+    Voxel,
+}
+
+pub mod prelude {
+    pub use crate::area::{AreaManaged, LodLevel, Observer};
+    pub use crate::debug::{DebugAction, TerrainDebug, TerrainDebugPlugin, ToggleTerrainDebug};
+    pub use crate::export::ExportTerrain;
+    pub use crate::marching_cubes::{ApplyBrush, Brush, Mesher, RedoBrush, UndoBrush};
+    pub use crate::planet::{Planet, PlanetPlugin};
+    pub use crate::preset::{ActiveTerrainPreset, TerrainPreset};
+    pub use crate::terrain::graph::{DensityGraph, DensityGraphPlugin, DensityNode};
+    pub use crate::{SurfaceBackend, WeavePlugin};
+}
+
 fn hello() {
     info!("Hello from weave! we up btw !")
 }
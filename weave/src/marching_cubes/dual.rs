@@ -0,0 +1,265 @@
+// Dual meshers, one vertex per cell instead of one per edge
+// Quads get built around every edge the surface crosses, joining the four cells that share it
+use super::mesh::{FIELD_SIZE, ISOLEVEL};
+use super::mesher::{ChunkSamples, IsosurfaceMesher};
+use super::tables::{CORNER_OFFSETS, CORNER_POINT_INDICES};
+use bevy::{
+    asset::RenderAssetUsages,
+    mesh::{Indices, PrimitiveTopology},
+    prelude::*,
+};
+
+/// Weight pulling the qef towards the mass point, stops flat areas from drifting around
+const QEF_BIAS: f32 = 0.05;
+
+/// Vertex at the average of the edge crossings
+pub struct SurfaceNets;
+
+impl IsosurfaceMesher for SurfaceNets {
+    fn mesh(&self, samples: &ChunkSamples) -> Mesh {
+        dual_mesh(samples, |crossings, _, _| mass_point(crossings))
+    }
+
+    fn needs_neighbours(&self) -> bool {
+        true
+    }
+}
+
+/// Vertex where the crossings tangent planes meet
+pub struct DualContouring;
+
+impl IsosurfaceMesher for DualContouring {
+    fn mesh(&self, samples: &ChunkSamples) -> Mesh {
+        dual_mesh(samples, solve_qef)
+    }
+
+    fn needs_neighbours(&self) -> bool {
+        true
+    }
+}
+
+/// Where the surface cuts a cell edge
+struct Crossing {
+    position: Vec3,
+    normal: Vec3,
+}
+
+fn mass_point(crossings: &[Crossing]) -> Vec3 {
+    crossings
+        .iter()
+        .map(|crossing| crossing.position)
+        .sum::<Vec3>()
+        / crossings.len() as f32
+}
+
+/// Least squares point closest to every crossings plane, kept inside the cell
+fn solve_qef(crossings: &[Crossing], min: Vec3, max: Vec3) -> Vec3 {
+    let mass = mass_point(crossings);
+    let mut ata = Mat3::from_diagonal(Vec3::splat(QEF_BIAS));
+    let mut atb = mass * QEF_BIAS;
+    for Crossing { position, normal } in crossings {
+        ata += Mat3::from_cols(normal * normal.x, normal * normal.y, normal * normal.z);
+        atb += normal * normal.dot(*position);
+    }
+
+    let solved = ata.inverse() * atb;
+    if solved.is_finite() {
+        solved.clamp(min, max)
+    } else {
+        mass
+    }
+}
+
+/// Places a vertex in every cell the surface passes through with `place`, then stitches them into quads.
+///
+/// Cells run one past the chunk into the +x, +y and +z neighbours so the seams close up,
+/// edges on the min faces belong to the chunk before this one.
+fn dual_mesh(samples: &ChunkSamples, place: impl Fn(&[Crossing], Vec3, Vec3) -> Vec3) -> Mesh {
+    let step = samples.step as i32;
+    let cells = (FIELD_SIZE as i32 - 1) / step;
+    let width = cells + 1;
+    let cell_index = |cell: IVec3| (cell.x + cell.y * width + cell.z * width * width) as usize;
+
+    // Faces next to coarser chunks read like the coarse grid so the seams line up
+    let density = |grid: IVec3| samples.get_seamless(grid * step);
+    let gradient = |grid: IVec3| {
+        let difference = |axis: IVec3| density(grid + axis) - density(grid - axis);
+        Vec3::new(
+            difference(IVec3::X),
+            difference(IVec3::Y),
+            difference(IVec3::Z),
+        ) / (2 * step) as f32
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut cell_vertices = vec![None; (width * width * width) as usize];
+    let mut crossings = Vec::with_capacity(12);
+
+    for z in 0..=cells {
+        for y in 0..=cells {
+            for x in 0..=cells {
+                let cell = IVec3::new(x, y, z);
+                let corners = CORNER_OFFSETS.map(|offset| cell + offset.as_ivec3());
+                let densities = corners.map(density);
+
+                crossings.clear();
+                for edge in CORNER_POINT_INDICES {
+                    let (a, b) = (edge[0] as usize, edge[1] as usize);
+                    let (start, end) = (densities[a], densities[b]);
+                    if (start > ISOLEVEL) == (end > ISOLEVEL) {
+                        continue;
+                    }
+                    let t = (ISOLEVEL - start) / (end - start);
+                    // Density rises into the solid so the surface faces down the gradient
+                    let normal = -gradient(corners[a])
+                        .lerp(gradient(corners[b]), t)
+                        .normalize_or_zero();
+                    crossings.push(Crossing {
+                        position: corners[a].as_vec3().lerp(corners[b].as_vec3(), t) * step as f32,
+                        normal,
+                    });
+                }
+                if crossings.is_empty() {
+                    continue;
+                }
+
+                let min = cell.as_vec3() * step as f32;
+                let vertex = place(&crossings, min, min + step as f32);
+                let normal = crossings
+                    .iter()
+                    .map(|crossing| crossing.normal)
+                    .sum::<Vec3>()
+                    .normalize_or(Vec3::Y);
+
+                cell_vertices[cell_index(cell)] = Some(positions.len() as u32);
+                positions.push(vertex.to_array());
+                normals.push(normal.to_array());
+            }
+        }
+    }
+
+    let mut indices = Vec::new();
+    let units = [IVec3::X, IVec3::Y, IVec3::Z];
+    for a in 0..3 {
+        let (b, c) = ((a + 1) % 3, (a + 2) % 3);
+        for z in 0..=cells {
+            for y in 0..=cells {
+                for x in 0..=cells {
+                    let grid = IVec3::new(x, y, z);
+                    if grid[a] == cells || grid[b] == 0 || grid[c] == 0 {
+                        continue;
+                    }
+
+                    let (start, end) = (density(grid), density(grid + units[a]));
+                    if (start > ISOLEVEL) == (end > ISOLEVEL) {
+                        continue;
+                    }
+
+                    let quad = [
+                        grid - units[b] - units[c],
+                        grid - units[c],
+                        grid,
+                        grid - units[b],
+                    ]
+                    .map(|cell| cell_vertices[cell_index(cell)]);
+                    let [Some(v0), Some(v1), Some(v2), Some(v3)] = quad else {
+                        continue;
+                    };
+
+                    // Solid at the start means the surface faces along the axis
+                    if start > ISOLEVEL {
+                        indices.extend_from_slice(&[v0, v1, v2, v0, v2, v3]);
+                    } else {
+                        indices.extend_from_slice(&[v0, v2, v1, v0, v3, v2]);
+                    }
+                }
+            }
+        }
+    }
+
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+    .with_inserted_indices(Indices::U32(indices))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::{mesh::VertexAttributeValues, platform::collections::HashMap};
+
+    const CENTER: Vec3 = Vec3::splat(8.0);
+    const RADIUS: f32 = 5.0;
+
+    fn sphere() -> Vec<f32> {
+        let size = FIELD_SIZE as i32;
+        (0..size * size * size)
+            .map(|i| {
+                let p = IVec3::new(i % size, i / size % size, i / (size * size));
+                RADIUS - p.as_vec3().distance(CENTER)
+            })
+            .collect()
+    }
+
+    fn attribute(mesh: &Mesh, id: impl Into<bevy::mesh::MeshVertexAttributeId>) -> Vec<Vec3> {
+        match mesh.attribute(id) {
+            Some(VertexAttributeValues::Float32x3(values)) => {
+                values.iter().copied().map(Vec3::from).collect()
+            }
+            _ => panic!("dual meshes have positions and normals"),
+        }
+    }
+
+    fn check_sphere(mesher: &dyn IsosurfaceMesher) {
+        let field = sphere();
+        let mesh = mesher.mesh(&ChunkSamples::new(&field));
+        let positions = attribute(&mesh, Mesh::ATTRIBUTE_POSITION);
+        let normals = attribute(&mesh, Mesh::ATTRIBUTE_NORMAL);
+        let indices: Vec<u32> = mesh.indices().unwrap().iter().map(|i| i as u32).collect();
+        assert!(!indices.is_empty());
+
+        for (position, normal) in positions.iter().zip(&normals) {
+            let distance = position.distance(CENTER);
+            assert!(
+                (distance - RADIUS).abs() < 0.5,
+                "vertex {distance} from the center"
+            );
+            assert!(normal.dot(*position - CENTER) > 0.0, "normal points inward");
+        }
+
+        // Closed means every edge is used once each way by the triangles either side of it
+        let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]];
+            for edge in [(a, b), (b, c), (c, a)] {
+                *edges.entry(edge).or_default() += 1;
+            }
+            // Wound so the front faces outward
+            let (pa, pb, pc) = (
+                positions[a as usize],
+                positions[b as usize],
+                positions[c as usize],
+            );
+            let facing = (pb - pa).cross(pc - pa);
+            assert!(facing.dot((pa + pb + pc) / 3.0 - CENTER) > 0.0);
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "edge {a}-{b} is doubled up");
+            assert_eq!(edges.get(&(b, a)), Some(&1), "hole along {a}-{b}");
+        }
+    }
+
+    #[test]
+    fn surface_nets_close_a_sphere() {
+        check_sphere(&SurfaceNets);
+    }
+
+    #[test]
+    fn dual_contouring_closes_a_sphere() {
+        check_sphere(&DualContouring);
+    }
+}
@@ -1,39 +1,241 @@
-use super::tables::*;
-use crate::terrain::{RequestComplete, field_compute::*};
-use bevy::{mesh::Indices, platform::collections::HashMap};
+use super::{
+    DensityField, MarchingTerrain, RemeshChunks, SculptedDensity, TerrainChunk, TerrainChunks,
+    TerrainRoot,
+    mesher::{ChunkSamples, IsosurfaceMesher, Mesher, neighbour_offset},
+    tables::*,
+};
+use crate::SurfaceBackend;
+use crate::area::{ChunkLodChanged, LoadedArea, LodLevel};
+use crate::terrain::{DensityComplete, field_compute::*};
+use crate::voxel::collider::ColliderReady;
+use bevy::{
+    mesh::{Indices, VertexAttributeValues},
+    platform::collections::HashMap,
+};
+
+/// Face neighbours in -x, +x, -y, +y, -z, +z order
+const FACES: [IVec3; 6] = [
+    IVec3::NEG_X,
+    IVec3::X,
+    IVec3::NEG_Y,
+    IVec3::Y,
+    IVec3::NEG_Z,
+    IVec3::Z,
+];
 
 pub fn recieve_mesh(
-    trigger: On<RequestComplete<super::NoiseParams>>,
+    trigger: On<DensityComplete>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut chunks: ResMut<TerrainChunks>,
+    root: Res<TerrainRoot>,
+    area: Res<LoadedArea>,
+    meshers: Query<&Mesher, With<MarchingTerrain>>,
+    mut sculpted: ResMut<SculptedDensity>,
+    backend: Res<SurfaceBackend>,
+    fields: Query<&DensityField>,
 ) {
     info!("Received terrain noise data");
-    let _position = trigger.event().position;
-    let mesh_handle = meshes.add(construct_mesh(trigger.event().data.to_owned()));
+    let position = trigger.event().position;
+    // The observer moved on or the backend got swapped before the density came back
+    if *backend != SurfaceBackend::MarchingCubes || !area.contains(position) {
+        return;
+    }
+
+    // Edits win over whatever got generated
+    let data = sculpted.density_for(position, &trigger.event().data);
+    let mesher = meshers.get(root.entity).copied().unwrap_or_default();
+    let field_at = |chunk: IVec3| {
+        let entity = chunks.get(&chunk)?;
+        fields.get(*entity).ok().map(|field| field.0.as_slice())
+    };
+    let mesh = build_mesh(mesher, &area, position, &data, field_at);
+    let collider = collider_ready(position, &mesh);
+    let mesh_handle = meshes.add(mesh);
+
+    // Dual meshers reach into this chunk from the ones before it, only the ones that read
+    // something different now need doing again
+    let stale_neighbours: Vec<IVec3> = if mesher.mesher().needs_neighbours() {
+        (0..7)
+            .map(neighbour_offset)
+            .filter(|&offset| {
+                let neighbour = position - offset;
+                let reach = 2 * area.lod(neighbour).unwrap_or_default().step() as i32;
+                field_at(position)
+                    .is_none_or(|previous| shared_samples_changed(previous, &data, offset, reach))
+            })
+            .map(|offset| position - offset)
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // Regenerated chunks keep their entity, only the mesh gets swapped
+    if let Some(&entity) = chunks.get(&position) {
+        commands
+            .entity(entity)
+            .insert((Mesh3d(mesh_handle), DensityField(data)));
+    } else {
+        let entity = commands
+            .spawn((
+                Name::new(format!("Terrain Chunk {position}")),
+                TerrainChunk(position),
+                DensityField(data),
+                Mesh3d(mesh_handle),
+                MeshMaterial3d(root.material.clone()),
+                Transform::from_translation(super::chunk_translation(position)),
+                ChildOf(root.entity),
+            ))
+            .id();
+        chunks.insert(position, entity);
+    }
+
+    // Both of these look the chunk up, so they go after it exists
+    commands.trigger(collider);
+    if !stale_neighbours.is_empty() {
+        commands.trigger(RemeshChunks::new(stale_neighbours));
+    }
+}
 
-    commands.spawn((
-        Name::new("Terrain Mesh"),
-        Mesh3d(mesh_handle),
-        MeshMaterial3d(materials.add(StandardMaterial::from_color(Color::srgb(1.0, 1.0, 1.0)))),
+/// Whether any sample the chunk at `-offset` reads from this one differs between the fields, it
+/// sees the band `reach` samples deep along each axis it's offset on
+fn shared_samples_changed(previous: &[f32], data: &[f32], offset: IVec3, reach: i32) -> bool {
+    let size = FIELD_SIZE as i32;
+    previous
+        .iter()
+        .zip(data)
+        .enumerate()
+        .any(|(index, (previous, density))| {
+            let index = index as i32;
+            let local = IVec3::new(index % size, index / size % size, index / (size * size));
+            let shared = offset.cmpeq(IVec3::ZERO) | local.cmple(IVec3::splat(reach));
+            shared.all() && previous != density
+        })
+}
+
+/// A chunk that changed lod gets re-meshed, its face neighbours too since their transition faces depend on it
+pub fn remesh_lod(trigger: On<ChunkLodChanged>, mut commands: Commands) {
+    let position = trigger.event().position;
+    commands.trigger(RemeshChunks::new(
+        FACES
+            .into_iter()
+            .chain([IVec3::ZERO])
+            .map(|face| position + face)
+            .collect(),
     ));
 }
 
-const FIELD_SIZE: u32 = 17;
-const ISOLEVEL: f32 = 0.0;
+/// A terrain's [`Mesher`] changed or went back to the default
+pub fn remesh_all(
+    mut commands: Commands,
+    chunks: Res<TerrainChunks>,
+    changed: Query<(), (Changed<Mesher>, With<MarchingTerrain>)>,
+    mut removed: RemovedComponents<Mesher>,
+) {
+    if changed.is_empty() && removed.read().count() == 0 {
+        return;
+    }
+    commands.trigger(RemeshChunks::new(chunks.keys().copied().collect()));
+}
+
+pub fn remesh_chunks(
+    trigger: On<RemeshChunks>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    chunks: Res<TerrainChunks>,
+    area: Res<LoadedArea>,
+    root: Res<TerrainRoot>,
+    meshers: Query<&Mesher, With<MarchingTerrain>>,
+    mut fields: Query<(&DensityField, &mut Mesh3d)>,
+) {
+    let mesher = meshers.get(root.entity).copied().unwrap_or_default();
+    let field_at = |chunk: IVec3| {
+        let entity = chunks.get(&chunk)?;
+        fields
+            .get(*entity)
+            .ok()
+            .map(|(field, _)| field.0.as_slice())
+    };
 
-pub fn construct_mesh(data: Vec<f32>) -> Mesh {
+    let rebuilt: Vec<_> = trigger
+        .event()
+        .positions
+        .iter()
+        .filter_map(|&position| {
+            let field = field_at(position)?;
+            let mesh = build_mesh(mesher, &area, position, field, field_at);
+            Some((position, chunks[&position], mesh))
+        })
+        .collect();
+
+    for (position, entity, mesh) in rebuilt {
+        commands.trigger(collider_ready(position, &mesh));
+        if let Ok((_, mut handle)) = fields.get_mut(entity) {
+            handle.0 = meshes.add(mesh);
+        }
+    }
+}
+
+fn collider_ready(coord: IVec3, mesh: &Mesh) -> ColliderReady {
+    let vertices = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        Some(VertexAttributeValues::Float32x3(positions)) => {
+            positions.iter().copied().map(Vec3::from).collect()
+        }
+        _ => Vec::new(),
+    };
+    let indices = match mesh.indices() {
+        Some(indices) => indices.iter().map(|index| index as u32).collect(),
+        None => Vec::new(),
+    };
+    ColliderReady {
+        coord,
+        vertices,
+        indices,
+    }
+}
+
+/// Meshes a chunk with the terrains mesher, `field_at` finds the density of other loaded chunks
+fn build_mesh<'a>(
+    mesher: Mesher,
+    area: &LoadedArea,
+    position: IVec3,
+    field: &'a [f32],
+    field_at: impl Fn(IVec3) -> Option<&'a [f32]>,
+) -> Mesh {
+    let step = area.lod(position).unwrap_or_default().step();
+    let samples = ChunkSamples {
+        field,
+        neighbours: std::array::from_fn(|index| field_at(position + neighbour_offset(index))),
+        step,
+        transitions: FACES.map(|face| area.lod(position + face).map_or(step, LodLevel::step)),
+    };
+    mesher.mesher().mesh(&samples)
+}
+
+pub(super) const FIELD_SIZE: u32 = 17;
+pub(super) const ISOLEVEL: f32 = 0.0;
+
+/// The original mesher
+pub struct MarchingCubes;
+
+impl IsosurfaceMesher for MarchingCubes {
+    fn mesh(&self, samples: &ChunkSamples) -> Mesh {
+        construct_mesh(samples)
+    }
+}
+
+/// Marching cubes over every `step`th sample.
+///
+/// `transitions` holds the step of each face neighbour. Where a neighbour is coarser its face samples
+/// get resampled from the coarse grid so both meshes put their vertices in the same spots along the
+/// shared edges. A skirt is hung off those faces to cover the slivers left inside the coarse cells.
+pub fn construct_mesh(samples: &ChunkSamples) -> Mesh {
+    let ChunkSamples { step, .. } = *samples;
     let mut vertices = Vec::new();
     let mut indices = Vec::new();
     let mut edge_vertices: HashMap<(u32, u32, u32, u8), u32> = HashMap::new();
 
-    let get_density = |x: u32, y: u32, z: u32| -> f32 {
-        if x >= FIELD_SIZE || y >= FIELD_SIZE || z >= FIELD_SIZE {
-            0.0
-        } else {
-            data[(x + y * FIELD_SIZE + z * FIELD_SIZE * FIELD_SIZE) as usize]
-        }
-    };
+    let get_density = |p: UVec3| samples.get_seamless(p.as_ivec3());
 
     let interpolate = |p1: Vec3, v1: f32, p2: Vec3, v2: f32| -> Vec3 {
         if (ISOLEVEL - v1).abs() < 0.0001 {
@@ -49,75 +251,52 @@ pub fn construct_mesh(data: Vec<f32>) -> Mesh {
         p1 + (p2 - p1) * t
     };
 
-    for x in 0..FIELD_SIZE - 1 {
-        for y in 0..FIELD_SIZE - 1 {
-            for z in 0..FIELD_SIZE - 1 {
-                let corners = [
-                    get_density(x, y, z),
-                    get_density(x + 1, y, z),
-                    get_density(x + 1, y + 1, z),
-                    get_density(x, y + 1, z),
-                    get_density(x, y, z + 1),
-                    get_density(x + 1, y, z + 1),
-                    get_density(x + 1, y + 1, z + 1),
-                    get_density(x, y + 1, z + 1),
-                ];
-
-                let mut cube_index = 0;
-                for i in 0..8 {
-                    if corners[i] < ISOLEVEL {
-                        cube_index |= 1 << i;
-                    }
-                }
+    for x in (0..FIELD_SIZE - 1).step_by(step as usize) {
+        for y in (0..FIELD_SIZE - 1).step_by(step as usize) {
+            for z in (0..FIELD_SIZE - 1).step_by(step as usize) {
+                let origin = UVec3::new(x, y, z);
+                let corners_pos = CORNER_OFFSETS.map(|offset| origin + offset * step);
+                let corners = corners_pos.map(get_density);
+
+                let cube_index = corners
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, density)| **density < ISOLEVEL)
+                    .fold(0, |index, (i, _)| index | (1 << i));
 
                 if cube_index == 0 || cube_index == 255 {
                     continue;
                 }
 
-                let edge_flag = EDGE_TABLE[cube_index as usize];
+                let edge_flag = EDGE_TABLE[cube_index];
                 let mut edge_list = [Vec3::ZERO; 12];
 
-                let corners_pos = [
-                    Vec3::new(x as f32, y as f32, z as f32),
-                    Vec3::new((x + 1) as f32, y as f32, z as f32),
-                    Vec3::new((x + 1) as f32, (y + 1) as f32, z as f32),
-                    Vec3::new(x as f32, (y + 1) as f32, z as f32),
-                    Vec3::new(x as f32, y as f32, (z + 1) as f32),
-                    Vec3::new((x + 1) as f32, y as f32, (z + 1) as f32),
-                    Vec3::new((x + 1) as f32, (y + 1) as f32, (z + 1) as f32),
-                    Vec3::new(x as f32, (y + 1) as f32, (z + 1) as f32),
-                ];
-
-                for i in 0..12 {
+                for (i, edge) in CORNER_POINT_INDICES.iter().enumerate() {
                     if edge_flag & (1 << i) != 0 {
-                        let edge = CORNER_POINT_INDICES[i];
-                        let p1 = corners_pos[edge[0] as usize];
-                        let p2 = corners_pos[edge[1] as usize];
-                        let v1 = corners[edge[0] as usize];
-                        let v2 = corners[edge[1] as usize];
-
-                        edge_list[i] = interpolate(p1, v1, p2, v2);
+                        let (a, b) = (edge[0] as usize, edge[1] as usize);
+                        edge_list[i] = interpolate(
+                            corners_pos[a].as_vec3(),
+                            corners[a],
+                            corners_pos[b].as_vec3(),
+                            corners[b],
+                        );
                     }
                 }
 
-                for i in (0..16).step_by(3) {
-                    if TRI_TABLE[cube_index as usize][i] == -1 {
+                for triangle in TRI_TABLE[cube_index].chunks(3) {
+                    if triangle[0] == -1 {
                         break;
                     }
 
-                    for j in 0..3 {
-                        let edge_idx = TRI_TABLE[cube_index as usize][i + j] as usize;
+                    for &edge in triangle {
+                        let edge_idx = edge as usize;
                         let vertex = edge_list[edge_idx];
 
                         let key = (x, y, z, edge_idx as u8);
-                        let idx = if let Some(&i) = edge_vertices.get(&key) {
-                            i
-                        } else {
-                            let idx = vertices.len() as u32;
+                        let idx = *edge_vertices.entry(key).or_insert_with(|| {
                             vertices.push([vertex.x, vertex.y, vertex.z]);
-                            edge_vertices.insert(key, idx);
-                            idx
-                        };
+                            vertices.len() as u32 - 1
+                        });
 
                         indices.push(idx);
                     }
@@ -126,6 +305,8 @@ pub fn construct_mesh(data: Vec<f32>) -> Mesh {
         }
     }
 
+    add_skirts(&mut vertices, &mut indices, samples);
+
     Mesh::new(
         bevy::render::render_resource::PrimitiveTopology::TriangleList,
         bevy::asset::RenderAssetUsages::default(),
@@ -133,3 +314,164 @@ pub fn construct_mesh(data: Vec<f32>) -> Mesh {
     .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, vertices)
     .with_inserted_indices(Indices::U32(indices))
 }
+
+/// Drops a double sided strip into the ground from every triangle edge lying on a face with a
+/// coarser neighbour, deep enough to cover the slivers
+fn add_skirts(vertices: &mut Vec<[f32; 3]>, indices: &mut Vec<u32>, samples: &ChunkSamples) {
+    let ChunkSamples {
+        step, transitions, ..
+    } = *samples;
+    let triangle_count = indices.len() / 3;
+    for (face, &neighbour) in transitions.iter().enumerate() {
+        if neighbour <= step {
+            continue;
+        }
+        let axis = face / 2;
+        let (plane, inward) = if face % 2 == 0 {
+            (0.0, 1.0)
+        } else {
+            ((FIELD_SIZE - 1) as f32, -1.0)
+        };
+        let on_face = |vertex: [f32; 3]| (vertex[axis] - plane).abs() < 0.0001;
+        // Density rises into the solid, so down the surface is up the gradient. A skirt pushed
+        // along the face normal would just lie flat on walls and leave their seams open
+        let down = |vertex: [f32; 3]| {
+            let p = Vec3::from(vertex).round().as_ivec3();
+            let reach = IVec3::splat(neighbour as i32);
+            let gradient = Vec3::new(
+                samples.get(p + reach * IVec3::X) - samples.get(p - reach * IVec3::X),
+                samples.get(p + reach * IVec3::Y) - samples.get(p - reach * IVec3::Y),
+                samples.get(p + reach * IVec3::Z) - samples.get(p - reach * IVec3::Z),
+            );
+            let mut fallback = Vec3::ZERO;
+            fallback[axis] = inward;
+            gradient.normalize_or(fallback) * neighbour as f32
+        };
+
+        for triangle in 0..triangle_count {
+            let corners = [
+                indices[triangle * 3],
+                indices[triangle * 3 + 1],
+                indices[triangle * 3 + 2],
+            ];
+            for i in 0..3 {
+                let (a, b) = (corners[i], corners[(i + 1) % 3]);
+                if !on_face(vertices[a as usize]) || !on_face(vertices[b as usize]) {
+                    continue;
+                }
+
+                let base = vertices.len() as u32;
+                for edge_vertex in [a, b] {
+                    let vertex = vertices[edge_vertex as usize];
+                    let lowered = Vec3::from(vertex) + down(vertex);
+                    vertices.push(lowered.to_array());
+                }
+                let (lowered_a, lowered_b) = (base, base + 1);
+                indices.extend_from_slice(&[a, b, lowered_b, a, lowered_b, lowered_a]);
+                indices.extend_from_slice(&[a, lowered_b, b, a, lowered_a, lowered_b]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marching_cubes::Mesher;
+
+    const FIELD_LEN: usize = (FIELD_SIZE * FIELD_SIZE * FIELD_SIZE) as usize;
+
+    fn field(density: impl Fn(IVec3) -> f32) -> Vec<f32> {
+        let size = FIELD_SIZE as i32;
+        (0..FIELD_LEN as i32)
+            .map(|i| density(IVec3::new(i % size, i / size % size, i / (size * size))))
+            .collect()
+    }
+
+    fn positions(mesh: &Mesh) -> Vec<Vec3> {
+        match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => {
+                positions.iter().copied().map(Vec3::from).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // Coarser on +x
+    fn next_to_coarse<'a>(field: &'a [f32]) -> ChunkSamples<'a> {
+        let mut samples = ChunkSamples::new(field);
+        samples.transitions[1] = 2;
+        samples
+    }
+
+    #[test]
+    fn skirts_hang_into_the_ground() {
+        let ground = field(|p| 8.5 - p.y as f32);
+        let plain = positions(&construct_mesh(&ChunkSamples::new(&ground)));
+        let skirted = positions(&construct_mesh(&next_to_coarse(&ground)));
+
+        // Flat ground resamples to the same thing, everything extra is skirt
+        assert_eq!(skirted[..plain.len()], plain[..]);
+        let skirt = &skirted[plain.len()..];
+        assert!(!skirt.is_empty());
+        for vertex in skirt {
+            assert!(
+                (vertex.x - 16.0).abs() < 1e-4,
+                "skirt left the face at {vertex}"
+            );
+            assert!((vertex.y - 6.5).abs() < 1e-4, "skirt at {vertex}");
+        }
+    }
+
+    #[test]
+    fn every_mesher_resamples_faces_next_to_coarser_chunks() {
+        // A ridge on every other sample of the +x face, which the coarse grid can't see
+        let bumpy = field(|p| 8.5 - p.y as f32 + if p.x == 16 && p.z % 2 == 1 { 3.0 } else { 0.0 });
+        let flat = field(|p| 8.5 - p.y as f32);
+        for mesher in [
+            Mesher::MarchingCubes,
+            Mesher::SurfaceNets,
+            Mesher::DualContouring,
+        ] {
+            let mut samples = ChunkSamples::new(&bumpy);
+            samples.neighbours[0] = Some(&flat);
+            let highest = |samples: &ChunkSamples| {
+                positions(&mesher.mesher().mesh(samples))
+                    .iter()
+                    .fold(f32::MIN, |highest, p| highest.max(p.y))
+            };
+            assert!(highest(&samples) > 10.0, "{mesher:?}");
+
+            samples.transitions[1] = 2;
+            assert!(highest(&samples) < 9.0, "{mesher:?}");
+        }
+    }
+
+    #[test]
+    fn only_changed_seams_count() {
+        let before = field(|p| 8.5 - p.y as f32);
+        let mut after = before.clone();
+        // Deep inside, none of the chunks before this one read that far
+        let far = (15 + 8 * FIELD_SIZE + 8 * FIELD_SIZE * FIELD_SIZE) as usize;
+        after[far] += 1.0;
+
+        assert!(!shared_samples_changed(&before, &before, IVec3::X, 2));
+        for offset in [IVec3::X, IVec3::Y, IVec3::Z, IVec3::ONE] {
+            assert!(!shared_samples_changed(&before, &after, offset, 2));
+        }
+        // Unless they're meshed coarse enough
+        assert!(shared_samples_changed(&before, &after, IVec3::Y, 8));
+
+        // The chunk below reads the whole of its top face, x included
+        let mut after = before.clone();
+        after[FIELD_SIZE as usize + 15] += 1.0;
+        assert!(shared_samples_changed(&before, &after, IVec3::Y, 2));
+        assert!(!shared_samples_changed(&before, &after, IVec3::X, 2));
+        assert!(!shared_samples_changed(
+            &before,
+            &after,
+            IVec3::new(1, 1, 0),
+            2
+        ));
+    }
+}
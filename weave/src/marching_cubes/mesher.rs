@@ -0,0 +1,135 @@
+// Isosurface extraction, every mesher reads the same density field so they can be swapped per terrain
+use super::{dual, mesh};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Turns a chunks density into a mesh, positive density is solid
+pub trait IsosurfaceMesher: Send + Sync {
+    fn mesh(&self, samples: &ChunkSamples) -> Mesh;
+
+    /// Whether the mesh reaches into the fields of the neighbours on the +x, +y and +z side,
+    /// chunks get re-meshed when those neighbours show up
+    fn needs_neighbours(&self) -> bool {
+        false
+    }
+}
+
+/// Which mesher a terrain uses, put it on the [`MarchingTerrain`](super::MarchingTerrain) root.
+/// Without one it's the default, changing it re-meshes every loaded chunk
+#[derive(
+    Component, Reflect, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[reflect(Component)]
+pub enum Mesher {
+    /// Smooth, but slivers and rounds off sharp features
+    #[default]
+    MarchingCubes,
+    /// Even quads, smooth everywhere
+    SurfaceNets,
+    /// Places vertices with a qef so cliffs and corners stay sharp
+    DualContouring,
+}
+
+impl Mesher {
+    pub fn mesher(self) -> &'static dyn IsosurfaceMesher {
+        match self {
+            Mesher::MarchingCubes => &mesh::MarchingCubes,
+            Mesher::SurfaceNets => &dual::SurfaceNets,
+            Mesher::DualContouring => &dual::DualContouring,
+        }
+    }
+}
+
+/// Offset of each neighbour in [`ChunkSamples::neighbours`], the bits of `index + 1` are the x, y and z steps
+pub fn neighbour_offset(index: usize) -> IVec3 {
+    let bits = index as i32 + 1;
+    IVec3::new(bits & 1, bits >> 1 & 1, bits >> 2 & 1)
+}
+
+/// A chunks density field along with what a mesher needs to know about its neighbours
+pub struct ChunkSamples<'a> {
+    pub field: &'a [f32],
+    /// Fields of every neighbour on the +x, +y and +z side if they're loaded, edges and the corner
+    /// included, see [`neighbour_offset`]
+    pub neighbours: [Option<&'a [f32]>; 7],
+    /// Distance between the samples the mesh is built from
+    pub step: u32,
+    /// Step of each face neighbour in -x, +x, -y, +y, -z, +z order
+    pub transitions: [u32; 6],
+}
+
+impl<'a> ChunkSamples<'a> {
+    pub fn new(field: &'a [f32]) -> Self {
+        Self {
+            field,
+            neighbours: [None; 7],
+            step: 1,
+            transitions: [1; 6],
+        }
+    }
+
+    /// Density at a sample coordinate, reading past the chunk's max faces, edges and corner from
+    /// whichever neighbour is there and clamping everywhere else
+    pub fn get(&self, position: IVec3) -> f32 {
+        let last = mesh::FIELD_SIZE as i32 - 1;
+        let position = position.max(IVec3::ZERO);
+
+        let over = position.cmpgt(IVec3::splat(last)).bitmask() as usize;
+        if over != 0
+            && let Some(field) = self.neighbours[over - 1]
+        {
+            let local = position - neighbour_offset(over - 1) * last;
+            return index(field, local.min(IVec3::splat(last)));
+        }
+
+        index(self.field, position.min(IVec3::splat(last)))
+    }
+
+    /// Like [`get`](Self::get), but samples on a face next to a coarser chunk are resampled from
+    /// its grid so both meshes cross the surface in the same spots along the seam
+    pub fn get_seamless(&self, position: IVec3) -> f32 {
+        let last = mesh::FIELD_SIZE as i32 - 1;
+        // Past the chunk the face belongs to whoever is there
+        if position.cmplt(IVec3::ZERO).any() || position.cmpgt(IVec3::splat(last)).any() {
+            return self.get(position);
+        }
+
+        // The coarsest neighbour whose face this sample sits on
+        let mut coarse = self.step as i32;
+        let mut face_axis = None;
+        for (face, &neighbour) in self.transitions.iter().enumerate() {
+            let axis = face / 2;
+            let plane = if face % 2 == 0 { 0 } else { last };
+            if neighbour as i32 > coarse && position[axis] == plane {
+                coarse = neighbour as i32;
+                face_axis = Some(axis);
+            }
+        }
+        let Some(axis) = face_axis else {
+            return self.get(position);
+        };
+
+        // Bilinear over the face from the neighbours samples
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut low = position;
+        low[u] = position[u] / coarse * coarse;
+        low[v] = position[v] / coarse * coarse;
+        let mut high_u = low;
+        high_u[u] = (low[u] + coarse).min(last);
+        let mut high_v = low;
+        high_v[v] = (low[v] + coarse).min(last);
+        let mut high = high_u;
+        high[v] = high_v[v];
+
+        let tu = (position[u] - low[u]) as f32 / coarse as f32;
+        let tv = (position[v] - low[v]) as f32 / coarse as f32;
+        let bottom = self.get(low).lerp(self.get(high_u), tu);
+        let top = self.get(high_v).lerp(self.get(high), tu);
+        bottom.lerp(top, tv)
+    }
+}
+
+fn index(field: &[f32], position: IVec3) -> f32 {
+    let size = mesh::FIELD_SIZE as i32;
+    field[(position.x + position.y * size + position.z * size * size) as usize]
+}
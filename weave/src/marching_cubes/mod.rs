@@ -1,24 +1,93 @@
-use crate::terrain::*;
-use bevy::prelude::*;
+use crate::SurfaceBackend;
+use crate::area::{CHUNK_SIZE, LoadChunk, UnloadChunk};
+use crate::terrain::{
+    graph::{DensityGraph, DensityNode, NoiseNode},
+    *,
+};
+use bevy::{asset::uuid_handle, platform::collections::HashMap, prelude::*};
+use serde::{Deserialize, Serialize};
 
+mod dual;
 mod mesh;
+mod mesher;
+mod sculpt;
 mod tables;
 
+pub use dual::{DualContouring, SurfaceNets};
+pub use mesh::{MarchingCubes, construct_mesh};
+pub use mesher::{ChunkSamples, IsosurfaceMesher, Mesher, neighbour_offset};
+pub use sculpt::{ApplyBrush, Brush, BrushHistory, RedoBrush, SculptedDensity, UndoBrush};
+
 pub struct MarchingCubesPlugin;
 
 impl Plugin for MarchingCubesPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(TerrainNoisePlugin(NoiseParams::default()));
-        app.add_systems(Startup, request_area);
+        app.add_plugins((
+            TerrainNoisePlugin(NoiseParams::default()),
+            sculpt::SculptPlugin,
+        ));
+        app.init_resource::<TerrainChunks>();
+        app.add_systems(Startup, spawn_terrain_root);
+        app.add_systems(Update, (compile_ground, mesh::remesh_all));
         app.add_observer(mesh::recieve_mesh);
+        app.add_observer(regenerate_chunks);
+        app.add_observer(mesh::remesh_lod);
+        app.add_observer(mesh::remesh_chunks);
+        app.add_observer(load_chunk);
+        app.add_observer(unload_chunk);
+    }
+}
+
+/// The chunk a terrain mesh was generated for
+#[derive(Component, Reflect, Debug, Clone, Copy, Deref)]
+pub struct TerrainChunk(pub IVec3);
+
+/// Every chunk mesh is a child of this, it stays at the origin so chunk transforms are world space
+#[derive(Component)]
+pub struct MarchingTerrain;
+
+/// The root chunks get parented to and the material they all share
+#[derive(Resource)]
+pub struct TerrainRoot {
+    pub entity: Entity,
+    pub material: Handle<StandardMaterial>,
+}
+
+/// The density a chunk was meshed from, kept around so lod changes can re-mesh without the gpu
+#[derive(Component, Deref)]
+pub struct DensityField(pub Vec<f32>);
+
+/// Chunk entities by coordinate
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct TerrainChunks(pub HashMap<IVec3, Entity>);
+
+/// Re-meshes chunks from their stored density, nothing new gets generated
+#[derive(Event)]
+pub struct RemeshChunks {
+    pub positions: Vec<IVec3>,
+}
+
+impl RemeshChunks {
+    pub fn new(positions: Vec<IVec3>) -> Self {
+        Self { positions }
     }
 }
 
-#[derive(Resource, Clone, Reflect)]
+/// Re-requests every meshed chunk, used when the noise changes
+#[derive(Event)]
+pub struct RegenerateChunks;
+
+/// The ground compiled from [`NoiseParams`], rebuilt whenever they change
+pub const GROUND_SHADER: Handle<Shader> = uuid_handle!("5d0f1e0c-8a49-4c1e-9f3b-2b6f4a7c9e01");
+
+/// The default ground both backends are built from, see [`NoiseParams::density_graph`]
+#[derive(Resource, Clone, Debug, Reflect, Serialize, Deserialize)]
 #[reflect(Resource)]
+#[serde(default)]
 pub struct NoiseParams {
     pub scale: f32,
     pub frequency: f32,
+    /// How far the hills go above and below zero
     pub amplitude: f32,
     pub octaves: u32,
 }
@@ -27,9 +96,32 @@ impl Default for NoiseParams {
     fn default() -> Self {
         Self {
             scale: 1.0,
-            frequency: 1.0,
-            amplitude: 1.0,
-            octaves: 1,
+            frequency: 0.02,
+            amplitude: 16.0,
+            octaves: 4,
+        }
+    }
+}
+
+impl NoiseParams {
+    /// Rolling perlin hills around y = 0, marching cubes generates this on the gpu and the voxel
+    /// backend searches it for its surface, so both end up with the same ground
+    pub fn density_graph(&self) -> DensityGraph {
+        DensityGraph {
+            root: DensityNode::Add(
+                Box::new(DensityNode::HeightGradient {
+                    height: 0.0,
+                    falloff: 1.0,
+                }),
+                Box::new(DensityNode::Mul(
+                    Box::new(DensityNode::Constant(self.amplitude)),
+                    Box::new(DensityNode::Perlin(NoiseNode {
+                        frequency: self.frequency,
+                        octaves: self.octaves,
+                        seed: 0,
+                    })),
+                )),
+            ),
         }
     }
 }
@@ -47,8 +139,67 @@ impl TerrainNoiseParams for NoiseParams {
     fn octaves(&self) -> u32 {
         self.octaves
     }
+    fn shader(&self) -> Option<Handle<Shader>> {
+        Some(GROUND_SHADER)
+    }
+}
+
+fn compile_ground(params: Res<NoiseParams>, shaders: Option<ResMut<Assets<Shader>>>) {
+    let Some(mut shaders) = shaders.filter(|_| params.is_changed()) else {
+        return;
+    };
+    // Never fails for uuid handles
+    let _ = shaders.insert(
+        &GROUND_SHADER,
+        Shader::from_wgsl(
+            params.density_graph().to_wgsl(),
+            "weave/marching_cubes/ground.wgsl",
+        ),
+    );
+}
+
+fn spawn_terrain_root(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
+    let entity = commands
+        .spawn((
+            Name::new("Marching Cubes Terrain"),
+            MarchingTerrain,
+            Transform::default(),
+            Visibility::default(),
+        ))
+        .id();
+    commands.insert_resource(TerrainRoot {
+        entity,
+        material: materials.add(StandardMaterial::from_color(Color::srgb(1.0, 1.0, 1.0))),
+    });
+}
+
+fn load_chunk(trigger: On<LoadChunk>, mut commands: Commands, backend: Res<SurfaceBackend>) {
+    if *backend == SurfaceBackend::MarchingCubes {
+        commands.trigger(RequestDensity::new_3d(trigger.event().position));
+    }
+}
+
+fn regenerate_chunks(
+    _trigger: On<RegenerateChunks>,
+    mut commands: Commands,
+    chunks: Query<&TerrainChunk>,
+) {
+    for chunk in chunks {
+        commands.trigger(RequestDensity::new_3d(chunk.0));
+    }
+}
+
+fn unload_chunk(
+    trigger: On<UnloadChunk>,
+    mut commands: Commands,
+    mut chunks: ResMut<TerrainChunks>,
+) {
+    if let Some(entity) = chunks.remove(&trigger.event().position) {
+        commands.entity(entity).despawn();
+    }
 }
 
-pub fn request_area(mut commands: Commands) {
-    commands.trigger(RequestNoise::<NoiseParams>::new(IVec2::ZERO))
+/// World space origin of a chunk
+pub fn chunk_translation(position: IVec3) -> Vec3 {
+    (position * CHUNK_SIZE).as_vec3()
 }
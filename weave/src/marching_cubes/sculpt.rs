@@ -0,0 +1,605 @@
+// Terraforming, sdf brushes applied straight to the cpu side density of loaded chunks
+// Sculpted chunks keep their density when they unload so edits survive walking away, strokes on
+// chunks that have never been generated wait for their density to show up
+use super::{DensityField, RemeshChunks, TerrainChunks, mesh::FIELD_SIZE};
+use crate::{area::CHUNK_SIZE, terrain::graph::smooth_min};
+use bevy::{platform::collections::HashMap, prelude::*};
+use serde::{Deserialize, Serialize};
+
+/// How many strokes undo can go back
+const MAX_HISTORY: usize = 64;
+
+pub struct SculptPlugin;
+
+impl Plugin for SculptPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SculptedDensity>();
+        app.init_resource::<BrushHistory>();
+        app.add_observer(apply_brush);
+        app.add_observer(undo_brush);
+        app.add_observer(redo_brush);
+    }
+}
+
+/// A single brush stroke, positions are in world space and positive density is solid
+#[derive(Clone, Debug, Reflect, Serialize, Deserialize)]
+pub enum Brush {
+    /// Fills in a sphere
+    Add { center: Vec3, radius: f32 },
+    /// Carves out a sphere
+    Subtract { center: Vec3, radius: f32 },
+    /// Fills in a sphere, blending into the surface around it over `smoothness`
+    SmoothUnion {
+        center: Vec3,
+        radius: f32,
+        smoothness: f32,
+    },
+    /// Pulls the terrain towards the plane through `center` facing `normal`
+    Flatten {
+        center: Vec3,
+        radius: f32,
+        normal: Dir3,
+        strength: f32,
+    },
+    /// Averages each sample with its neighbours
+    Smooth {
+        center: Vec3,
+        radius: f32,
+        strength: f32,
+    },
+}
+
+impl Brush {
+    pub fn center(&self) -> Vec3 {
+        match self {
+            Brush::Add { center, .. }
+            | Brush::Subtract { center, .. }
+            | Brush::SmoothUnion { center, .. }
+            | Brush::Flatten { center, .. }
+            | Brush::Smooth { center, .. } => *center,
+        }
+    }
+
+    /// How far from the center samples can change
+    fn reach(&self) -> f32 {
+        match self {
+            Brush::Add { radius, .. } | Brush::Subtract { radius, .. } => *radius + 1.0,
+            Brush::SmoothUnion {
+                radius, smoothness, ..
+            } => *radius + *smoothness + 1.0,
+            Brush::Flatten { radius, .. } => *radius,
+            // Smoothing reads one sample past its radius
+            Brush::Smooth { radius, .. } => *radius + 1.0,
+        }
+    }
+
+    /// Every chunk with a sample the brush can touch
+    fn chunks(&self) -> Vec<IVec3> {
+        let size = CHUNK_SIZE as f32;
+        // Samples on a chunk's max face belong to the next one too
+        let min = ((self.center() - self.reach() - size) / size)
+            .ceil()
+            .as_ivec3();
+        let max = ((self.center() + self.reach()) / size).floor().as_ivec3();
+
+        let mut chunks = Vec::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    chunks.push(IVec3::new(x, y, z));
+                }
+            }
+        }
+        chunks
+    }
+
+    /// New density for the sample at `position`, `sample` reads the density from before the stroke
+    fn apply(&self, density: f32, position: IVec3, sample: impl Fn(IVec3) -> Option<f32>) -> f32 {
+        let point = position.as_vec3();
+        match *self {
+            Brush::Add { center, radius } => density.max(radius - point.distance(center)),
+            Brush::Subtract { center, radius } => density.min(point.distance(center) - radius),
+            Brush::SmoothUnion {
+                center,
+                radius,
+                smoothness,
+            } => -smooth_min(-density, point.distance(center) - radius, smoothness),
+            Brush::Flatten {
+                center,
+                radius,
+                normal,
+                strength,
+            } => {
+                let plane = normal.dot(center - point);
+                density.lerp(plane, falloff(point.distance(center), radius) * strength)
+            }
+            Brush::Smooth {
+                center,
+                radius,
+                strength,
+            } => {
+                let neighbours = [
+                    IVec3::X,
+                    IVec3::NEG_X,
+                    IVec3::Y,
+                    IVec3::NEG_Y,
+                    IVec3::Z,
+                    IVec3::NEG_Z,
+                ]
+                .map(|offset| sample(position + offset).unwrap_or(density));
+                let average = neighbours.iter().sum::<f32>() / neighbours.len() as f32;
+                density.lerp(average, falloff(point.distance(center), radius) * strength)
+            }
+        }
+    }
+}
+
+/// 1 at the center easing to 0 at the radius
+fn falloff(distance: f32, radius: f32) -> f32 {
+    let t = (1.0 - distance / radius).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Sculpt the terrain
+#[derive(Event, Clone, Debug)]
+pub struct ApplyBrush(pub Brush);
+
+/// Puts back the density from before the last stroke
+#[derive(Event)]
+pub struct UndoBrush;
+
+/// Applies the last undone stroke again
+#[derive(Event)]
+pub struct RedoBrush;
+
+/// Density of every chunk that's been sculpted, used over the generated density when it loads again
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct SculptedDensity {
+    #[deref]
+    pub fields: HashMap<IVec3, Vec<f32>>,
+    /// Strokes on chunks nothing has generated density for yet, in the order they were made
+    pub pending: HashMap<IVec3, Vec<Brush>>,
+    /// Generated density and the queued strokes that were applied over it, so undo can take them off again
+    baked: HashMap<IVec3, (Vec<f32>, Vec<Brush>)>,
+}
+
+impl SculptedDensity {
+    /// What a chunk should be meshed from now `generated` came back for it, strokes that were
+    /// waiting on it get applied and it counts as sculpted from then on
+    pub fn density_for(&mut self, position: IVec3, generated: &[f32]) -> Vec<f32> {
+        if let Some(field) = self.fields.get(&position) {
+            return field.clone();
+        }
+        let Some(brushes) = self.pending.remove(&position) else {
+            return generated.to_vec();
+        };
+        let field = bake(position, generated, &brushes);
+        self.fields.insert(position, field.clone());
+        self.baked.insert(position, (generated.to_vec(), brushes));
+        field
+    }
+
+    /// Takes the last queued stroke back off a chunk, `None` if it was still waiting and
+    /// otherwise the density the chunk should have without it
+    fn unqueue(&mut self, position: IVec3) -> Option<Vec<f32>> {
+        if let Some(brushes) = self.pending.get_mut(&position) {
+            brushes.pop();
+            if brushes.is_empty() {
+                self.pending.remove(&position);
+            }
+            return None;
+        }
+        let (generated, brushes) = self.baked.get_mut(&position)?;
+        brushes.pop();
+        let field = bake(position, generated, brushes);
+        if brushes.is_empty() {
+            // Back to plain generated density, nothing to keep around for it
+            self.fields.remove(&position);
+            self.baked.remove(&position);
+        } else {
+            self.fields.insert(position, field.clone());
+        }
+        Some(field)
+    }
+}
+
+/// Applies queued strokes over freshly generated density
+fn bake(position: IVec3, generated: &[f32], brushes: &[Brush]) -> Vec<f32> {
+    let origin = position * CHUNK_SIZE;
+    brushes.iter().fold(generated.to_vec(), |field, brush| {
+        // The neighbours might not exist yet, smoothing just sees this chunk
+        let sample = |world: IVec3| {
+            let local = world - origin;
+            (local.cmpge(IVec3::ZERO).all() && local.cmplt(IVec3::splat(FIELD_SIZE as i32)).all())
+                .then(|| field[sample_index(local)])
+        };
+        sculpt_field(brush, origin, &field, sample)
+    })
+}
+
+struct Stroke {
+    brush: Brush,
+    before: Vec<(IVec3, Vec<f32>)>,
+    /// Chunks in `before` that only had generated density, undo makes them unsculpted again
+    generated: Vec<IVec3>,
+    /// Chunks the brush got queued on instead
+    queued: Vec<IVec3>,
+}
+
+#[derive(Resource, Default)]
+pub struct BrushHistory {
+    undo: Vec<Stroke>,
+    redo: Vec<Brush>,
+}
+
+impl BrushHistory {
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+fn apply_brush(
+    trigger: On<ApplyBrush>,
+    mut commands: Commands,
+    mut history: ResMut<BrushHistory>,
+    mut sculpted: ResMut<SculptedDensity>,
+    chunks: Res<TerrainChunks>,
+    mut fields: Query<&mut DensityField>,
+) {
+    let brush = trigger.event().0.clone();
+    let Some(stroke) = stroke(&mut commands, brush, &mut sculpted, &chunks, &mut fields) else {
+        return;
+    };
+
+    history.redo.clear();
+    history.undo.push(stroke);
+    if history.undo.len() > MAX_HISTORY {
+        history.undo.remove(0);
+    }
+}
+
+fn undo_brush(
+    _trigger: On<UndoBrush>,
+    mut commands: Commands,
+    mut history: ResMut<BrushHistory>,
+    mut sculpted: ResMut<SculptedDensity>,
+    chunks: Res<TerrainChunks>,
+    mut fields: Query<&mut DensityField>,
+) {
+    let Some(stroke) = history.undo.pop() else {
+        return;
+    };
+
+    let mut positions = Vec::new();
+    let mut restore = |position: IVec3, before: &Vec<f32>| {
+        if let Some(mut field) = chunks
+            .get(&position)
+            .and_then(|entity| fields.get_mut(*entity).ok())
+        {
+            field.0.clone_from(before);
+            positions.push(position);
+        }
+    };
+    for (position, before) in stroke.before {
+        restore(position, &before);
+        if stroke.generated.contains(&position) {
+            sculpted.remove(&position);
+        } else {
+            sculpted.insert(position, before);
+        }
+    }
+    // Queued strokes might have been baked in since
+    for position in stroke.queued {
+        if let Some(before) = sculpted.unqueue(position) {
+            restore(position, &before);
+        }
+    }
+
+    commands.trigger(RemeshChunks::new(positions));
+    history.redo.push(stroke.brush);
+}
+
+fn redo_brush(
+    _trigger: On<RedoBrush>,
+    mut commands: Commands,
+    mut history: ResMut<BrushHistory>,
+    mut sculpted: ResMut<SculptedDensity>,
+    chunks: Res<TerrainChunks>,
+    mut fields: Query<&mut DensityField>,
+) {
+    let Some(brush) = history.redo.pop() else {
+        return;
+    };
+    if let Some(stroke) = stroke(&mut commands, brush, &mut sculpted, &chunks, &mut fields) {
+        history.undo.push(stroke);
+    }
+}
+
+/// Applies a brush to every chunk it touches, returning what it changed. Loaded chunks get
+/// re-meshed, ones that were sculpted before keep their edits, the rest queue it up
+fn stroke(
+    commands: &mut Commands,
+    brush: Brush,
+    sculpted: &mut SculptedDensity,
+    chunks: &TerrainChunks,
+    fields: &mut Query<&mut DensityField>,
+) -> Option<Stroke> {
+    let mut before = Vec::new();
+    let mut generated = Vec::new();
+    let mut queued = Vec::new();
+    for position in brush.chunks() {
+        let loaded = chunks
+            .get(&position)
+            .and_then(|entity| fields.get(*entity).ok());
+        match loaded
+            .map(|field| &field.0)
+            .or_else(|| sculpted.fields.get(&position))
+        {
+            Some(field) => {
+                before.push((position, field.clone()));
+                if !sculpted.fields.contains_key(&position) {
+                    generated.push(position);
+                }
+            }
+            None => queued.push(position),
+        }
+    }
+    if before.is_empty() && queued.is_empty() {
+        return None;
+    }
+    for position in &queued {
+        sculpted
+            .pending
+            .entry(*position)
+            .or_default()
+            .push(brush.clone());
+    }
+
+    // Smoothing reads across chunk borders so everything samples the untouched density
+    let snapshot: HashMap<IVec3, usize> = before
+        .iter()
+        .enumerate()
+        .map(|(index, (position, _))| (*position, index))
+        .collect();
+    let sample = |world: IVec3| {
+        let chunk = snapshot.get(&world.div_euclid(IVec3::splat(CHUNK_SIZE)))?;
+        Some(before[*chunk].1[sample_index(world.rem_euclid(IVec3::splat(CHUNK_SIZE)))])
+    };
+
+    let mut positions = Vec::new();
+    for (position, field) in &before {
+        let sculpted_field = sculpt_field(&brush, position * CHUNK_SIZE, field, sample);
+        if let Some(mut chunk_field) = chunks
+            .get(position)
+            .and_then(|entity| fields.get_mut(*entity).ok())
+        {
+            chunk_field.0.clone_from(&sculpted_field);
+            positions.push(*position);
+        }
+        sculpted.insert(*position, sculpted_field);
+    }
+
+    commands.trigger(RemeshChunks::new(positions));
+    Some(Stroke {
+        brush,
+        before,
+        generated,
+        queued,
+    })
+}
+
+fn sculpt_field(
+    brush: &Brush,
+    origin: IVec3,
+    field: &[f32],
+    sample: impl Fn(IVec3) -> Option<f32> + Copy,
+) -> Vec<f32> {
+    field
+        .iter()
+        .enumerate()
+        .map(|(index, &density)| brush.apply(density, origin + sample_position(index), sample))
+        .collect()
+}
+
+fn sample_index(local: IVec3) -> usize {
+    let size = FIELD_SIZE as i32;
+    (local.x + local.y * size + local.z * size * size) as usize
+}
+
+fn sample_position(index: usize) -> IVec3 {
+    let size = FIELD_SIZE as usize;
+    IVec3::new(
+        (index % size) as i32,
+        (index / size % size) as i32,
+        (index / (size * size)) as i32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIELD_LEN: usize = (FIELD_SIZE * FIELD_SIZE * FIELD_SIZE) as usize;
+
+    #[derive(Resource, Default)]
+    struct Remeshed(Vec<IVec3>);
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, SculptPlugin));
+        app.init_resource::<TerrainChunks>();
+        app.init_resource::<Remeshed>();
+        app.add_observer(
+            |trigger: On<RemeshChunks>, mut remeshed: ResMut<Remeshed>| {
+                remeshed.0.extend(&trigger.event().positions);
+            },
+        );
+        app
+    }
+
+    // Open air everywhere, like the chunk came back from a generator with nothing in it
+    fn load(app: &mut App, position: IVec3, field: Vec<f32>) -> Entity {
+        let entity = app.world_mut().spawn(DensityField(field)).id();
+        app.world_mut()
+            .resource_mut::<TerrainChunks>()
+            .insert(position, entity);
+        entity
+    }
+
+    fn at(field: &[f32], local: IVec3) -> f32 {
+        field[sample_index(local)]
+    }
+
+    fn add(center: Vec3) -> Brush {
+        Brush::Add {
+            center,
+            radius: 3.0,
+        }
+    }
+
+    #[test]
+    fn brushes_follow_their_sdfs() {
+        let center = Vec3::splat(8.0);
+        let none = |_| None;
+        let inside = IVec3::splat(8);
+        let outside = IVec3::new(8, 8, 14);
+
+        assert_eq!(add(center).apply(-1.0, inside, none), 3.0);
+        assert_eq!(add(center).apply(-1.0, outside, none), -1.0);
+        // Adding never takes anything away
+        assert_eq!(add(center).apply(10.0, inside, none), 10.0);
+
+        let subtract = Brush::Subtract {
+            center,
+            radius: 3.0,
+        };
+        assert_eq!(subtract.apply(1.0, inside, none), -3.0);
+        assert_eq!(subtract.apply(1.0, outside, none), 1.0);
+
+        let smooth_union = Brush::SmoothUnion {
+            center,
+            radius: 3.0,
+            smoothness: 2.0,
+        };
+        assert_eq!(smooth_union.apply(-10.0, inside, none), 3.0);
+        assert_eq!(smooth_union.apply(-1.0, outside, none), -1.0);
+        // Near the edge it bulges past a plain union
+        let edge = IVec3::new(8, 8, 12);
+        assert!(smooth_union.apply(-1.0, edge, none) > add(center).apply(-1.0, edge, none));
+
+        let flatten = Brush::Flatten {
+            center,
+            radius: 4.0,
+            normal: Dir3::Y,
+            strength: 1.0,
+        };
+        assert_eq!(flatten.apply(5.0, inside, none), 0.0);
+        assert_eq!(flatten.apply(5.0, outside, none), 5.0);
+
+        let smooth = Brush::Smooth {
+            center,
+            radius: 4.0,
+            strength: 1.0,
+        };
+        assert_eq!(smooth.apply(6.0, inside, |_| Some(0.0)), 0.0);
+        // Missing neighbours count as the sample itself
+        assert_eq!(smooth.apply(6.0, inside, none), 6.0);
+    }
+
+    #[test]
+    fn brushes_reach_chunks_sharing_a_face() {
+        assert_eq!(add(Vec3::splat(8.0)).chunks(), [IVec3::ZERO]);
+        // x = 0 is also the last sample of the chunk before
+        let chunks = add(Vec3::new(0.0, 8.0, 8.0)).chunks();
+        assert!(chunks.contains(&IVec3::ZERO) && chunks.contains(&IVec3::NEG_X));
+        assert_eq!(chunks.len(), 2);
+    }
+
+    #[test]
+    fn undo_and_redo_loaded_chunks() {
+        let mut app = app();
+        let chunk = load(&mut app, IVec3::ZERO, vec![-1.0; FIELD_LEN]);
+        let field = |app: &App| app.world().get::<DensityField>(chunk).unwrap().0.clone();
+
+        app.world_mut().trigger(ApplyBrush(add(Vec3::splat(8.0))));
+        app.world_mut().trigger(ApplyBrush(add(Vec3::splat(4.0))));
+        assert_eq!(at(&field(&app), IVec3::splat(8)), 3.0);
+        assert_eq!(at(&field(&app), IVec3::splat(4)), 3.0);
+        // Remeshing goes out as commands
+        app.world_mut().flush();
+        assert_eq!(app.world().resource::<Remeshed>().0, [IVec3::ZERO; 2]);
+
+        app.world_mut().trigger(UndoBrush);
+        assert_eq!(at(&field(&app), IVec3::splat(4)), -1.0);
+        assert_eq!(at(&field(&app), IVec3::splat(8)), 3.0);
+        assert_eq!(
+            app.world().resource::<SculptedDensity>().get(&IVec3::ZERO),
+            Some(&field(&app))
+        );
+
+        // All the way back it's just generated density again, nothing to keep when it unloads
+        app.world_mut().trigger(UndoBrush);
+        assert_eq!(field(&app), vec![-1.0; FIELD_LEN]);
+        assert!(app.world().resource::<SculptedDensity>().is_empty());
+        assert!(!app.world().resource::<BrushHistory>().can_undo());
+
+        app.world_mut().trigger(RedoBrush);
+        assert_eq!(at(&field(&app), IVec3::splat(8)), 3.0);
+        assert!(
+            app.world()
+                .resource::<SculptedDensity>()
+                .contains_key(&IVec3::ZERO)
+        );
+        assert!(app.world().resource::<BrushHistory>().can_redo());
+        // A new stroke drops whatever could still be redone
+        app.world_mut()
+            .trigger(ApplyBrush(add(Vec3::new(12.0, 4.0, 4.0))));
+        assert!(!app.world().resource::<BrushHistory>().can_redo());
+    }
+
+    #[test]
+    fn undo_queued_strokes_before_and_after_they_land() {
+        let mut app = app();
+        let position = IVec3::new(5, 0, 0);
+        let center = Vec3::new(88.0, 8.0, 8.0);
+        app.world_mut().trigger(ApplyBrush(add(center)));
+        assert_eq!(
+            app.world().resource::<SculptedDensity>().pending[&position].len(),
+            1
+        );
+        // Still waiting, it just gets dropped
+        app.world_mut().trigger(UndoBrush);
+        assert!(app.world().resource::<SculptedDensity>().pending.is_empty());
+
+        // This time the density shows up before the undo
+        app.world_mut().trigger(RedoBrush);
+        let generated = vec![-1.0; FIELD_LEN];
+        let data = app
+            .world_mut()
+            .resource_mut::<SculptedDensity>()
+            .density_for(position, &generated);
+        assert_eq!(at(&data, IVec3::splat(8)), 3.0);
+        let chunk = load(&mut app, position, data);
+
+        app.world_mut().trigger(UndoBrush);
+        assert_eq!(app.world().get::<DensityField>(chunk).unwrap().0, generated);
+        app.world_mut().flush();
+        assert!(app.world().resource::<Remeshed>().0.contains(&position));
+        let sculpted = app.world().resource::<SculptedDensity>();
+        assert!(sculpted.is_empty() && sculpted.pending.is_empty());
+
+        // And redoing applies it like any other loaded chunk
+        app.world_mut().trigger(RedoBrush);
+        let field = &app.world().get::<DensityField>(chunk).unwrap().0;
+        assert_eq!(at(field, IVec3::splat(8)), 3.0);
+    }
+}
@@ -2,6 +2,8 @@
 // ========================= TABLES ========================
 // =========================================================
 
+use bevy::math::UVec3;
+
 // =============== EDGE TABLE ===============
 pub const EDGE_TABLE: [i32; 256] = [
     0x0, 0x109, 0x203, 0x30a, 0x406, 0x50f, 0x605, 0x70c, 0x80c, 0x905, 0xa0f, 0xb06, 0xc0a, 0xd03,
@@ -305,3 +307,16 @@ pub const TRI_TABLE: [[i8; 16]; 256] = [
         -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
     ],
 ];
+
+// =============== CORNER OFFSETS ===============
+// Position of each cube corner, in the order the tables above use
+pub const CORNER_OFFSETS: [UVec3; 8] = [
+    UVec3::new(0, 0, 0),
+    UVec3::new(1, 0, 0),
+    UVec3::new(1, 1, 0),
+    UVec3::new(0, 1, 0),
+    UVec3::new(0, 0, 1),
+    UVec3::new(1, 0, 1),
+    UVec3::new(1, 1, 1),
+    UVec3::new(0, 1, 1),
+];
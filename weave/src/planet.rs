@@ -0,0 +1,357 @@
+// Planet mode, the terrain wraps around a sphere instead of stretching out flat
+// Chunks are still the same axis aligned cubes, the cube-sphere only picks which ones sit on the shell
+// Only the marching cubes backend makes sense here, voxel_terrain is a heightmap
+use crate::{
+    area::{AreaManaged, CHUNK_SIZE, WantedChunks, want},
+    marching_cubes::NoiseParams,
+    terrain::{
+        DensityLayers,
+        graph::{DensityGraph, DensityGraphPlugin, DensityNode, NoiseNode},
+    },
+};
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use character_controller::LocalGravity;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::FRAC_PI_2;
+
+/// Turns the world into a single planet, its graph takes over from the flat noise layer
+#[derive(Default)]
+pub struct PlanetPlugin(pub Planet);
+
+impl Plugin for PlanetPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.0.clone());
+        // Its own layer, so a game can still blend its own graph on top
+        app.add_plugins(DensityGraphPlugin::from_graph(self.0.density_graph()).tagged::<Planet>());
+        app.add_systems(
+            FixedUpdate,
+            (add_local_gravity, update_local_gravity, apply_local_gravity).chain(),
+        );
+    }
+
+    // After every plugin is built so it doesn't matter whether weave was added first
+    fn finish(&self, app: &mut App) {
+        // The sphere is the whole surface, flat noise under it would just bury it
+        if let Some(mut layers) = app.world_mut().get_resource_mut::<DensityLayers>() {
+            layers.remove::<NoiseParams>();
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Planet {
+    pub center: Vec3,
+    pub radius: f32,
+    /// How far the noise pushes the surface in and out
+    pub roughness: f32,
+    pub noise: NoiseNode,
+    /// Pull at sea level, falls off with the square of the distance above it
+    pub surface_gravity: f32,
+    /// Chunk layers loaded under the surface
+    pub depth: i32,
+    /// Chunk layers loaded over the surface, should cover the roughness
+    pub height: i32,
+}
+
+impl Default for Planet {
+    fn default() -> Self {
+        Self {
+            center: Vec3::ZERO,
+            radius: 256.0,
+            roughness: 12.0,
+            noise: NoiseNode::default(),
+            surface_gravity: 9.81,
+            depth: 1,
+            height: 1,
+        }
+    }
+}
+
+// Each cube face as its normal and the two axes spanning it
+const CUBE_FACES: [(Vec3, Vec3, Vec3); 6] = [
+    (Vec3::X, Vec3::Y, Vec3::Z),
+    (Vec3::NEG_X, Vec3::Z, Vec3::Y),
+    (Vec3::Y, Vec3::Z, Vec3::X),
+    (Vec3::NEG_Y, Vec3::X, Vec3::Z),
+    (Vec3::Z, Vec3::X, Vec3::Y),
+    (Vec3::NEG_Z, Vec3::Y, Vec3::X),
+];
+
+impl Planet {
+    /// Height above sea level, negative underground
+    pub fn altitude(&self, position: Vec3) -> f32 {
+        position.distance(self.center) - self.radius
+    }
+
+    /// Away from the center, falls back to +y right at the center
+    pub fn up(&self, position: Vec3) -> Dir3 {
+        Dir3::new(position - self.center).unwrap_or(Dir3::Y)
+    }
+
+    /// Inverse square outside, fading linearly to nothing at the center underground
+    pub fn gravity_at(&self, position: Vec3) -> Vec3 {
+        let distance = position.distance(self.center);
+        if distance <= f32::EPSILON {
+            return Vec3::ZERO;
+        }
+        let strength = if distance < self.radius {
+            self.surface_gravity * distance / self.radius
+        } else {
+            self.surface_gravity * (self.radius / distance).powi(2)
+        };
+        -self.up(position) * strength
+    }
+
+    /// Distance from the center with some noise on top, positive inside
+    pub fn density_graph(&self) -> DensityGraph {
+        DensityGraph {
+            root: DensityNode::Add(
+                Box::new(DensityNode::Sphere {
+                    center: self.center,
+                    radius: self.radius,
+                }),
+                Box::new(DensityNode::Mul(
+                    Box::new(DensityNode::Constant(self.roughness)),
+                    Box::new(DensityNode::Value(self.noise.clone())),
+                )),
+            ),
+        }
+    }
+
+    /// Chunks along the shell near the observer, lod goes by whichever is further of
+    /// the observers altitude and how far along the surface the chunk is
+    pub(crate) fn shell_area(
+        &self,
+        observer: Vec3,
+        managed: &AreaManaged,
+        wanted: &mut WantedChunks,
+    ) {
+        let chunk_size = CHUNK_SIZE as f32;
+        // Cells half a chunk wide so neighbouring samples never skip over a chunk
+        let cells = (FRAC_PI_2 * self.radius / (chunk_size * 0.5))
+            .ceil()
+            .max(1.0) as i32;
+        let altitude = (self.altitude(observer).max(0.0) / chunk_size) as i32;
+        let reach = (managed.render_distance + altitude) as f32 * chunk_size;
+
+        for (normal, tangent, bitangent) in CUBE_FACES {
+            for i in 0..cells {
+                for j in 0..cells {
+                    let u = (i as f32 + 0.5) / cells as f32 * 2.0 - 1.0;
+                    let v = (j as f32 + 0.5) / cells as f32 * 2.0 - 1.0;
+                    let direction = cube_to_sphere(normal + tangent * u + bitangent * v);
+                    let surface = self.center + direction * self.radius;
+
+                    let along = surface.distance(observer);
+                    if along > reach {
+                        continue;
+                    }
+                    let distance = (along / chunk_size) as i32;
+                    let lod = managed.lod(distance.max(altitude));
+                    for layer in -self.depth..=self.height {
+                        let point = surface + direction * (layer as f32 * chunk_size);
+                        let position = (point / chunk_size).floor().as_ivec3();
+                        want(wanted, position, lod, distance);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Spreads points on the cube more evenly over the sphere than just normalizing
+fn cube_to_sphere(p: Vec3) -> Vec3 {
+    let p2 = p * p;
+    Vec3::new(
+        p.x * (1.0 - p2.y / 2.0 - p2.z / 2.0 + p2.y * p2.z / 3.0).sqrt(),
+        p.y * (1.0 - p2.z / 2.0 - p2.x / 2.0 + p2.z * p2.x / 3.0).sqrt(),
+        p.z * (1.0 - p2.x / 2.0 - p2.y / 2.0 + p2.x * p2.y / 3.0).sqrt(),
+    )
+}
+
+/// Anything that moves gets planet gravity, dynamic bodies stop using avian's
+fn add_local_gravity(
+    mut commands: Commands,
+    bodies: Query<(Entity, &RigidBody), Without<LocalGravity>>,
+) {
+    for (entity, body) in bodies {
+        match body {
+            RigidBody::Dynamic => {
+                commands
+                    .entity(entity)
+                    .insert((LocalGravity::default(), GravityScale(0.0)));
+            }
+            // Kinematic controllers read it themselves
+            RigidBody::Kinematic => {
+                commands.entity(entity).insert(LocalGravity::default());
+            }
+            RigidBody::Static => {}
+        }
+    }
+}
+
+fn update_local_gravity(
+    planet: Res<Planet>,
+    mut bodies: Query<(&GlobalTransform, &mut LocalGravity)>,
+) {
+    for (transform, mut gravity) in &mut bodies {
+        gravity.0 = planet.gravity_at(transform.translation());
+    }
+}
+
+fn apply_local_gravity(
+    time: Res<Time>,
+    mut bodies: Query<(&RigidBody, &LocalGravity, &mut LinearVelocity)>,
+) {
+    for (body, gravity, mut velocity) in &mut bodies {
+        if *body == RigidBody::Dynamic {
+            velocity.0 += gravity.0 * time.delta_secs();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::area::LodLevel;
+    use bevy::time::TimeUpdateStrategy;
+    use std::{f32::consts::PI, time::Duration};
+
+    fn planet() -> Planet {
+        Planet {
+            radius: 64.0,
+            ..default()
+        }
+    }
+
+    // Evenly spread directions to poke the sphere with
+    fn directions() -> impl Iterator<Item = Vec3> {
+        let count = 2000;
+        (0..count).map(move |i| {
+            let y = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+            let angle = i as f32 * PI * (3.0 - 5f32.sqrt());
+            let ring = (1.0 - y * y).sqrt();
+            Vec3::new(ring * angle.cos(), y, ring * angle.sin())
+        })
+    }
+
+    fn chunk_of(point: Vec3) -> IVec3 {
+        (point / CHUNK_SIZE as f32).floor().as_ivec3()
+    }
+
+    #[test]
+    fn shell_covers_the_surface_and_nothing_else() {
+        let planet = planet();
+        let managed = AreaManaged {
+            render_distance: 20,
+            ..default()
+        };
+        let mut wanted = WantedChunks::new();
+        planet.shell_area(planet.center, &managed, &mut wanted);
+
+        for direction in directions() {
+            let surface = chunk_of(planet.center + direction * planet.radius);
+            assert!(wanted.contains_key(&surface), "hole at {surface}");
+        }
+        // Every chunk touches the band between the lowest and highest layer
+        let size = CHUNK_SIZE as f32;
+        let lowest = planet.radius - planet.depth as f32 * size;
+        let highest = planet.radius + planet.height as f32 * size;
+        for position in wanted.keys() {
+            let min = position.as_vec3() * size;
+            let max = min + size;
+            let nearest = planet.center.clamp(min, max).distance(planet.center);
+            let furthest = (planet.center - min)
+                .abs()
+                .max((planet.center - max).abs())
+                .length();
+            assert!(
+                nearest <= highest && furthest >= lowest,
+                "{position} is off the shell"
+            );
+        }
+        assert!(!wanted.contains_key(&chunk_of(planet.center)));
+    }
+
+    #[test]
+    fn shell_only_reaches_so_far_around() {
+        let planet = planet();
+        let managed = AreaManaged {
+            render_distance: 3,
+            ..default()
+        };
+        let observer = planet.center + Vec3::Y * (planet.radius + 4.0);
+        let mut wanted = WantedChunks::new();
+        planet.shell_area(observer, &managed, &mut wanted);
+
+        let below = chunk_of(planet.center + Vec3::Y * planet.radius);
+        assert_eq!(
+            wanted.get(&below).map(|(lod, _)| *lod),
+            Some(LodLevel::High)
+        );
+        let far_side = chunk_of(planet.center - Vec3::Y * planet.radius);
+        assert!(!wanted.contains_key(&far_side));
+    }
+
+    #[test]
+    fn gravity_points_at_the_center() {
+        let planet = planet();
+        let surface = planet.center + Vec3::X * planet.radius;
+        assert!((planet.gravity_at(surface) - Vec3::NEG_X * 9.81).length() < 1e-4);
+        // Inverse square above, linear below
+        let above = planet.center + Vec3::Z * planet.radius * 2.0;
+        assert!((planet.gravity_at(above) - Vec3::NEG_Z * 9.81 / 4.0).length() < 1e-4);
+        let below = planet.center + Vec3::NEG_Y * planet.radius * 0.5;
+        assert!((planet.gravity_at(below) - Vec3::Y * 9.81 / 2.0).length() < 1e-4);
+        assert_eq!(planet.gravity_at(planet.center), Vec3::ZERO);
+    }
+
+    #[test]
+    fn bodies_fall_towards_the_planet() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins);
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+            100,
+        )));
+        app.insert_resource(planet());
+        app.add_systems(
+            Update,
+            (add_local_gravity, update_local_gravity, apply_local_gravity).chain(),
+        );
+
+        let position = Vec3::new(0.0, 0.0, 100.0);
+        let body = |app: &mut App, kind| {
+            app.world_mut()
+                .spawn((
+                    kind,
+                    GlobalTransform::from_translation(position),
+                    LinearVelocity::ZERO,
+                ))
+                .id()
+        };
+        let dynamic = body(&mut app, RigidBody::Dynamic);
+        let kinematic = body(&mut app, RigidBody::Kinematic);
+        let fixed = body(&mut app, RigidBody::Static);
+        for _ in 0..3 {
+            app.update();
+        }
+
+        let world = app.world();
+        let expected = planet().gravity_at(position);
+        assert_eq!(world.get::<LocalGravity>(dynamic).unwrap().0, expected);
+        assert_eq!(world.get::<GravityScale>(dynamic), Some(&GravityScale(0.0)));
+        let velocity = world.get::<LinearVelocity>(dynamic).unwrap().0;
+        assert!(velocity.normalize().dot(Vec3::NEG_Z) > 0.999);
+
+        // Controllers only get told which way is down
+        assert_eq!(world.get::<LocalGravity>(kinematic).unwrap().0, expected);
+        assert!(world.get::<GravityScale>(kinematic).is_none());
+        assert_eq!(
+            world.get::<LinearVelocity>(kinematic).unwrap().0,
+            Vec3::ZERO
+        );
+        assert!(world.get::<LocalGravity>(fixed).is_none());
+    }
+}
@@ -0,0 +1,104 @@
+// Terrain presets, one `.terrain.ron` file that every terrain backend reads from
+// Saving the file regenerates whatever chunks are loaded
+use crate::{
+    SurfaceBackend,
+    marching_cubes::{Mesher, NoiseParams, RegenerateChunks, TerrainRoot},
+};
+use bevy::{
+    asset::{AssetLoader, LoadContext, io::Reader},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use voxel_terrain::prelude::*;
+
+pub struct TerrainPresetPlugin;
+
+impl Plugin for TerrainPresetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<TerrainPreset>()
+            .register_asset_loader(TerrainPresetLoader);
+        app.add_systems(Update, apply_preset);
+    }
+}
+
+/// Anything missing from the file falls back to the backend's defaults
+#[derive(Asset, TypePath, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerrainPreset {
+    pub backend: SurfaceBackend,
+    pub marching: NoiseParams,
+    pub mesher: Mesher,
+    pub voxel: VoxelTerrainSettings,
+}
+
+/// The preset the terrain is generated from
+/// ```ignore
+/// commands.insert_resource(ActiveTerrainPreset(asset_server.load("default.terrain.ron")));
+/// ```
+#[derive(Resource, Clone, Debug, Deref)]
+pub struct ActiveTerrainPreset(pub Handle<TerrainPreset>);
+
+fn apply_preset(
+    mut events: MessageReader<AssetEvent<TerrainPreset>>,
+    active: Option<Res<ActiveTerrainPreset>>,
+    presets: Res<Assets<TerrainPreset>>,
+    root: Option<Res<TerrainRoot>>,
+    meshers: Query<&Mesher>,
+    backend: Res<SurfaceBackend>,
+    mut commands: Commands,
+) {
+    let Some(active) = active else {
+        events.clear();
+        return;
+    };
+    // Swapping to an already loaded preset won't send any asset events
+    let mut changed = active.is_changed();
+    for event in events.read() {
+        changed |= event.is_loaded_with_dependencies(&active.0) || event.is_modified(&active.0);
+    }
+    if !changed {
+        return;
+    }
+    let Some(preset) = presets.get(&active.0) else {
+        return;
+    };
+
+    info!("Applying terrain preset {:?}", active.path());
+    commands.insert_resource(preset.marching.clone());
+    commands.insert_resource(preset.voxel);
+    if *backend != preset.backend {
+        commands.insert_resource(preset.backend);
+    }
+    // The regenerate below re-meshes everything anyway
+    if let Some(root) = root
+        && meshers.get(root.entity).copied().unwrap_or_default() != preset.mesher
+    {
+        commands.entity(root.entity).insert(preset.mesher);
+    }
+    // Voxel chunks regenerate on their own once the new settings land
+    commands.trigger(RegenerateChunks);
+}
+
+#[derive(Default)]
+pub struct TerrainPresetLoader;
+
+impl AssetLoader for TerrainPresetLoader {
+    type Asset = TerrainPreset;
+    type Settings = ();
+    type Error = BevyError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["terrain.ron"]
+    }
+}
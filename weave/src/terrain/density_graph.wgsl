@@ -0,0 +1,227 @@
+// Density graph compute shader
+// Noise library + entry point, `fn density` is generated from a DensityGraph and appended
+// CHUNK_SIZE, FIELD_SIZE and PERLIN_GRADIENTS are generated from the rust side and prepended
+
+struct NoiseParams {
+    chunk_x: i32,
+    chunk_y: i32,
+    chunk_z: i32,
+    scale: f32,
+    frequency: f32,
+    amplitude: f32,
+    octaves: u32,
+    _padding: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: NoiseParams;
+
+@group(0) @binding(1)
+var<storage, read_write> noise_field: array<f32>;
+
+// Keep every function below in sync with terrain/graph.rs, the CPU path mirrors them
+
+fn hash(v: vec3<f32>) -> f32 {
+    let p = vec3<f32>(
+        dot(v, vec3<f32>(127.1, 311.7, 74.7)),
+        dot(v, vec3<f32>(269.5, 183.3, 246.1)),
+        dot(v, vec3<f32>(113.5, 271.9, 124.6))
+    );
+    return fract(sin(p.x) * 43758.5453123 + sin(p.y) * 12345.6789 + sin(p.z) * 98765.4321);
+}
+
+fn hash3(v: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        hash(v),
+        hash(v + vec3<f32>(19.19, 7.13, 3.71)),
+        hash(v + vec3<f32>(5.3, 41.7, 13.1))
+    );
+}
+
+fn fade(t: vec3<f32>) -> vec3<f32> {
+    return t * t * (3.0 - 2.0 * t);
+}
+
+// Value noise, -1 to 1
+fn value_noise(p: vec3<f32>) -> f32 {
+    let pi = floor(p);
+    let w = fade(fract(p));
+
+    let c00 = mix(hash(pi), hash(pi + vec3<f32>(1.0, 0.0, 0.0)), w.x);
+    let c10 = mix(hash(pi + vec3<f32>(0.0, 1.0, 0.0)), hash(pi + vec3<f32>(1.0, 1.0, 0.0)), w.x);
+    let c01 = mix(hash(pi + vec3<f32>(0.0, 0.0, 1.0)), hash(pi + vec3<f32>(1.0, 0.0, 1.0)), w.x);
+    let c11 = mix(hash(pi + vec3<f32>(0.0, 1.0, 1.0)), hash(pi + vec3<f32>(1.0, 1.0, 1.0)), w.x);
+
+    return mix(mix(c00, c10, w.y), mix(c01, c11, w.y), w.z) * 2.0 - 1.0;
+}
+
+// Improved perlin, the gradient is one of the cube's edges picked by the corner's hash
+fn perlin_gradient(cell: vec3<f32>) -> vec3<f32> {
+    // Runtime indexing needs a variable, not a constant
+    var gradients = PERLIN_GRADIENTS;
+    return gradients[min(u32(hash(cell) * 12.0), 11u)];
+}
+
+fn perlin_corner(pi: vec3<f32>, f: vec3<f32>, offset: vec3<f32>) -> f32 {
+    return dot(perlin_gradient(pi + offset), f - offset);
+}
+
+// Gradient noise, -1 to 1 and zero on every grid point
+fn perlin(p: vec3<f32>) -> f32 {
+    let pi = floor(p);
+    let f = p - pi;
+    let w = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+
+    let c00 = mix(perlin_corner(pi, f, vec3<f32>(0.0, 0.0, 0.0)), perlin_corner(pi, f, vec3<f32>(1.0, 0.0, 0.0)), w.x);
+    let c10 = mix(perlin_corner(pi, f, vec3<f32>(0.0, 1.0, 0.0)), perlin_corner(pi, f, vec3<f32>(1.0, 1.0, 0.0)), w.x);
+    let c01 = mix(perlin_corner(pi, f, vec3<f32>(0.0, 0.0, 1.0)), perlin_corner(pi, f, vec3<f32>(1.0, 0.0, 1.0)), w.x);
+    let c11 = mix(perlin_corner(pi, f, vec3<f32>(0.0, 1.0, 1.0)), perlin_corner(pi, f, vec3<f32>(1.0, 1.0, 1.0)), w.x);
+
+    return clamp(mix(mix(c00, c10, w.y), mix(c01, c11, w.y), w.z), -1.0, 1.0);
+}
+
+fn simplex_corner(x: vec3<f32>, cell: vec3<f32>) -> f32 {
+    let w = max(0.6 - dot(x, x), 0.0);
+    let w2 = w * w;
+    return w2 * w2 * dot(x, hash3(cell) - 0.5);
+}
+
+// Simplex noise, roughly -1 to 1
+fn simplex(p: vec3<f32>) -> f32 {
+    let s = floor(p + dot(p, vec3<f32>(1.0 / 3.0)));
+    let x0 = p - s + dot(s, vec3<f32>(1.0 / 6.0));
+
+    let e = step(vec3<f32>(0.0), x0 - x0.yzx);
+    let i1 = e * (1.0 - e.zxy);
+    let i2 = 1.0 - e.zxy * (1.0 - e);
+
+    let x1 = x0 - i1 + 1.0 / 6.0;
+    let x2 = x0 - i2 + 1.0 / 3.0;
+    let x3 = x0 - 0.5;
+
+    let n = simplex_corner(x0, s)
+        + simplex_corner(x1, s + i1)
+        + simplex_corner(x2, s + i2)
+        + simplex_corner(x3, s + 1.0);
+    return n * 52.0;
+}
+
+// Distance to the closest feature point, remapped to -1 to 1
+fn worley(p: vec3<f32>) -> f32 {
+    let cell = floor(p);
+    let local = fract(p);
+    var closest = 8.0;
+    for (var z = -1; z <= 1; z = z + 1) {
+        for (var y = -1; y <= 1; y = y + 1) {
+            for (var x = -1; x <= 1; x = x + 1) {
+                let offset = vec3<f32>(f32(x), f32(y), f32(z));
+                let point = offset + hash3(cell + offset);
+                closest = min(closest, length(point - local));
+            }
+        }
+    }
+    return min(closest, 1.0) * 2.0 - 1.0;
+}
+
+fn fbm_value(p: vec3<f32>, octaves: u32) -> f32 {
+    var value = 0.0;
+    var amplitude = 1.0;
+    var total = 0.0;
+    var q = p;
+    for (var i = 0u; i < octaves; i = i + 1u) {
+        value = value + amplitude * value_noise(q);
+        total = total + amplitude;
+        amplitude = amplitude * 0.5;
+        q = q * 2.0;
+    }
+    return value / total;
+}
+
+fn fbm_perlin(p: vec3<f32>, octaves: u32) -> f32 {
+    var value = 0.0;
+    var amplitude = 1.0;
+    var total = 0.0;
+    var q = p;
+    for (var i = 0u; i < octaves; i = i + 1u) {
+        value = value + amplitude * perlin(q);
+        total = total + amplitude;
+        amplitude = amplitude * 0.5;
+        q = q * 2.0;
+    }
+    return value / total;
+}
+
+fn fbm_simplex(p: vec3<f32>, octaves: u32) -> f32 {
+    var value = 0.0;
+    var amplitude = 1.0;
+    var total = 0.0;
+    var q = p;
+    for (var i = 0u; i < octaves; i = i + 1u) {
+        value = value + amplitude * simplex(q);
+        total = total + amplitude;
+        amplitude = amplitude * 0.5;
+        q = q * 2.0;
+    }
+    return value / total;
+}
+
+fn fbm_worley(p: vec3<f32>, octaves: u32) -> f32 {
+    var value = 0.0;
+    var amplitude = 1.0;
+    var total = 0.0;
+    var q = p;
+    for (var i = 0u; i < octaves; i = i + 1u) {
+        value = value + amplitude * worley(q);
+        total = total + amplitude;
+        amplitude = amplitude * 0.5;
+        q = q * 2.0;
+    }
+    return value / total;
+}
+
+// Sharp crests where simplex crosses zero, -1 to 1
+fn ridged(p: vec3<f32>, octaves: u32) -> f32 {
+    var value = 0.0;
+    var amplitude = 1.0;
+    var total = 0.0;
+    var q = p;
+    for (var i = 0u; i < octaves; i = i + 1u) {
+        let n = 1.0 - abs(simplex(q));
+        value = value + amplitude * n * n;
+        total = total + amplitude;
+        amplitude = amplitude * 0.5;
+        q = q * 2.0;
+    }
+    return value / total * 2.0 - 1.0;
+}
+
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    let h = clamp(0.5 + 0.5 * (b - a) / k, 0.0, 1.0);
+    return mix(b, a, h) - k * h * (1.0 - h);
+}
+
+fn sd_box(p: vec3<f32>, half_extents: vec3<f32>) -> f32 {
+    let q = abs(p) - half_extents;
+    return length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if (global_id.x >= FIELD_SIZE || global_id.y >= FIELD_SIZE || global_id.z >= FIELD_SIZE) {
+        return;
+    }
+
+    let world_pos = vec3<f32>(
+        f32(params.chunk_x) * CHUNK_SIZE + f32(global_id.x),
+        f32(params.chunk_y) * CHUNK_SIZE + f32(global_id.y),
+        f32(params.chunk_z) * CHUNK_SIZE + f32(global_id.z)
+    ) * params.scale;
+
+    let index = global_id.x + global_id.y * FIELD_SIZE + global_id.z * FIELD_SIZE * FIELD_SIZE;
+    noise_field[index] = density(world_pos);
+
+    // Written flag, lets the readback tell a dispatched field from an untouched buffer
+    if (index == 0u) {
+        noise_field[FIELD_SIZE * FIELD_SIZE * FIELD_SIZE] = 1.0;
+    }
+}
@@ -5,10 +5,11 @@ pub use bevy::{
         RenderApp, RenderStartup,
         extract_resource::{ExtractResource, ExtractResourcePlugin},
         gpu_readback::{Readback, ReadbackComplete},
+        render_asset::RenderAssets,
         render_graph::{self, RenderGraph, RenderLabel},
         render_resource::*,
         renderer::{RenderContext, RenderDevice},
-        storage::ShaderStorageBuffer,
+        storage::{GpuShaderStorageBuffer, ShaderStorageBuffer},
     },
 };
 use bytemuck::{Pod, Zeroable};
@@ -16,6 +17,7 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 
 pub const FIELD_SIZE: u32 = 17;
+pub const FIELD_LEN: usize = (FIELD_SIZE * FIELD_SIZE * FIELD_SIZE) as usize;
 pub const WORKGROUP_SIZE: u32 = 4;
 
 #[repr(C)]
@@ -46,34 +48,61 @@ impl Default for NoiseParams {
     }
 }
 
+#[derive(Clone)]
+pub struct NoiseRequest {
+    pub position: IVec3,
+    pub params: NoiseParams,
+    /// The buffer the readback is watching, the compute pass writes straight into it
+    pub buffer: Handle<ShaderStorageBuffer>,
+    /// Shader to generate the field with, `None` uses noise_field.wgsl
+    pub shader: Option<Handle<Shader>>,
+}
+
 #[derive(Resource, Default, Clone, ExtractResource)]
-pub struct NoiseRequests(pub HashMap<Entity, (IVec3, NoiseParams)>);
+pub struct NoiseRequests(pub HashMap<Entity, NoiseRequest>);
 
 #[derive(Resource)]
 struct NoiseComputePipeline {
     layout: BindGroupLayout,
     pipeline_id: CachedComputePipelineId,
+    /// Pipelines for requests with their own shader, same layout as the default one
+    custom: HashMap<AssetId<Shader>, CachedComputePipelineId>,
+}
+
+impl NoiseComputePipeline {
+    fn pipeline_for(&self, request: &NoiseRequest) -> Option<CachedComputePipelineId> {
+        match &request.shader {
+            Some(shader) => self.custom.get(&shader.id()).copied(),
+            None => Some(self.pipeline_id),
+        }
+    }
 }
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
 struct NoiseComputeLabel;
 
-struct NoiseComputeNode(bool);
-
-impl Default for NoiseComputeNode {
-    fn default() -> Self {
-        Self(false)
-    }
-}
+struct NoiseComputeNode;
 
 impl render_graph::Node for NoiseComputeNode {
     fn update(&mut self, world: &mut World) {
-        let pipeline = world.resource::<NoiseComputePipeline>();
-        let cache = world.resource::<PipelineCache>();
-        self.0 = matches!(
-            cache.get_compute_pipeline_state(pipeline.pipeline_id),
-            CachedPipelineState::Ok(_)
-        );
+        let Some(requests) = world.get_resource::<NoiseRequests>() else {
+            return;
+        };
+        let shaders: Vec<Handle<Shader>> = requests
+            .0
+            .values()
+            .filter_map(|request| request.shader.clone())
+            .collect();
+
+        world.resource_scope(|world, mut pipeline: Mut<NoiseComputePipeline>| {
+            let cache = world.resource::<PipelineCache>();
+            let layout = pipeline.layout.clone();
+            for shader in shaders {
+                pipeline.custom.entry(shader.id()).or_insert_with(|| {
+                    cache.queue_compute_pipeline(pipeline_descriptor(layout.clone(), shader))
+                });
+            }
+        });
     }
 
     fn run(
@@ -82,36 +111,33 @@ impl render_graph::Node for NoiseComputeNode {
         render_context: &mut RenderContext,
         world: &World,
     ) -> Result<(), render_graph::NodeRunError> {
-        if !self.0 {
-            return Ok(());
-        }
-
         let requests = world.resource::<NoiseRequests>();
-        let pipeline = world.resource::<NoiseComputePipeline>();
+        let compute_pipeline = world.resource::<NoiseComputePipeline>();
         let cache = world.resource::<PipelineCache>();
         let device = world.resource::<RenderDevice>();
+        let buffers = world.resource::<RenderAssets<GpuShaderStorageBuffer>>();
+
+        for request in requests.0.values() {
+            // Requests whose pipeline is still compiling just wait for a later frame
+            let Some(pipeline) = compute_pipeline
+                .pipeline_for(request)
+                .and_then(|id| cache.get_compute_pipeline(id))
+            else {
+                continue;
+            };
+            let Some(storage_buf) = buffers.get(&request.buffer) else {
+                continue;
+            };
 
-        let Some(pipeline) = cache.get_compute_pipeline(pipeline.pipeline_id) else {
-            return Ok(());
-        };
-
-        for (_entity, (_chunk_coord, params)) in &requests.0 {
             let params_buf = device.create_buffer_with_data(&BufferInitDescriptor {
                 label: Some("noise_params"),
-                contents: bytemuck::cast_slice(&[*params]),
+                contents: bytemuck::cast_slice(&[request.params]),
                 usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
             });
 
-            let storage_buf = device.create_buffer(&BufferDescriptor {
-                label: Some("noise_storage"),
-                size: (FIELD_SIZE * FIELD_SIZE * FIELD_SIZE * 4) as u64,
-                usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
-                mapped_at_creation: false,
-            });
-
             let bind_group = device.create_bind_group(
                 Some("noise_bind_group"),
-                &world.resource::<NoiseComputePipeline>().layout,
+                &compute_pipeline.layout,
                 &[
                     BindGroupEntry {
                         binding: 0,
@@ -119,7 +145,7 @@ impl render_graph::Node for NoiseComputeNode {
                     },
                     BindGroupEntry {
                         binding: 1,
-                        resource: storage_buf.as_entire_binding(),
+                        resource: storage_buf.buffer.as_entire_binding(),
                     },
                 ],
             );
@@ -130,9 +156,9 @@ impl render_graph::Node for NoiseComputeNode {
             pass.set_pipeline(pipeline);
             pass.set_bind_group(0, &bind_group, &[]);
             pass.dispatch_workgroups(
-                (FIELD_SIZE + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
-                (FIELD_SIZE + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
-                (FIELD_SIZE + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                FIELD_SIZE.div_ceil(WORKGROUP_SIZE),
+                FIELD_SIZE.div_ceil(WORKGROUP_SIZE),
+                FIELD_SIZE.div_ceil(WORKGROUP_SIZE),
             );
         }
 
@@ -153,7 +179,7 @@ impl Plugin for NoiseFieldComputePlugin {
         render_app.add_systems(RenderStartup, init_pipeline);
 
         let mut graph = render_app.world_mut().resource_mut::<RenderGraph>();
-        graph.add_node(NoiseComputeLabel, NoiseComputeNode::default());
+        graph.add_node(NoiseComputeLabel, NoiseComputeNode);
     }
 }
 
@@ -197,18 +223,29 @@ fn init_pipeline(
         ],
     );
 
-    let pipeline_id = cache.queue_compute_pipeline(ComputePipelineDescriptor {
-        label: Some("noise_pipeline".into()),
-        layout: vec![layout.clone()],
-        push_constant_ranges: vec![],
-        shader: asset_server.load("embedded://weave/terrain/noise_field.wgsl"),
-        shader_defs: vec![],
-        entry_point: Some(Cow::from("main")),
-        ..default()
-    });
+    let pipeline_id = cache.queue_compute_pipeline(pipeline_descriptor(
+        layout.clone(),
+        asset_server.load("embedded://weave/terrain/noise_field.wgsl"),
+    ));
 
     commands.insert_resource(NoiseComputePipeline {
         layout,
         pipeline_id,
+        custom: HashMap::new(),
     });
 }
+
+fn pipeline_descriptor(
+    layout: BindGroupLayout,
+    shader: Handle<Shader>,
+) -> ComputePipelineDescriptor {
+    ComputePipelineDescriptor {
+        label: Some("noise_pipeline".into()),
+        layout: vec![layout],
+        push_constant_ranges: vec![],
+        shader,
+        shader_defs: vec![],
+        entry_point: Some(Cow::from("main")),
+        ..default()
+    }
+}
@@ -0,0 +1,981 @@
+// Composable density graphs
+// A tree of nodes that either gets compiled to WGSL for the compute path
+// or sampled directly on the CPU, both sides have to stay in sync with density_graph.wgsl
+use super::*;
+use crate::area::CHUNK_SIZE;
+use bevy::asset::{AssetLoader, LoadContext, io::Reader, uuid::Uuid};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+
+/// Compiled graphs live under this plus a hash of the layer's tag, see [`DensityGraphLayer::shader`]
+const DENSITY_GRAPH_SHADER_PREFIX: u64 = 0xf2fbf726_ea1446a4;
+
+const SHADER_LIBRARY: &str = include_str!("density_graph.wgsl");
+
+// Where the second and third warp samples are taken so the three axes don't move together
+const WARP_OFFSET_Y: Vec3 = Vec3::new(31.4, 17.9, 5.3);
+const WARP_OFFSET_Z: Vec3 = Vec3::new(-11.7, 43.1, 27.5);
+
+/// Generates a density layer from a [`DensityGraph`]. Each one is its own layer keyed by the tag
+/// type `L`, so add caves or ore veins with [`tagged`](Self::tagged) next to the untagged ground
+pub struct DensityGraphPlugin<L = ()> {
+    pub source: GraphSource,
+    pub scale: f32,
+    pub blend: LayerBlend,
+    pub backend: GraphBackend,
+    _layer: PhantomData<L>,
+}
+
+pub enum GraphSource {
+    /// A `.density.ron` file in the assets folder
+    Path(String),
+    Inline(DensityGraph),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum GraphBackend {
+    #[default]
+    Gpu,
+    Cpu,
+}
+
+impl DensityGraphPlugin {
+    pub fn from_path<S: Into<String>>(path: S) -> Self {
+        Self::new(GraphSource::Path(path.into()))
+    }
+
+    pub fn from_graph(graph: DensityGraph) -> Self {
+        Self::new(GraphSource::Inline(graph))
+    }
+
+    fn new(source: GraphSource) -> Self {
+        Self {
+            source,
+            scale: 1.0,
+            blend: LayerBlend::Add,
+            backend: GraphBackend::Gpu,
+            _layer: PhantomData,
+        }
+    }
+}
+
+impl<L: Send + Sync + 'static> DensityGraphPlugin<L> {
+    /// The same graph as its own layer, one plugin per tag
+    pub fn tagged<T: Send + Sync + 'static>(self) -> DensityGraphPlugin<T> {
+        DensityGraphPlugin {
+            source: self.source,
+            scale: self.scale,
+            blend: self.blend,
+            backend: self.backend,
+            _layer: PhantomData,
+        }
+    }
+
+    pub fn on_cpu(mut self) -> Self {
+        self.backend = GraphBackend::Cpu;
+        self
+    }
+
+    pub fn with_blend(mut self, blend: LayerBlend) -> Self {
+        self.blend = blend;
+        self
+    }
+}
+
+// Stays unique, a second plugin with the same tag would fight over the same layer. Bevy keys that
+// on the type name, which includes the tag
+impl<L: Send + Sync + 'static> Plugin for DensityGraphPlugin<L> {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<DensityGraphAssetPlugin>() {
+            app.add_plugins(DensityGraphAssetPlugin);
+        }
+
+        let graph = match &self.source {
+            GraphSource::Path(path) => app.world().resource::<AssetServer>().load(path.clone()),
+            GraphSource::Inline(graph) => app
+                .world_mut()
+                .resource_mut::<Assets<DensityGraph>>()
+                .add(graph.clone()),
+        };
+        let layer = DensityGraphLayer::<L>::new(graph, self.scale, self.blend, self.backend);
+
+        match self.backend {
+            GraphBackend::Gpu => {
+                app.add_plugins(TerrainNoisePlugin(layer));
+                app.add_systems(Update, compile_density_graph::<L>);
+            }
+            GraphBackend::Cpu => {
+                app.insert_resource(layer);
+                app.init_resource::<DensityLayers>();
+                app.world_mut()
+                    .resource_mut::<DensityLayers>()
+                    .register::<DensityGraphLayer<L>>(self.blend);
+                app.init_resource::<CpuGraphQueue<L>>();
+                app.add_observer(queue_cpu_chunk::<L>);
+                app.add_systems(Update, sample_cpu_chunks::<L>);
+            }
+        }
+    }
+}
+
+/// What every [`DensityGraphPlugin`] shares, whatever its tag
+struct DensityGraphAssetPlugin;
+
+impl Plugin for DensityGraphAssetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<DensityGraph>()
+            .register_asset_loader(DensityGraphLoader);
+    }
+}
+
+/// The noise layer a [`DensityGraphPlugin`] registers, the untagged one is what the voxel
+/// backend and terrain sync read
+#[derive(Resource)]
+pub struct DensityGraphLayer<L = ()> {
+    pub graph: Handle<DensityGraph>,
+    pub scale: f32,
+    pub blend: LayerBlend,
+    pub backend: GraphBackend,
+    _layer: PhantomData<L>,
+}
+
+impl<L> Clone for DensityGraphLayer<L> {
+    fn clone(&self) -> Self {
+        Self {
+            graph: self.graph.clone(),
+            scale: self.scale,
+            blend: self.blend,
+            backend: self.backend,
+            _layer: PhantomData,
+        }
+    }
+}
+
+impl<L: Send + Sync + 'static> DensityGraphLayer<L> {
+    pub fn new(
+        graph: Handle<DensityGraph>,
+        scale: f32,
+        blend: LayerBlend,
+        backend: GraphBackend,
+    ) -> Self {
+        Self {
+            graph,
+            scale,
+            blend,
+            backend,
+            _layer: PhantomData,
+        }
+    }
+
+    /// Where this layer's compiled graph goes, every tag gets its own
+    pub fn shader() -> Handle<Shader> {
+        let mut hasher = std::hash::DefaultHasher::new();
+        std::any::TypeId::of::<L>().hash(&mut hasher);
+        Handle::Uuid(
+            Uuid::from_u64_pair(DENSITY_GRAPH_SHADER_PREFIX, hasher.finish()),
+            PhantomData,
+        )
+    }
+}
+
+// The graph carries its own frequencies and amplitudes, these are just neutral
+impl<L: Send + Sync + 'static> TerrainNoiseParams for DensityGraphLayer<L> {
+    fn scale(&self) -> f32 {
+        self.scale
+    }
+    fn frequency(&self) -> f32 {
+        1.0
+    }
+    fn amplitude(&self) -> f32 {
+        1.0
+    }
+    fn octaves(&self) -> u32 {
+        1
+    }
+    fn blend(&self) -> LayerBlend {
+        self.blend
+    }
+    fn shader(&self) -> Option<Handle<Shader>> {
+        match self.backend {
+            GraphBackend::Gpu => Some(Self::shader()),
+            GraphBackend::Cpu => None,
+        }
+    }
+}
+
+/// Positive density is solid, negative is air
+#[derive(Asset, TypePath, Clone, Debug, Serialize, Deserialize)]
+pub struct DensityGraph {
+    pub root: DensityNode,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum DensityNode {
+    Constant(f32),
+    /// Random values on a grid blended smoothly between, blobbier than simplex
+    Value(NoiseNode),
+    /// Gradient noise, zero on every grid point and smoother than value noise
+    Perlin(NoiseNode),
+    Simplex(NoiseNode),
+    Worley(NoiseNode),
+    Ridged(NoiseNode),
+    Add(Box<DensityNode>, Box<DensityNode>),
+    Mul(Box<DensityNode>, Box<DensityNode>),
+    Min(Box<DensityNode>, Box<DensityNode>),
+    Max(Box<DensityNode>, Box<DensityNode>),
+    Negate(Box<DensityNode>),
+    /// Blends the two with a rounded seam `k` units wide, intersection of solids
+    SmoothMin {
+        a: Box<DensityNode>,
+        b: Box<DensityNode>,
+        k: f32,
+    },
+    /// Same as [`DensityNode::SmoothMin`] but a union of solids
+    SmoothMax {
+        a: Box<DensityNode>,
+        b: Box<DensityNode>,
+        k: f32,
+    },
+    /// Offsets the position `source` is sampled at by `warp` on each axis
+    DomainWarp {
+        source: Box<DensityNode>,
+        warp: Box<DensityNode>,
+        strength: f32,
+    },
+    /// Solid below `height`, `falloff` is how fast it changes per unit of height
+    HeightGradient {
+        height: f32,
+        falloff: f32,
+    },
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    Cuboid {
+        center: Vec3,
+        half_extents: Vec3,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoiseNode {
+    pub frequency: f32,
+    pub octaves: u32,
+    pub seed: u32,
+}
+
+impl Default for NoiseNode {
+    fn default() -> Self {
+        Self {
+            frequency: 0.05,
+            octaves: 3,
+            seed: 0,
+        }
+    }
+}
+
+impl NoiseNode {
+    fn seed_offset(&self) -> Vec3 {
+        self.seed as f32 * Vec3::new(1.731, 3.117, 0.737)
+    }
+
+    fn position(&self, p: Vec3) -> Vec3 {
+        p * self.frequency + self.seed_offset()
+    }
+}
+
+impl DensityGraph {
+    pub fn sample(&self, p: Vec3) -> f32 {
+        self.root.sample(p)
+    }
+
+    /// Samples a whole chunk in the same layout the compute shader writes
+    pub fn sample_field(&self, chunk: IVec3, scale: f32) -> Vec<f32> {
+        let chunk_size = CHUNK_SIZE as f32;
+        let mut field = Vec::with_capacity(FIELD_LEN);
+        for z in 0..FIELD_SIZE {
+            for y in 0..FIELD_SIZE {
+                for x in 0..FIELD_SIZE {
+                    let local = UVec3::new(x, y, z).as_vec3();
+                    let world = (chunk.as_vec3() * chunk_size + local) * scale;
+                    field.push(self.root.sample(world));
+                }
+            }
+        }
+        field
+    }
+
+    /// The full compute shader for this graph
+    pub fn to_wgsl(&self) -> String {
+        let mut builder = WgslBuilder::default();
+        let result = self.root.emit("p", &mut builder);
+        format!(
+            "{}{SHADER_LIBRARY}\nfn density(p: vec3<f32>) -> f32 {{\n{}    return {result};\n}}\n",
+            shared_constants(),
+            builder.body
+        )
+    }
+}
+
+impl DensityNode {
+    pub fn sample(&self, p: Vec3) -> f32 {
+        match self {
+            DensityNode::Constant(value) => *value,
+            DensityNode::Value(noise) => fbm(value_noise, noise.position(p), noise.octaves),
+            DensityNode::Perlin(noise) => fbm(perlin, noise.position(p), noise.octaves),
+            DensityNode::Simplex(noise) => fbm(simplex, noise.position(p), noise.octaves),
+            DensityNode::Worley(noise) => fbm(worley, noise.position(p), noise.octaves),
+            DensityNode::Ridged(noise) => ridged(noise.position(p), noise.octaves),
+            DensityNode::Add(a, b) => a.sample(p) + b.sample(p),
+            DensityNode::Mul(a, b) => a.sample(p) * b.sample(p),
+            DensityNode::Min(a, b) => a.sample(p).min(b.sample(p)),
+            DensityNode::Max(a, b) => a.sample(p).max(b.sample(p)),
+            DensityNode::Negate(a) => -a.sample(p),
+            DensityNode::SmoothMin { a, b, k } => smooth_min(a.sample(p), b.sample(p), *k),
+            DensityNode::SmoothMax { a, b, k } => -smooth_min(-a.sample(p), -b.sample(p), *k),
+            DensityNode::DomainWarp {
+                source,
+                warp,
+                strength,
+            } => {
+                let offset = Vec3::new(
+                    warp.sample(p),
+                    warp.sample(p + WARP_OFFSET_Y),
+                    warp.sample(p + WARP_OFFSET_Z),
+                );
+                source.sample(p + offset * *strength)
+            }
+            DensityNode::HeightGradient { height, falloff } => (height - p.y) * falloff,
+            DensityNode::Sphere { center, radius } => radius - p.distance(*center),
+            DensityNode::Cuboid {
+                center,
+                half_extents,
+            } => -sd_box(p - *center, *half_extents),
+        }
+    }
+
+    /// Writes this node into the builder and returns the name of the variable holding it
+    fn emit(&self, p: &str, builder: &mut WgslBuilder) -> String {
+        let expr = match self {
+            DensityNode::Constant(value) => lit(*value),
+            DensityNode::Value(noise) => noise_call("fbm_value", noise, p, builder),
+            DensityNode::Perlin(noise) => noise_call("fbm_perlin", noise, p, builder),
+            DensityNode::Simplex(noise) => noise_call("fbm_simplex", noise, p, builder),
+            DensityNode::Worley(noise) => noise_call("fbm_worley", noise, p, builder),
+            DensityNode::Ridged(noise) => noise_call("ridged", noise, p, builder),
+            DensityNode::Add(a, b) => {
+                format!("{} + {}", a.emit(p, builder), b.emit(p, builder))
+            }
+            DensityNode::Mul(a, b) => {
+                format!("{} * {}", a.emit(p, builder), b.emit(p, builder))
+            }
+            DensityNode::Min(a, b) => {
+                format!("min({}, {})", a.emit(p, builder), b.emit(p, builder))
+            }
+            DensityNode::Max(a, b) => {
+                format!("max({}, {})", a.emit(p, builder), b.emit(p, builder))
+            }
+            DensityNode::Negate(a) => format!("-{}", a.emit(p, builder)),
+            DensityNode::SmoothMin { a, b, k } => format!(
+                "smooth_min({}, {}, {})",
+                a.emit(p, builder),
+                b.emit(p, builder),
+                lit(*k)
+            ),
+            DensityNode::SmoothMax { a, b, k } => format!(
+                "-smooth_min(-{}, -{}, {})",
+                a.emit(p, builder),
+                b.emit(p, builder),
+                lit(*k)
+            ),
+            DensityNode::DomainWarp {
+                source,
+                warp,
+                strength,
+            } => {
+                let x = warp.emit(p, builder);
+                let y_pos = builder.push(format!("{p} + {}", vec_lit(WARP_OFFSET_Y)));
+                let y = warp.emit(&y_pos, builder);
+                let z_pos = builder.push(format!("{p} + {}", vec_lit(WARP_OFFSET_Z)));
+                let z = warp.emit(&z_pos, builder);
+                let warped = builder.push(format!(
+                    "{p} + vec3<f32>({x}, {y}, {z}) * {}",
+                    lit(*strength)
+                ));
+                return source.emit(&warped, builder);
+            }
+            DensityNode::HeightGradient { height, falloff } => {
+                format!("({} - {p}.y) * {}", lit(*height), lit(*falloff))
+            }
+            DensityNode::Sphere { center, radius } => {
+                format!("{} - distance({p}, {})", lit(*radius), vec_lit(*center))
+            }
+            DensityNode::Cuboid {
+                center,
+                half_extents,
+            } => format!(
+                "-sd_box({p} - {}, {})",
+                vec_lit(*center),
+                vec_lit(*half_extents)
+            ),
+        };
+        builder.push(expr)
+    }
+}
+
+#[derive(Default)]
+struct WgslBuilder {
+    body: String,
+    next: usize,
+}
+
+impl WgslBuilder {
+    fn push(&mut self, expr: String) -> String {
+        let name = format!("v{}", self.next);
+        self.next += 1;
+        let _ = writeln!(self.body, "    let {name} = {expr};");
+        name
+    }
+}
+
+fn noise_call(function: &str, noise: &NoiseNode, p: &str, builder: &mut WgslBuilder) -> String {
+    let position = builder.push(format!(
+        "{p} * {} + {}",
+        lit(noise.frequency),
+        vec_lit(noise.seed_offset())
+    ));
+    format!("{function}({position}, {}u)", noise.octaves)
+}
+
+fn lit(value: f32) -> String {
+    // Debug always keeps the decimal point, which WGSL needs to infer f32
+    if value < 0.0 {
+        format!("({value:?})")
+    } else {
+        format!("{value:?}")
+    }
+}
+
+fn vec_lit(value: Vec3) -> String {
+    format!(
+        "vec3<f32>({}, {}, {})",
+        lit(value.x),
+        lit(value.y),
+        lit(value.z)
+    )
+}
+
+/// Everything the CPU side decides and the shader library needs to agree on
+fn shared_constants() -> String {
+    let gradients: Vec<String> = PERLIN_GRADIENTS.iter().map(|g| vec_lit(*g)).collect();
+    format!(
+        "const CHUNK_SIZE: f32 = {};\nconst FIELD_SIZE: u32 = {FIELD_SIZE}u;\nconst PERLIN_GRADIENTS = array<vec3<f32>, {}>(\n    {},\n);\n\n",
+        lit(CHUNK_SIZE as f32),
+        PERLIN_GRADIENTS.len(),
+        gradients.join(",\n    ")
+    )
+}
+
+fn compile_density_graph<L: Send + Sync + 'static>(
+    mut events: MessageReader<AssetEvent<DensityGraph>>,
+    layer: Res<DensityGraphLayer<L>>,
+    graphs: Res<Assets<DensityGraph>>,
+    mut shaders: ResMut<Assets<Shader>>,
+) {
+    for event in events.read() {
+        if !event.is_added(&layer.graph) && !event.is_modified(&layer.graph) {
+            continue;
+        }
+        let Some(graph) = graphs.get(&layer.graph) else {
+            continue;
+        };
+        // Never fails for uuid handles
+        let _ = shaders.insert(
+            &DensityGraphLayer::<L>::shader(),
+            Shader::from_wgsl(graph.to_wgsl(), "weave/terrain/density_graph.wgsl"),
+        );
+    }
+}
+
+/// CPU requests wait here until the graph is loaded
+#[derive(Resource)]
+struct CpuGraphQueue<L>(Vec<IVec3>, PhantomData<L>);
+
+impl<L> Default for CpuGraphQueue<L> {
+    fn default() -> Self {
+        Self(Vec::new(), PhantomData)
+    }
+}
+
+fn queue_cpu_chunk<L: Send + Sync + 'static>(
+    trigger: On<RequestNoise<DensityGraphLayer<L>>>,
+    mut queue: ResMut<CpuGraphQueue<L>>,
+) {
+    queue.0.push(trigger.event().position);
+}
+
+fn sample_cpu_chunks<L: Send + Sync + 'static>(
+    mut commands: Commands,
+    mut queue: ResMut<CpuGraphQueue<L>>,
+    mut layers: ResMut<DensityLayers>,
+    layer: Res<DensityGraphLayer<L>>,
+    graphs: Res<Assets<DensityGraph>>,
+) {
+    let Some(graph) = graphs.get(&layer.graph) else {
+        return;
+    };
+    for position in queue.0.drain(..) {
+        let data = graph.sample_field(position, layer.scale);
+        complete_noise::<DensityGraphLayer<L>>(&mut commands, &mut layers, position, data);
+    }
+}
+
+#[derive(Default)]
+pub struct DensityGraphLoader;
+
+impl AssetLoader for DensityGraphLoader {
+    type Asset = DensityGraph;
+    type Settings = ();
+    type Error = BevyError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["density.ron"]
+    }
+}
+
+// Everything below mirrors density_graph.wgsl
+
+fn wgsl_fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+fn hash(v: Vec3) -> f32 {
+    let p = Vec3::new(
+        v.dot(Vec3::new(127.1, 311.7, 74.7)),
+        v.dot(Vec3::new(269.5, 183.3, 246.1)),
+        v.dot(Vec3::new(113.5, 271.9, 124.6)),
+    );
+    wgsl_fract(p.x.sin() * 43758.547 + p.y.sin() * 12345.679 + p.z.sin() * 98765.43)
+}
+
+fn hash3(v: Vec3) -> Vec3 {
+    Vec3::new(
+        hash(v),
+        hash(v + Vec3::new(19.19, 7.13, 3.71)),
+        hash(v + Vec3::new(5.3, 41.7, 13.1)),
+    )
+}
+
+fn fade(t: Vec3) -> Vec3 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Smoother than [`fade`], perlin needs the second derivative to be zero at the grid points too
+fn quintic(t: Vec3) -> Vec3 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+/// The cube's edge directions, every grid point picks one of these as its gradient
+const PERLIN_GRADIENTS: [Vec3; 12] = [
+    Vec3::new(1.0, 1.0, 0.0),
+    Vec3::new(-1.0, 1.0, 0.0),
+    Vec3::new(1.0, -1.0, 0.0),
+    Vec3::new(-1.0, -1.0, 0.0),
+    Vec3::new(1.0, 0.0, 1.0),
+    Vec3::new(-1.0, 0.0, 1.0),
+    Vec3::new(1.0, 0.0, -1.0),
+    Vec3::new(-1.0, 0.0, -1.0),
+    Vec3::new(0.0, 1.0, 1.0),
+    Vec3::new(0.0, -1.0, 1.0),
+    Vec3::new(0.0, 1.0, -1.0),
+    Vec3::new(0.0, -1.0, -1.0),
+];
+
+fn perlin_gradient(cell: Vec3) -> Vec3 {
+    let index = ((hash(cell) * 12.0) as usize).min(11);
+    PERLIN_GRADIENTS[index]
+}
+
+fn value_noise(p: Vec3) -> f32 {
+    let pi = p.floor();
+    let w = fade(p - pi);
+    let corner = |x: f32, y: f32, z: f32| hash(pi + Vec3::new(x, y, z));
+
+    let c00 = corner(0.0, 0.0, 0.0).lerp(corner(1.0, 0.0, 0.0), w.x);
+    let c10 = corner(0.0, 1.0, 0.0).lerp(corner(1.0, 1.0, 0.0), w.x);
+    let c01 = corner(0.0, 0.0, 1.0).lerp(corner(1.0, 0.0, 1.0), w.x);
+    let c11 = corner(0.0, 1.0, 1.0).lerp(corner(1.0, 1.0, 1.0), w.x);
+
+    c00.lerp(c10, w.y).lerp(c01.lerp(c11, w.y), w.z) * 2.0 - 1.0
+}
+
+fn perlin(p: Vec3) -> f32 {
+    let pi = p.floor();
+    let f = p - pi;
+    let w = quintic(f);
+    let corner = |x: f32, y: f32, z: f32| {
+        let offset = Vec3::new(x, y, z);
+        perlin_gradient(pi + offset).dot(f - offset)
+    };
+
+    let c00 = corner(0.0, 0.0, 0.0).lerp(corner(1.0, 0.0, 0.0), w.x);
+    let c10 = corner(0.0, 1.0, 0.0).lerp(corner(1.0, 1.0, 0.0), w.x);
+    let c01 = corner(0.0, 0.0, 1.0).lerp(corner(1.0, 0.0, 1.0), w.x);
+    let c11 = corner(0.0, 1.0, 1.0).lerp(corner(1.0, 1.0, 1.0), w.x);
+
+    // Edge gradients can just poke past one in the middle of a cell
+    c00.lerp(c10, w.y)
+        .lerp(c01.lerp(c11, w.y), w.z)
+        .clamp(-1.0, 1.0)
+}
+
+fn simplex_corner(x: Vec3, cell: Vec3) -> f32 {
+    let w = (0.6 - x.dot(x)).max(0.0);
+    let w2 = w * w;
+    w2 * w2 * x.dot(hash3(cell) - 0.5)
+}
+
+fn simplex(p: Vec3) -> f32 {
+    let s = (p + p.dot(Vec3::splat(1.0 / 3.0))).floor();
+    let x0 = p - s + s.dot(Vec3::splat(1.0 / 6.0));
+
+    let step = |v: f32| if v >= 0.0 { 1.0 } else { 0.0 };
+    let d = x0 - x0.yzx();
+    let e = Vec3::new(step(d.x), step(d.y), step(d.z));
+    let i1 = e * (1.0 - e.zxy());
+    let i2 = 1.0 - e.zxy() * (1.0 - e);
+
+    let x1 = x0 - i1 + 1.0 / 6.0;
+    let x2 = x0 - i2 + 1.0 / 3.0;
+    let x3 = x0 - 0.5;
+
+    let n = simplex_corner(x0, s)
+        + simplex_corner(x1, s + i1)
+        + simplex_corner(x2, s + i2)
+        + simplex_corner(x3, s + 1.0);
+    n * 52.0
+}
+
+fn worley(p: Vec3) -> f32 {
+    let cell = p.floor();
+    let local = p - cell;
+    let mut closest: f32 = 8.0;
+    for z in -1..=1 {
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let offset = IVec3::new(x, y, z).as_vec3();
+                let point = offset + hash3(cell + offset);
+                closest = closest.min(point.distance(local));
+            }
+        }
+    }
+    closest.min(1.0) * 2.0 - 1.0
+}
+
+fn fbm(noise: fn(Vec3) -> f32, p: Vec3, octaves: u32) -> f32 {
+    let mut value = 0.0;
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+    let mut q = p;
+    for _ in 0..octaves {
+        value += amplitude * noise(q);
+        total += amplitude;
+        amplitude *= 0.5;
+        q *= 2.0;
+    }
+    value / total
+}
+
+fn ridged(p: Vec3, octaves: u32) -> f32 {
+    let ridge = |q: Vec3| {
+        let n = 1.0 - simplex(q).abs();
+        n * n
+    };
+    fbm(ridge, p, octaves) * 2.0 - 1.0
+}
+
+pub(crate) fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b.lerp(a, h) - k * h * (1.0 - h)
+}
+
+fn sd_box(p: Vec3, half_extents: Vec3) -> f32 {
+    let q = p.abs() - half_extents;
+    q.max(Vec3::ZERO).length() + q.x.max(q.y.max(q.z)).min(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise() -> NoiseNode {
+        NoiseNode {
+            frequency: 0.1,
+            octaves: 2,
+            seed: 7,
+        }
+    }
+
+    fn boxed(node: DensityNode) -> Box<DensityNode> {
+        Box::new(node)
+    }
+
+    /// One of every node kind
+    fn every_node() -> Vec<DensityNode> {
+        let sphere = || DensityNode::Sphere {
+            center: Vec3::ZERO,
+            radius: 4.0,
+        };
+        vec![
+            DensityNode::Constant(-1.5),
+            DensityNode::Value(noise()),
+            DensityNode::Perlin(noise()),
+            DensityNode::Simplex(noise()),
+            DensityNode::Worley(noise()),
+            DensityNode::Ridged(noise()),
+            DensityNode::Add(boxed(sphere()), boxed(DensityNode::Constant(1.0))),
+            DensityNode::Mul(boxed(sphere()), boxed(DensityNode::Constant(2.0))),
+            DensityNode::Min(boxed(sphere()), boxed(DensityNode::Constant(0.0))),
+            DensityNode::Max(boxed(sphere()), boxed(DensityNode::Constant(0.0))),
+            DensityNode::Negate(boxed(sphere())),
+            DensityNode::SmoothMin {
+                a: boxed(sphere()),
+                b: boxed(DensityNode::Constant(0.0)),
+                k: 2.0,
+            },
+            DensityNode::SmoothMax {
+                a: boxed(sphere()),
+                b: boxed(DensityNode::Constant(0.0)),
+                k: 2.0,
+            },
+            DensityNode::DomainWarp {
+                source: boxed(sphere()),
+                warp: boxed(DensityNode::Simplex(noise())),
+                strength: 3.0,
+            },
+            DensityNode::HeightGradient {
+                height: 2.0,
+                falloff: 0.5,
+            },
+            sphere(),
+            DensityNode::Cuboid {
+                center: Vec3::ONE,
+                half_extents: Vec3::new(1.0, 2.0, 3.0),
+            },
+        ]
+    }
+
+    #[test]
+    fn shapes_are_solid_inside() {
+        let sphere = DensityNode::Sphere {
+            center: Vec3::new(1.0, 2.0, 3.0),
+            radius: 4.0,
+        };
+        assert_eq!(sphere.sample(Vec3::new(1.0, 2.0, 3.0)), 4.0);
+        assert_eq!(sphere.sample(Vec3::new(1.0, 8.0, 3.0)), -2.0);
+
+        let cuboid = DensityNode::Cuboid {
+            center: Vec3::ZERO,
+            half_extents: Vec3::splat(2.0),
+        };
+        assert_eq!(cuboid.sample(Vec3::ZERO), 2.0);
+        assert_eq!(cuboid.sample(Vec3::new(3.0, 0.0, 0.0)), -1.0);
+
+        let ground = DensityNode::HeightGradient {
+            height: 2.0,
+            falloff: 0.5,
+        };
+        assert_eq!(ground.sample(Vec3::ZERO), 1.0);
+        assert_eq!(ground.sample(Vec3::new(5.0, 6.0, -5.0)), -2.0);
+    }
+
+    #[test]
+    fn math_nodes_combine_their_inputs() {
+        let constant = |value| boxed(DensityNode::Constant(value));
+        let p = Vec3::ZERO;
+        assert_eq!(
+            DensityNode::Add(constant(1.0), constant(2.0)).sample(p),
+            3.0
+        );
+        assert_eq!(
+            DensityNode::Mul(constant(3.0), constant(2.0)).sample(p),
+            6.0
+        );
+        assert_eq!(
+            DensityNode::Min(constant(3.0), constant(2.0)).sample(p),
+            2.0
+        );
+        assert_eq!(
+            DensityNode::Max(constant(3.0), constant(2.0)).sample(p),
+            3.0
+        );
+        assert_eq!(DensityNode::Negate(constant(3.0)).sample(p), -3.0);
+
+        // Far apart the seam doesn't matter, right on it both get pulled in
+        let smooth_min = |a, b| {
+            DensityNode::SmoothMin {
+                a: constant(a),
+                b: constant(b),
+                k: 1.0,
+            }
+            .sample(p)
+        };
+        assert_eq!(smooth_min(-5.0, 5.0), -5.0);
+        assert!(smooth_min(1.0, 1.0) < 1.0);
+        let smooth_max = DensityNode::SmoothMax {
+            a: constant(1.0),
+            b: constant(1.0),
+            k: 1.0,
+        };
+        assert!(smooth_max.sample(p) > 1.0);
+    }
+
+    #[test]
+    fn noise_stays_in_range_and_repeats() {
+        for node in [
+            DensityNode::Value(noise()),
+            DensityNode::Perlin(noise()),
+            DensityNode::Simplex(noise()),
+            DensityNode::Worley(noise()),
+            DensityNode::Ridged(noise()),
+        ] {
+            for i in 0..200 {
+                let p = Vec3::new(i as f32 * 1.37, i as f32 * -0.71, i as f32 * 2.13);
+                let density = node.sample(p);
+                assert!((-1.0..=1.0).contains(&density), "{node:?} gave {density}");
+                assert_eq!(density, node.sample(p));
+            }
+        }
+    }
+
+    #[test]
+    fn perlin_is_gradient_noise() {
+        // Zero right on the grid, that's what tells it apart from value noise
+        for i in -5..5 {
+            let corner = IVec3::new(i, i * 3, -i * 2).as_vec3();
+            assert_eq!(perlin(corner), 0.0);
+        }
+        // And it really does go somewhere in between
+        let samples: Vec<f32> = (0..100)
+            .map(|i| perlin(Vec3::new(0.5, 0.37, 0.71) + i as f32 * Vec3::X))
+            .collect();
+        assert!(samples.iter().any(|n| *n > 0.1));
+        assert!(samples.iter().any(|n| *n < -0.1));
+        // Smooth, nearby points land nearby
+        let p = Vec3::new(3.21, -1.7, 8.9);
+        assert!((perlin(p) - perlin(p + Vec3::splat(0.001))).abs() < 0.01);
+        // Right across a cell boundary too
+        let edge = Vec3::new(4.0, 2.5, 1.5);
+        assert!((perlin(edge - Vec3::X * 1e-4) - perlin(edge + Vec3::X * 1e-4)).abs() < 0.01);
+    }
+
+    #[test]
+    fn shader_constants_come_from_the_cpu_side() {
+        let source = DensityGraph {
+            root: DensityNode::Perlin(noise()),
+        }
+        .to_wgsl();
+        assert!(source.contains(&format!(
+            "const CHUNK_SIZE: f32 = {};",
+            lit(CHUNK_SIZE as f32)
+        )));
+        assert!(source.contains(&format!("const FIELD_SIZE: u32 = {FIELD_SIZE}u;")));
+        for gradient in PERLIN_GRADIENTS {
+            assert!(source.contains(&vec_lit(gradient)));
+        }
+        // Nothing left hard coded in the library
+        assert!(!SHADER_LIBRARY.contains("16.0"));
+        assert!(!SHADER_LIBRARY.contains("const FIELD_SIZE"));
+    }
+
+    struct Caves;
+
+    #[test]
+    fn tagged_graphs_are_separate_layers() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default(), DensityLayersPlugin));
+        app.add_plugins(
+            DensityGraphPlugin::from_graph(DensityGraph {
+                root: DensityNode::Constant(2.0),
+            })
+            .on_cpu(),
+        );
+        app.add_plugins(
+            DensityGraphPlugin::from_graph(DensityGraph {
+                root: DensityNode::Constant(0.5),
+            })
+            .tagged::<Caves>()
+            .with_blend(LayerBlend::Subtract)
+            .on_cpu(),
+        );
+        app.init_resource::<Combined>();
+        app.add_observer(
+            |trigger: On<DensityComplete>, mut combined: ResMut<Combined>| {
+                combined.0 = Some(trigger.event().data.clone());
+            },
+        );
+        assert_eq!(app.world().resource::<DensityLayers>().len(), 2);
+        assert_ne!(
+            DensityGraphLayer::<()>::shader(),
+            DensityGraphLayer::<Caves>::shader()
+        );
+
+        app.world_mut().trigger(RequestDensity::new_3d(IVec3::ZERO));
+        app.update();
+        let combined = app.world().resource::<Combined>().0.clone().unwrap();
+        assert!(combined.iter().all(|density| *density == 1.5));
+    }
+
+    #[derive(Resource, Default)]
+    struct Combined(Option<Vec<f32>>);
+
+    #[test]
+    fn seeds_change_the_noise() {
+        let p = Vec3::new(3.3, 1.2, -4.5);
+        let seeded = |seed| DensityNode::Simplex(NoiseNode { seed, ..noise() }).sample(p);
+        assert_ne!(seeded(0), seeded(1));
+    }
+
+    #[test]
+    fn field_matches_single_samples() {
+        let graph = DensityGraph {
+            root: DensityNode::Simplex(noise()),
+        };
+        let chunk = IVec3::new(1, -2, 3);
+        let field = graph.sample_field(chunk, 0.5);
+        assert_eq!(field.len(), FIELD_LEN);
+
+        // The last sample in the first row
+        let local = Vec3::new((FIELD_SIZE - 1) as f32, 0.0, 0.0);
+        let world = (chunk.as_vec3() * (FIELD_SIZE - 1) as f32 + local) * 0.5;
+        assert_eq!(field[FIELD_SIZE as usize - 1], graph.sample(world));
+    }
+
+    #[test]
+    fn every_node_compiles_to_valid_wgsl() {
+        for root in every_node() {
+            let graph = DensityGraph { root };
+            let source = graph.to_wgsl();
+            if let Err(error) = naga::front::wgsl::parse_str(&source) {
+                panic!(
+                    "{:?} didn't parse: {}",
+                    graph.root,
+                    error.emit_to_string(&source)
+                );
+            }
+        }
+    }
+}
@@ -1,8 +1,15 @@
-use bevy::prelude::*;
+use crate::area::UnloadChunk;
+use bevy::{
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+};
 use field_compute::*;
+use std::any::TypeId;
+use std::marker::PhantomData;
 
 //mod experimental;
 pub mod field_compute;
+pub mod graph;
 
 /// Handles the compute shader noise
 pub struct TerrainNoisePlugin<T: TerrainNoiseParams + Clone>(pub T);
@@ -10,16 +17,50 @@ pub struct TerrainNoisePlugin<T: TerrainNoiseParams + Clone>(pub T);
 impl<T: TerrainNoiseParams + Clone> Plugin for TerrainNoisePlugin<T> {
     fn build(&self, app: &mut App) {
         app.insert_resource(self.0.clone());
+        app.init_resource::<DensityLayers>();
+        app.world_mut()
+            .resource_mut::<DensityLayers>()
+            .register::<T>(self.0.blend());
         app.add_observer(queue_chunk::<T>);
         app.add_observer(on_complete::<T>);
     }
 }
 
+/// Combines every registered [`TerrainNoisePlugin`] into a single density field per chunk
+pub struct DensityLayersPlugin;
+
+impl Plugin for DensityLayersPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DensityLayers>();
+        app.add_observer(request_density);
+        app.add_observer(forget_unloaded);
+    }
+}
+
 pub trait TerrainNoiseParams: Resource {
     fn scale(&self) -> f32;
     fn frequency(&self) -> f32;
     fn amplitude(&self) -> f32;
     fn octaves(&self) -> u32;
+    /// How this layer is folded onto the layers registered before it
+    fn blend(&self) -> LayerBlend {
+        LayerBlend::Add
+    }
+    /// Compute shader to generate this layer with, `None` uses the built-in fbm
+    fn shader(&self) -> Option<Handle<Shader>> {
+        None
+    }
+}
+
+/// Tags a readback entity with the pipeline that requested it,
+/// so only that pipeline's `on_complete` picks it up
+#[derive(Component)]
+pub struct NoisePipeline<T: TerrainNoiseParams>(PhantomData<T>);
+
+impl<T: TerrainNoiseParams> Default for NoisePipeline<T> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
 }
 
 #[derive(Event)]
@@ -52,6 +93,167 @@ pub struct RequestComplete<T: TerrainNoiseParams> {
     _phantom: std::marker::PhantomData<T>,
 }
 
+/// Request a chunk from every registered noise layer
+#[derive(Event)]
+pub struct RequestDensity {
+    position: IVec3,
+}
+
+impl RequestDensity {
+    pub fn new(position: IVec2) -> Self {
+        Self {
+            position: position.xyx().with_z(0),
+        }
+    }
+
+    #[allow(unused)]
+    pub fn new_3d(position: IVec3) -> Self {
+        Self { position }
+    }
+}
+
+/// Every layer for this chunk has come back and been combined
+#[derive(Event)]
+pub struct DensityComplete {
+    pub position: IVec3,
+    pub data: Vec<f32>,
+}
+
+/// How a layer's field is combined with the field built up so far
+#[derive(Clone, Copy, Debug, Default, PartialEq, Reflect)]
+pub enum LayerBlend {
+    #[default]
+    Add,
+    /// Useful for carving caves out of the base terrain
+    Subtract,
+    Multiply,
+    Min,
+    Max,
+}
+
+impl LayerBlend {
+    pub fn combine(&self, base: f32, layer: f32) -> f32 {
+        match self {
+            LayerBlend::Add => base + layer,
+            LayerBlend::Subtract => base - layer,
+            LayerBlend::Multiply => base * layer,
+            LayerBlend::Min => base.min(layer),
+            LayerBlend::Max => base.max(layer),
+        }
+    }
+
+    fn apply(&self, base: &mut [f32], layer: &[f32]) {
+        for (base, layer) in base.iter_mut().zip(layer) {
+            *base = self.combine(*base, *layer);
+        }
+    }
+}
+
+struct DensityLayer {
+    pipeline: TypeId,
+    blend: LayerBlend,
+    request: fn(&mut Commands, IVec3),
+}
+
+/// Registered noise layers in the order they get combined, the first one is the base
+#[derive(Resource, Default)]
+pub struct DensityLayers {
+    layers: Vec<DensityLayer>,
+    pending: HashMap<IVec3, Vec<Option<Vec<f32>>>>,
+    /// Asked for again while their fields were still on the way, these go round once more when they land
+    stale: HashSet<IVec3>,
+}
+
+impl DensityLayers {
+    pub fn register<T: TerrainNoiseParams + Clone>(&mut self, blend: LayerBlend) {
+        let pipeline = TypeId::of::<T>();
+        if self.layers.iter().any(|layer| layer.pipeline == pipeline) {
+            return;
+        }
+        self.layers.push(DensityLayer {
+            pipeline,
+            blend,
+            request: |commands, position| {
+                commands.trigger(RequestNoise::<T>::new_3d(position));
+            },
+        });
+    }
+
+    /// Stops combining `T` in, for when something replaces it outright
+    pub fn remove<T: TerrainNoiseParams>(&mut self) {
+        let pipeline = TypeId::of::<T>();
+        self.layers.retain(|layer| layer.pipeline != pipeline);
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Whether a chunk is still waiting on some of its layers
+    pub fn is_pending(&self, position: IVec3) -> bool {
+        self.pending.contains_key(&position)
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Stores one layer's field, returns the combined field once every layer has arrived
+    fn insert<T: TerrainNoiseParams>(&mut self, position: IVec3, data: &[f32]) -> Option<Vec<f32>> {
+        let pipeline = TypeId::of::<T>();
+        let index = self
+            .layers
+            .iter()
+            .position(|layer| layer.pipeline == pipeline)?;
+        // Only chunks requested through RequestDensity are combined
+        let fields = self.pending.get_mut(&position)?;
+        fields[index] = Some(data.to_vec());
+        if fields.iter().any(Option::is_none) {
+            return None;
+        }
+
+        let mut fields = self.pending.remove(&position)?.into_iter().flatten();
+        let mut combined = fields.next()?;
+        for (layer, field) in self.layers.iter().skip(1).zip(fields) {
+            layer.blend.apply(&mut combined, &field);
+        }
+        Some(combined)
+    }
+}
+
+fn request_density(
+    trigger: On<RequestDensity>,
+    mut commands: Commands,
+    mut layers: ResMut<DensityLayers>,
+) {
+    let position = trigger.event().position;
+    if layers.is_empty() {
+        warn!("Requested density at {position} but no noise layers are registered");
+        return;
+    }
+    // Whatever's in flight was made before this request, so it can't be used as is
+    if layers.pending.contains_key(&position) {
+        layers.stale.insert(position);
+        return;
+    }
+
+    let count = layers.len();
+    layers.pending.insert(position, vec![None; count]);
+    for layer in &layers.layers {
+        (layer.request)(&mut commands, position);
+    }
+}
+
+fn forget_unloaded(trigger: On<UnloadChunk>, mut layers: ResMut<DensityLayers>) {
+    let position = trigger.event().position;
+    layers.pending.remove(&position);
+    layers.stale.remove(&position);
+}
+
 fn queue_chunk<C: TerrainNoiseParams>(
     trigger: On<RequestNoise<C>>,
     mut commands: Commands,
@@ -60,7 +262,6 @@ fn queue_chunk<C: TerrainNoiseParams>(
     params: Res<C>,
 ) {
     let coord = trigger.event().position;
-    let chunk_coord = IVec3::new(coord.x, coord.y, 0);
 
     let noise_params = NoiseParams {
         chunk_x: coord.x,
@@ -73,29 +274,217 @@ fn queue_chunk<C: TerrainNoiseParams>(
         _padding: 0,
     };
 
-    let mut buffer =
-        ShaderStorageBuffer::from(vec![0f32; (FIELD_SIZE * FIELD_SIZE * FIELD_SIZE) as usize]);
+    // One extra slot at the end for the shader's written flag
+    let mut buffer = ShaderStorageBuffer::from(vec![0f32; FIELD_LEN + 1]);
     buffer.buffer_description.usage |= BufferUsages::COPY_SRC;
     let buffer_handle = buffers.add(buffer);
 
-    let entity = commands.spawn((Readback::buffer(buffer_handle),)).id();
+    let entity = commands
+        .spawn((
+            Readback::buffer(buffer_handle.clone()),
+            NoisePipeline::<C>::default(),
+        ))
+        .id();
 
-    requests.0.insert(entity, (chunk_coord, noise_params));
+    requests.0.insert(
+        entity,
+        NoiseRequest {
+            position: coord,
+            params: noise_params,
+            buffer: buffer_handle,
+            shader: params.shader(),
+        },
+    );
 }
 
-// Terrain Noise Params could collide here!!!
 fn on_complete<C: TerrainNoiseParams>(
     trigger: On<ReadbackComplete>,
     mut commands: Commands,
     mut requests: ResMut<NoiseRequests>,
+    mut layers: ResMut<DensityLayers>,
+    pipeline: Query<(), With<NoisePipeline<C>>>,
 ) {
-    if let Some((position, _params)) = requests.0.remove(&trigger.entity) {
-        let data: Vec<f32> = trigger.to_shader_type();
+    // Every pipeline observes every readback, skip the ones another pipeline asked for
+    if !pipeline.contains(trigger.entity) {
+        return;
+    }
+    let mut data: Vec<f32> = trigger.to_shader_type();
+    // Readbacks run every frame, so one can land before the compute pass was dispatched
+    if data.get(FIELD_LEN) != Some(&1.0) {
+        return;
+    }
+    data.truncate(FIELD_LEN);
+
+    if let Some(request) = requests.0.remove(&trigger.entity) {
         commands.entity(trigger.entity).despawn();
-        commands.trigger(RequestComplete::<C> {
-            position,
-            data,
-            _phantom: std::marker::PhantomData,
+        complete_noise::<C>(&mut commands, &mut layers, request.position, data);
+    }
+}
+
+/// Hands a finished field to the layer combiner and to anyone listening for this pipeline
+fn complete_noise<C: TerrainNoiseParams>(
+    commands: &mut Commands,
+    layers: &mut DensityLayers,
+    position: IVec3,
+    data: Vec<f32>,
+) {
+    if let Some(combined) = layers.insert::<C>(position, &data) {
+        if layers.stale.remove(&position) {
+            commands.trigger(RequestDensity::new_3d(position));
+        } else {
+            commands.trigger(DensityComplete {
+                position,
+                data: combined,
+            });
+        }
+    }
+    commands.trigger(RequestComplete::<C> {
+        position,
+        data,
+        _phantom: std::marker::PhantomData,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! test_layer {
+        ($name:ident) => {
+            #[derive(Resource, Clone)]
+            struct $name;
+
+            impl TerrainNoiseParams for $name {
+                fn scale(&self) -> f32 {
+                    1.0
+                }
+                fn frequency(&self) -> f32 {
+                    1.0
+                }
+                fn amplitude(&self) -> f32 {
+                    1.0
+                }
+                fn octaves(&self) -> u32 {
+                    1
+                }
+            }
+        };
+    }
+
+    test_layer!(Ground);
+    test_layer!(Caves);
+    test_layer!(Floor);
+
+    #[derive(Resource, Default)]
+    struct Seen {
+        requests: u32,
+        complete: Vec<Vec<f32>>,
+    }
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, DensityLayersPlugin));
+        app.init_resource::<Seen>();
+        app.world_mut()
+            .resource_mut::<DensityLayers>()
+            .register::<Ground>(LayerBlend::Add);
+        // Stands in for the compute pipeline, the fields get handed back by hand
+        app.add_observer(|_: On<RequestNoise<Ground>>, mut seen: ResMut<Seen>| {
+            seen.requests += 1;
+        });
+        app.add_observer(|trigger: On<DensityComplete>, mut seen: ResMut<Seen>| {
+            seen.complete.push(trigger.event().data.clone());
+        });
+        app
+    }
+
+    fn request(app: &mut App, position: IVec3) {
+        let world = app.world_mut();
+        world.trigger(RequestDensity::new_3d(position));
+        // The layer requests go out as commands
+        world.flush();
+    }
+
+    fn land(app: &mut App, position: IVec3, value: f32) {
+        let world = app.world_mut();
+        world.resource_scope(|world, mut layers: Mut<DensityLayers>| {
+            complete_noise::<Ground>(&mut world.commands(), &mut layers, position, vec![value]);
         });
+        world.flush();
+    }
+
+    #[test]
+    fn blends_combine() {
+        assert_eq!(LayerBlend::Add.combine(3.0, 2.0), 5.0);
+        assert_eq!(LayerBlend::Subtract.combine(3.0, 2.0), 1.0);
+        assert_eq!(LayerBlend::Multiply.combine(3.0, 2.0), 6.0);
+        assert_eq!(LayerBlend::Min.combine(3.0, 2.0), 2.0);
+        assert_eq!(LayerBlend::Max.combine(3.0, 2.0), 3.0);
+    }
+
+    #[test]
+    fn layers_combine_in_registration_order() {
+        let mut layers = DensityLayers::default();
+        layers.register::<Ground>(LayerBlend::Add);
+        layers.register::<Caves>(LayerBlend::Subtract);
+        layers.register::<Floor>(LayerBlend::Max);
+        // Registering again doesn't add a second copy
+        layers.register::<Caves>(LayerBlend::Add);
+        assert_eq!(layers.len(), 3);
+
+        let position = IVec3::new(1, 2, 3);
+        assert_eq!(layers.insert::<Ground>(position, &[4.0, 4.0]), None);
+        layers.pending.insert(position, vec![None; 3]);
+        // Arrival order doesn't matter, only the order they were registered in
+        assert_eq!(layers.insert::<Floor>(position, &[0.0, 3.0]), None);
+        assert_eq!(layers.insert::<Ground>(position, &[4.0, 4.0]), None);
+        assert!(layers.is_pending(position));
+        assert_eq!(
+            layers.insert::<Caves>(position, &[1.0, 2.0]),
+            Some(vec![3.0, 3.0])
+        );
+        assert!(!layers.is_pending(position));
+
+        layers.remove::<Caves>();
+        assert_eq!(layers.len(), 2);
+    }
+
+    #[test]
+    fn requesting_again_mid_flight_restarts() {
+        let mut app = app();
+        let position = IVec3::new(0, 1, 0);
+        request(&mut app, position);
+        request(&mut app, position);
+        assert_eq!(app.world().resource::<Seen>().requests, 1);
+
+        // The first field was made before the second request, so it's thrown away
+        land(&mut app, position, 1.0);
+        let seen = app.world().resource::<Seen>();
+        assert!(seen.complete.is_empty());
+        assert_eq!(seen.requests, 2);
+        assert!(app.world().resource::<DensityLayers>().is_pending(position));
+
+        land(&mut app, position, 2.0);
+        assert_eq!(app.world().resource::<Seen>().complete, [vec![2.0]]);
+        assert_eq!(app.world().resource::<DensityLayers>().pending_len(), 0);
+    }
+
+    #[test]
+    fn unloading_forgets_pending_chunks() {
+        let mut app = app();
+        let position = IVec3::new(2, 0, 0);
+        request(&mut app, position);
+        request(&mut app, position);
+        app.world_mut().trigger(UnloadChunk { position });
+        assert_eq!(app.world().resource::<DensityLayers>().pending_len(), 0);
+
+        // Late readbacks for it go nowhere, and loading it again starts fresh
+        land(&mut app, position, 1.0);
+        assert!(app.world().resource::<Seen>().complete.is_empty());
+        request(&mut app, position);
+        land(&mut app, position, 3.0);
+        let seen = app.world().resource::<Seen>();
+        assert_eq!(seen.requests, 2);
+        assert_eq!(seen.complete, [vec![3.0]]);
     }
 }
@@ -108,4 +108,9 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     // Store in flat buffer (x + y*SIZE + z*SIZE*SIZE)
     let index = global_id.x + global_id.y * FIELD_SIZE + global_id.z * FIELD_SIZE * FIELD_SIZE;
     noise_field[index] = density;
+
+    // Written flag, lets the readback tell a dispatched field from an untouched buffer
+    if (index == 0u) {
+        noise_field[FIELD_SIZE * FIELD_SIZE * FIELD_SIZE] = 1.0;
+    }
 }
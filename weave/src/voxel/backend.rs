@@ -0,0 +1,348 @@
+// Drives voxel_terrain from weave's area manager, voxel chunks are wide columns so
+// every area chunk inside one just keeps it alive
+use crate::{
+    SurfaceBackend,
+    area::{AreaManaged, CHUNK_SIZE, LoadChunk, Observer, UnloadChunk},
+    marching_cubes::NoiseParams,
+    terrain::{
+        LayerBlend,
+        graph::{DensityGraph, DensityGraphLayer},
+    },
+};
+use bevy::{platform::collections::HashMap, prelude::*};
+use voxel_terrain::prelude::*;
+
+/// How far above and below zero a graph gets searched for its surface
+const HEIGHT_RANGE: f32 = 64.0;
+/// Coarse step of that search before it gets refined
+const HEIGHT_STEP: f32 = 4.0;
+
+/// How many loaded area chunks sit inside each voxel column
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct VoxelColumns(HashMap<IVec2, u32>);
+
+/// The voxel column an area chunk falls in, voxel chunks are centered on their coordinate
+fn column(position: IVec3) -> Chunk {
+    let center = position.xz() * CHUNK_SIZE + CHUNK_SIZE / 2;
+    Chunk(
+        (center + voxel_terrain::prelude::CHUNK_SIZE / 2)
+            .div_euclid(IVec2::splat(voxel_terrain::prelude::CHUNK_SIZE)),
+    )
+}
+
+pub fn load_column(
+    trigger: On<LoadChunk>,
+    backend: Res<SurfaceBackend>,
+    mut columns: ResMut<VoxelColumns>,
+    mut manager: ResMut<ChunkManager>,
+) {
+    if *backend != SurfaceBackend::Voxel {
+        return;
+    }
+    let chunk = column(trigger.event().position);
+    let count = columns.entry(chunk.0).or_default();
+    *count += 1;
+    if *count == 1 {
+        manager.request_chunk(chunk);
+    }
+}
+
+// Not gated on the backend, a backend swap unloads everything that was counted
+pub fn unload_column(
+    trigger: On<UnloadChunk>,
+    mut columns: ResMut<VoxelColumns>,
+    mut manager: ResMut<ChunkManager>,
+) {
+    let chunk = column(trigger.event().position);
+    let Some(count) = columns.get_mut(&chunk.0) else {
+        return;
+    };
+    *count -= 1;
+    if *count == 0 {
+        columns.remove(&chunk.0);
+        manager.unload_chunk(chunk);
+    }
+}
+
+/// Spawns or removes the voxel terrain root to match the backend
+pub fn sync_voxel_root(
+    mut commands: Commands,
+    backend: Res<SurfaceBackend>,
+    mut manager: ResMut<ChunkManager>,
+    roots: Query<Entity, With<VoxelTerrain>>,
+) {
+    match (*backend, roots.is_empty()) {
+        (SurfaceBackend::Voxel, true) => {
+            commands.spawn(VoxelTerrain);
+        }
+        (SurfaceBackend::MarchingCubes, false) => {
+            for root in &roots {
+                commands.entity(root).despawn();
+            }
+            manager.clear();
+        }
+        _ => {}
+    }
+}
+
+/// Nearest chunks to the first observer get generated first
+pub fn focus_observer(
+    mut manager: ResMut<ChunkManager>,
+    observer: Option<Single<&GlobalTransform, (With<Observer>, With<AreaManaged>)>>,
+) {
+    if let Some(observer) = observer {
+        let position = (observer.translation() / CHUNK_SIZE as f32)
+            .floor()
+            .as_ivec3();
+        manager.set_focus(column(position).0);
+    }
+}
+
+/// Density graphs are the one noise source both backends can read, so the voxel heights come from
+/// the same ground and graph layer marching cubes combines instead of voxel_terrain's own noise
+pub fn sync_height_source(
+    mut commands: Commands,
+    ground: Res<NoiseParams>,
+    layer: Option<Res<DensityGraphLayer>>,
+    graphs: Option<Res<Assets<DensityGraph>>>,
+    mut graph_events: MessageReader<AssetEvent<DensityGraph>>,
+    imported: Option<Res<ImportedHeightmap>>,
+) {
+    // Assets get mutably borrowed every frame so they always look changed, only a real edit to
+    // our graph counts. Reads every event either way so none are left over for next frame
+    let mut graph_edited = false;
+    for event in graph_events.read() {
+        if let (AssetEvent::Added { id } | AssetEvent::Modified { id }, Some(layer)) =
+            (event, &layer)
+        {
+            graph_edited |= *id == layer.graph.id();
+        }
+    }
+    // An imported heightmap wins until something else replaces it
+    if imported.is_some() {
+        return;
+    }
+    let layer_changed = layer.as_ref().is_some_and(|layer| layer.is_changed());
+    if !ground.is_changed() && !graph_edited && !layer_changed {
+        return;
+    }
+
+    commands.insert_resource(height_source(&ground, layer.as_deref(), graphs.as_deref()));
+    commands.trigger(RegenerateTerrain);
+}
+
+/// The ground with the loaded density graph layered on top, same as marching cubes gets
+pub fn height_source(
+    ground: &NoiseParams,
+    layer: Option<&DensityGraphLayer>,
+    graphs: Option<&Assets<DensityGraph>>,
+) -> TerrainHeight {
+    let mut layers = vec![HeightLayer {
+        graph: ground.density_graph(),
+        scale: ground.scale,
+        blend: LayerBlend::Add,
+    }];
+    // Still loading, it shows up once its asset event does
+    if let Some((layer, graph)) = layer
+        .zip(graphs)
+        .and_then(|(layer, graphs)| Some((layer, graphs.get(&layer.graph)?)))
+    {
+        layers.push(HeightLayer {
+            graph: graph.clone(),
+            scale: layer.scale,
+            blend: layer.blend,
+        });
+    }
+    TerrainHeight::new(GraphHeight { layers })
+}
+
+pub struct HeightLayer {
+    pub graph: DensityGraph,
+    pub scale: f32,
+    pub blend: LayerBlend,
+}
+
+/// Heights from where some density graphs combined turn from solid to air, the first is the base
+/// and the rest fold onto it with their blends
+pub struct GraphHeight {
+    pub layers: Vec<HeightLayer>,
+}
+
+impl GraphHeight {
+    pub fn density(&self, p: Vec3) -> f32 {
+        let mut layers = self.layers.iter();
+        let Some(base) = layers.next() else {
+            return 0.0;
+        };
+        layers.fold(base.graph.sample(p * base.scale), |density, layer| {
+            layer
+                .blend
+                .combine(density, layer.graph.sample(p * layer.scale))
+        })
+    }
+}
+
+impl HeightSource for GraphHeight {
+    fn height(&self, position: Vec2) -> f32 {
+        let density = |y: f32| self.density(Vec3::new(position.x, y, position.y));
+
+        // Walk down until something solid shows up
+        let mut air = HEIGHT_RANGE;
+        let mut solid = air - HEIGHT_STEP;
+        while density(solid) <= 0.0 {
+            if solid <= -HEIGHT_RANGE {
+                return -HEIGHT_RANGE;
+            }
+            air = solid;
+            solid -= HEIGHT_STEP;
+        }
+
+        // Then narrow it down
+        for _ in 0..4 {
+            let middle = (air + solid) / 2.0;
+            if density(middle) > 0.0 {
+                solid = middle;
+            } else {
+                air = middle;
+            }
+        }
+        (air + solid) / 2.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::graph::{DensityNode, GraphBackend};
+
+    #[derive(Resource, Default)]
+    struct Regenerated(u32);
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()));
+        app.init_asset::<DensityGraph>();
+        app.init_resource::<NoiseParams>();
+        app.init_resource::<Regenerated>();
+        app.add_systems(Update, sync_height_source);
+        app.add_observer(
+            |_trigger: On<RegenerateTerrain>, mut regenerated: ResMut<Regenerated>| {
+                regenerated.0 += 1;
+            },
+        );
+        let graph = app
+            .world_mut()
+            .resource_mut::<Assets<DensityGraph>>()
+            .add(DensityGraph {
+                root: DensityNode::HeightGradient {
+                    height: 0.0,
+                    falloff: 1.0,
+                },
+            });
+        app.insert_resource(DensityGraphLayer::<()>::new(
+            graph,
+            1.0,
+            LayerBlend::Add,
+            GraphBackend::Cpu,
+        ));
+        app
+    }
+
+    fn regenerated(app: &App) -> u32 {
+        app.world().resource::<Regenerated>().0
+    }
+
+    /// The ground and the graph's asset event land a frame apart
+    fn settled_app() -> App {
+        let mut app = app();
+        app.update();
+        app.update();
+        app
+    }
+
+    #[test]
+    fn quiet_frame_does_not_regenerate() {
+        let mut app = settled_app();
+        let before = regenerated(&app);
+        assert!(before > 0);
+
+        app.update();
+        assert_eq!(regenerated(&app), before);
+    }
+
+    #[test]
+    fn editing_the_graph_regenerates() {
+        let mut app = settled_app();
+        let before = regenerated(&app);
+
+        let graph = app.world().resource::<DensityGraphLayer>().graph.clone();
+        app.world_mut()
+            .resource_mut::<Assets<DensityGraph>>()
+            .get_mut(&graph)
+            .unwrap()
+            .root = DensityNode::Constant(1.0);
+        app.update();
+        app.update();
+        assert_eq!(regenerated(&app), before + 1);
+    }
+
+    #[test]
+    fn other_graphs_are_ignored() {
+        let mut app = settled_app();
+        let before = regenerated(&app);
+
+        app.world_mut()
+            .resource_mut::<Assets<DensityGraph>>()
+            .add(DensityGraph {
+                root: DensityNode::Constant(1.0),
+            });
+        app.update();
+        app.update();
+        assert_eq!(regenerated(&app), before);
+    }
+
+    #[test]
+    fn voxels_sit_on_the_marching_cubes_ground() {
+        let ground = NoiseParams::default();
+        let graph = ground.density_graph();
+        let height = height_source(&ground, None, None);
+        for i in 0..20 {
+            let position = Vec2::new(i as f32 * 13.7, i as f32 * -7.3);
+            let surface = height.height(position);
+            // Solid just under where the voxels put the surface, air just over it
+            let at = |y: f32| graph.sample(Vec3::new(position.x, y, position.y));
+            assert!(at(surface - 0.5) > 0.0, "no ground under {position}");
+            assert!(at(surface + 0.5) < 0.0, "ground over {position}");
+        }
+    }
+
+    #[test]
+    fn graph_layers_fold_onto_the_ground() {
+        let flat = |height| DensityGraph {
+            root: DensityNode::HeightGradient {
+                height,
+                falloff: 1.0,
+            },
+        };
+        let layer = |blend| HeightLayer {
+            graph: DensityGraph {
+                root: DensityNode::Constant(4.0),
+            },
+            scale: 1.0,
+            blend,
+        };
+        let ground = || HeightLayer {
+            graph: flat(2.0),
+            scale: 1.0,
+            blend: LayerBlend::Add,
+        };
+        let raised = GraphHeight {
+            layers: vec![ground(), layer(LayerBlend::Add)],
+        };
+        assert!((raised.height(Vec2::ZERO) - 6.0).abs() < 0.5);
+        let lowered = GraphHeight {
+            layers: vec![ground(), layer(LayerBlend::Subtract)],
+        };
+        assert!((lowered.height(Vec2::ZERO) + 2.0).abs() < 0.5);
+    }
+}
@@ -1,49 +1,74 @@
+use crate::marching_cubes::{TerrainChunk, TerrainChunks};
 use avian3d::prelude::*;
-use bevy::prelude::*;
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task, block_on, futures_lite::future},
+};
 
 #[derive(Component)]
 #[allow(unused)]
 pub struct ChunkCollider {
-    pub coord: IVec2,
+    pub coord: IVec3,
 }
 
+/// Trimesh collider being built off the main thread for a chunk's mesh entity
+#[derive(Component)]
+pub struct ColliderTask(Task<Collider>);
+
 #[derive(Event)]
 pub struct ColliderReady {
-    pub coord: IVec2,
+    pub coord: IVec3,
     pub vertices: Vec<Vec3>,
     pub indices: Vec<u32>,
 }
 
-pub fn on_collider_ready(trigger: On<ColliderReady>, mut commands: Commands) {
-    let event = trigger.event();
+pub fn on_collider_ready(
+    mut trigger: On<ColliderReady>,
+    mut commands: Commands,
+    chunks: Res<TerrainChunks>,
+) {
+    let coord = trigger.event().coord;
+    // Chunk unloaded before its mesh got here
+    let Some(&entity) = chunks.get(&coord) else {
+        return;
+    };
 
+    let event = trigger.event_mut();
     if event.vertices.is_empty() || event.indices.is_empty() {
+        // Nothing left to collide with, drop whatever was there along with any pending build
+        commands
+            .entity(entity)
+            .remove::<(Collider, RigidBody, ChunkCollider, ColliderTask)>();
         return;
     }
 
-    let triangles: Vec<[u32; 3]> = event
-        .indices
-        .chunks_exact(3)
-        .map(|chunk| [chunk[0], chunk[1], chunk[2]])
-        .collect();
-
-    let collider = Collider::trimesh(event.vertices.clone(), triangles);
-    let chunk_pos = chunk_to_world(event.coord);
-
-    commands.spawn((
-        collider,
-        RigidBody::Static,
-        Transform::from_translation(chunk_pos),
-        GlobalTransform::default(),
-        ChunkCollider { coord: event.coord },
-    ));
+    let vertices = std::mem::take(&mut event.vertices);
+    let indices = std::mem::take(&mut event.indices);
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let triangles: Vec<[u32; 3]> = indices
+            .chunks_exact(3)
+            .map(|chunk| [chunk[0], chunk[1], chunk[2]])
+            .collect();
+        Collider::trimesh(vertices, triangles)
+    });
+
+    // Replacing the task drops the old one, so a stale mesh never wins
+    commands.entity(entity).insert(ColliderTask(task));
 }
 
-fn chunk_to_world(coord: IVec2) -> Vec3 {
-    const CHUNK_SIZE: f32 = 16.0;
-    Vec3::new(
-        coord.x as f32 * CHUNK_SIZE,
-        0.0,
-        coord.y as f32 * CHUNK_SIZE,
-    )
+pub fn poll_collider_tasks(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut ColliderTask, &TerrainChunk)>,
+) {
+    for (entity, mut task, chunk) in &mut tasks {
+        let Some(collider) = block_on(future::poll_once(&mut task.0)) else {
+            continue;
+        };
+
+        commands.entity(entity).remove::<ColliderTask>().insert((
+            collider,
+            RigidBody::Static,
+            ChunkCollider { coord: chunk.0 },
+        ));
+    }
 }
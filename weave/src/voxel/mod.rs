@@ -1,11 +1,27 @@
 use bevy::prelude::*;
 
-mod collider;
+pub mod backend;
+pub mod collider;
 
 pub struct VoxelPlugin;
 
 impl Plugin for VoxelPlugin {
     fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<voxel_terrain::VoxelTerrainPlugin>() {
+            app.add_plugins(voxel_terrain::VoxelTerrainPlugin);
+        }
+        app.init_resource::<backend::VoxelColumns>();
         app.add_observer(collider::on_collider_ready);
+        app.add_observer(backend::load_column);
+        app.add_observer(backend::unload_column);
+        app.add_systems(
+            Update,
+            (
+                collider::poll_collider_tasks,
+                backend::sync_voxel_root,
+                backend::focus_observer,
+                backend::sync_height_source,
+            ),
+        );
     }
 }